@@ -0,0 +1,481 @@
+// Экспорт в один файл-архив вместо папки: треки и файл плейлиста пишутся
+// потоком в любой Write, поэтому архив можно отдать и в stdout. Треки уже
+// сжаты, так что ZIP пишется без сжатия; tar.gz - для тех, кому нужен gzip.
+// Имена в архиве - те же, что при экспорте в папку (plan_dest_paths), пути в
+// плейлисте всегда относительные, абсолютные внутри архива не имеют смысла.
+// Из настроек переноса архиву важна только проверка сумм: архив пишется одним
+// потоком по порядку, а способа переноса, кроме копии, у него нет. С проверкой
+// в архив добавляется SHA256SUMS по исходным файлам, и распакованные треки
+// можно сверить через sha256sum -c
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::{AudioEntry, checksums, format_date};
+use crate::export::{ExportOptions, ExportReport, plan_dest_paths, sanitize_file_name};
+use crate::hooks::{HookContext, run_post_export_hook};
+use crate::playlist_formats::{PlaylistContext, PlaylistTrack};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 3] = [ArchiveFormat::Zip, ArchiveFormat::Tar, ArchiveFormat::TarGz];
+    pub fn extension (self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip   => "zip",
+            ArchiveFormat::Tar   => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+    pub fn key (self) -> &'static str {
+        self.extension()
+    }
+    pub fn from_key (key: &str) -> Option<ArchiveFormat> {
+        ArchiveFormat::ALL.iter().copied().find(|format| format.key() == key)
+    }
+}
+impl std::fmt::Display for ArchiveFormat {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ArchiveFormat::Zip   => "ZIP",
+            ArchiveFormat::Tar   => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        })
+    }
+}
+
+// Куда складываются файлы; ошибка записи означает испорченный архив, после
+// неё экспорт останавливается
+trait ArchiveSink {
+    fn add_file (&mut self, name: &str, file: &mut std::fs::File, metadata: &std::fs::Metadata) -> std::io::Result<()>;
+    fn add_bytes (&mut self, name: &str, bytes: &[u8]) -> std::io::Result<()>;
+    fn finish (self: Box<Self>) -> std::io::Result<()>;
+}
+
+fn unix_secs (time: std::io::Result<std::time::SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn now_unix_secs () -> u64 {
+    unix_secs(Ok(std::time::SystemTime::now()))
+}
+
+struct TarSink<W: Write> {
+    builder: tar::Builder<W>,
+}
+impl<W: Write> TarSink<W> {
+    fn header (size: u64, mtime: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_entry_type(tar::EntryType::Regular);
+        header
+    }
+}
+impl<W: Write> ArchiveSink for TarSink<W> {
+    fn add_file (&mut self, name: &str, file: &mut std::fs::File, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+        let mut header = TarSink::<W>::header(metadata.len(), unix_secs(metadata.modified()));
+        self.builder.append_data(&mut header, name, file)
+    }
+    fn add_bytes (&mut self, name: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let mut header = TarSink::<W>::header(bytes.len() as u64, now_unix_secs());
+        self.builder.append_data(&mut header, name, bytes)
+    }
+    fn finish (self: Box<Self>) -> std::io::Result<()> {
+        self.builder.into_inner()?.flush()
+    }
+}
+
+struct TarGzSink<W: Write> {
+    tar: TarSink<flate2::write::GzEncoder<W>>,
+}
+impl<W: Write> ArchiveSink for TarGzSink<W> {
+    fn add_file (&mut self, name: &str, file: &mut std::fs::File, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+        self.tar.add_file(name, file, metadata)
+    }
+    fn add_bytes (&mut self, name: &str, bytes: &[u8]) -> std::io::Result<()> {
+        self.tar.add_bytes(name, bytes)
+    }
+    fn finish (self: Box<Self>) -> std::io::Result<()> {
+        self.tar.builder.into_inner()?.finish()?.flush()
+    }
+}
+
+// ZIP без сжатия и без Seek: CRC и размеры становятся известны только после
+// данных, поэтому они пишутся в data descriptor (флаг 3), а в заголовке стоит
+// поле ZIP64 - так архив может быть больше 4 ГБ
+const ZIP_VERSION: u16 = 45;
+// старший байт - система, где создан архив: 3 - Unix, тогда внешние атрибуты
+// несут права файла, и распаковщики не гадают о кодировке имён по DOS
+const ZIP_VERSION_MADE_BY: u16 = (3 << 8) | ZIP_VERSION;
+const ZIP_EXTERNAL_ATTRIBUTES: u32 = 0o100644 << 16;
+const ZIP_FLAGS: u16 = 0x0008 | 0x0800; // data descriptor, имена в UTF-8
+const ZIP_U32_MAX: u64 = 0xFFFF_FFFF;
+
+struct ZipEntry {
+    name:     String,
+    crc:      u32,
+    size:     u64,
+    offset:   u64,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+struct ZipSink<W: Write> {
+    writer:   W,
+    position: u64,
+    entries:  Vec<ZipEntry>,
+}
+impl<W: Write> ZipSink<W> {
+    fn write_all (&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+    // Время в формате DOS, без часового пояса, как и format_date
+    fn dos_datetime (unix_secs: u64) -> (u16, u16) {
+        let date = format_date(unix_secs);
+        let mut parts = date.split('-').map(|part| part.parse::<u16>().unwrap_or(1));
+        let (year, month, day) = (parts.next().unwrap_or(1980), parts.next().unwrap_or(1), parts.next().unwrap_or(1));
+        let secs_of_day = unix_secs % 86400;
+        let dos_time = (((secs_of_day / 3600) << 11) | ((secs_of_day % 3600 / 60) << 5) | ((secs_of_day % 60) / 2)) as u16;
+        let dos_date = ((year.max(1980) - 1980) << 9) | (month << 5) | day;
+        (dos_time, dos_date)
+    }
+    fn add_entry (&mut self, name: &str, mtime: u64, data: &mut dyn std::io::Read) -> std::io::Result<()> {
+        let (dos_time, dos_date) = ZipSink::<W>::dos_datetime(mtime);
+        let offset = self.position;
+        let mut header = Vec::with_capacity(50 + name.len());
+        header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // без сжатия
+        header.extend_from_slice(&dos_time.to_le_bytes());
+        header.extend_from_slice(&dos_date.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // crc - в data descriptor
+        header.extend_from_slice(&(ZIP_U32_MAX as u32).to_le_bytes());
+        header.extend_from_slice(&(ZIP_U32_MAX as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&0x0001u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        self.write_all(&header)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0u64;
+        let mut buffer = vec![0u8; 1 << 16];
+        loop {
+            let read = match data.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if std::io::ErrorKind::Interrupted == err.kind() => continue,
+                Err(err) => return Err(err),
+            };
+            hasher.update(&buffer[..read]);
+            self.write_all(&buffer[..read])?;
+            size += read as u64;
+        }
+        let crc = hasher.finalize();
+
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+        descriptor.extend_from_slice(&crc.to_le_bytes());
+        descriptor.extend_from_slice(&size.to_le_bytes());
+        descriptor.extend_from_slice(&size.to_le_bytes());
+        self.write_all(&descriptor)?;
+        self.entries.push(ZipEntry {name: name.to_string(), crc, size, offset, dos_time, dos_date});
+        Ok(())
+    }
+}
+impl<W: Write> ArchiveSink for ZipSink<W> {
+    fn add_file (&mut self, name: &str, file: &mut std::fs::File, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+        self.add_entry(name, unix_secs(metadata.modified()), file)
+    }
+    fn add_bytes (&mut self, name: &str, mut bytes: &[u8]) -> std::io::Result<()> {
+        self.add_entry(name, now_unix_secs(), &mut bytes)
+    }
+    fn finish (mut self: Box<Self>) -> std::io::Result<()> {
+        let central_directory_offset = self.position;
+        let entries = std::mem::take(&mut self.entries);
+        for entry in &entries {
+            // поля, которые не помещаются в 32 бита, уходят в ZIP64 в этом порядке
+            let mut zip64 = Vec::new();
+            if ZIP_U32_MAX <= entry.size {
+                zip64.extend_from_slice(&entry.size.to_le_bytes());
+                zip64.extend_from_slice(&entry.size.to_le_bytes());
+            }
+            if ZIP_U32_MAX <= entry.offset {
+                zip64.extend_from_slice(&entry.offset.to_le_bytes());
+            }
+            let extra_len = if zip64.is_empty() {0} else {4 + zip64.len()};
+            let mut header = Vec::with_capacity(46 + entry.name.len() + extra_len);
+            header.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            header.extend_from_slice(&ZIP_VERSION_MADE_BY.to_le_bytes());
+            header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+            header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(&entry.dos_time.to_le_bytes());
+            header.extend_from_slice(&entry.dos_date.to_le_bytes());
+            header.extend_from_slice(&entry.crc.to_le_bytes());
+            header.extend_from_slice(&(entry.size.min(ZIP_U32_MAX) as u32).to_le_bytes());
+            header.extend_from_slice(&(entry.size.min(ZIP_U32_MAX) as u32).to_le_bytes());
+            header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(extra_len as u16).to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes()); // комментарий
+            header.extend_from_slice(&0u16.to_le_bytes()); // номер диска
+            header.extend_from_slice(&0u16.to_le_bytes()); // внутренние атрибуты
+            header.extend_from_slice(&ZIP_EXTERNAL_ATTRIBUTES.to_le_bytes()); // обычный файл rw-r--r--
+            header.extend_from_slice(&(entry.offset.min(ZIP_U32_MAX) as u32).to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+            if !zip64.is_empty() {
+                header.extend_from_slice(&0x0001u16.to_le_bytes());
+                header.extend_from_slice(&(zip64.len() as u16).to_le_bytes());
+                header.extend_from_slice(&zip64);
+            }
+            self.write_all(&header)?;
+        }
+        let central_directory_size = self.position - central_directory_offset;
+        let entry_count = entries.len() as u64;
+        let mut end = Vec::new();
+        if 0xFFFF <= entry_count || ZIP_U32_MAX <= central_directory_offset || ZIP_U32_MAX <= central_directory_size {
+            let zip64_end_offset = self.position;
+            end.extend_from_slice(&0x06064b50u32.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&ZIP_VERSION_MADE_BY.to_le_bytes());
+            end.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&entry_count.to_le_bytes());
+            end.extend_from_slice(&entry_count.to_le_bytes());
+            end.extend_from_slice(&central_directory_size.to_le_bytes());
+            end.extend_from_slice(&central_directory_offset.to_le_bytes());
+            end.extend_from_slice(&0x07064b50u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&zip64_end_offset.to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end.extend_from_slice(&(entry_count.min(0xFFFF) as u16).to_le_bytes());
+        end.extend_from_slice(&(entry_count.min(0xFFFF) as u16).to_le_bytes());
+        end.extend_from_slice(&(central_directory_size.min(ZIP_U32_MAX) as u32).to_le_bytes());
+        end.extend_from_slice(&(central_directory_offset.min(ZIP_U32_MAX) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.write_all(&end)?;
+        self.writer.flush()
+    }
+}
+
+fn new_sink<'a, W: Write + 'a> (archive_format: ArchiveFormat, writer: W) -> Box<dyn ArchiveSink + 'a> {
+    match archive_format {
+        ArchiveFormat::Zip => Box::new(ZipSink {writer, position: 0, entries: Vec::new()}),
+        ArchiveFormat::Tar => Box::new(TarSink {builder: tar::Builder::new(writer)}),
+        ArchiveFormat::TarGz => Box::new(TarGzSink {
+            tar: TarSink {builder: tar::Builder::new(flate2::write::GzEncoder::new(writer, flate2::Compression::default()))},
+        }),
+    }
+}
+
+// Команда после экспорта для архива, записанного в файл: папкой результата
+// считается папка архива, а файлом плейлиста - сам архив. Архив в stdout
+// команду не запускает - файла, который ей передать, нет
+pub fn run_archive_hook (command: &str, playlist_name: &str, archive_path: &Path, report: &ExportReport) -> Vec<String> {
+    let output_path = match archive_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    run_post_export_hook(command, &HookContext {
+        playlist_name,
+        output_path,
+        playlist_path: Some(archive_path),
+        exported:      report.exported,
+        failed:        report.failed,
+    })
+}
+
+// Отчёт как у export_playlist; playlist_path не заполняется - файл плейлиста
+// лежит внутри архива
+pub fn export_playlist_to_archive<W: Write> (playlist_name: &str, audio_in_playlist: &[AudioEntry], writer: W, archive_format: ArchiveFormat, options: &ExportOptions) -> ExportReport {
+    let mut report = ExportReport {
+        log:           Vec::new(),
+        exported:      0,
+        failed:        0,
+        playlist_path: None,
+    };
+    let mut sink = new_sink(archive_format, writer);
+    let archive_root = Path::new("");
+    let mut tracks = Vec::new();
+    let mut checksum_lines = String::new();
+    options.progress.add_total(audio_in_playlist.len());
+    for (audio, dest_path) in audio_in_playlist.iter().zip(plan_dest_paths(audio_in_playlist, archive_root)) {
+        options.progress.add_done();
+        let name = dest_path.to_string_lossy().into_owned();
+        if options.verify_checksums {
+            match checksums::sha256_file(Path::new(&audio.path)) {
+                Ok(sha256) => checksum_lines.push_str(&format!("{}  {}\n", sha256, name)),
+                Err(err) => {
+                    report.log.push(format!("# Ошибка: не удалось прочитать '{}': {}", audio.path, err));
+                    report.failed += 1;
+                    continue;
+                },
+            }
+        }
+        let opened = std::fs::File::open(&audio.path).and_then(|file| file.metadata().map(|metadata| (file, metadata)));
+        let (mut file, metadata) = match opened {
+            Ok(opened) => opened,
+            Err(err) => {
+                report.log.push(format!("# Ошибка: не удалось открыть '{}': {}", audio.path, err));
+                report.failed += 1;
+                continue;
+            },
+        };
+        if let Err(err) = sink.add_file(&name, &mut file, &metadata) {
+            report.log.push(format!("# Ошибка: запись архива прервана на '{}', архив испорчен: {}", audio.path, err));
+            report.failed = audio_in_playlist.len() - report.exported;
+            return report;
+        }
+        report.log.push(format!("{} => {}:{}", audio.path, archive_format, name));
+        report.exported += 1;
+        tracks.push(PlaylistTrack {audio, dest_path});
+    }
+    let context = PlaylistContext {
+        name: playlist_name,
+        playlist_dir: archive_root,
+        relative_locations: true,
+    };
+    match options.format.render(&context, &tracks) {
+        Ok((content, extension)) => {
+            let name = format!("{}.{}", sanitize_file_name(playlist_name), extension);
+            match sink.add_bytes(&name, content.as_bytes()) {
+                Ok(_) => report.log.push(format!("Записан плейлист {}: {}:{}", options.format, archive_format, name)),
+                Err(err) => {
+                    report.log.push(format!("# Ошибка: не удалось записать плейлист в архив: {}", err));
+                    return report;
+                },
+            }
+        },
+        Err(err) => report.log.push(format!("# Ошибка: {}", err)),
+    }
+    if options.verify_checksums {
+        match sink.add_bytes(checksums::CHECKSUM_FILE_NAME, checksum_lines.as_bytes()) {
+            Ok(_) => report.log.push(format!("Записаны контрольные суммы: {}:{}", archive_format, checksums::CHECKSUM_FILE_NAME)),
+            Err(err) => {
+                report.log.push(format!("# Ошибка: не удалось записать контрольные суммы в архив: {}", err));
+                return report;
+            },
+        }
+    }
+    if let Err(err) = sink.finish() {
+        report.log.push(format!("# Ошибка: не удалось завершить архив: {}", err));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at (bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+    fn u32_at (bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    // Читает записи ZIP по центральному каталогу: (имя, данные, версия создателя, флаги)
+    fn read_zip (archive: &[u8]) -> Vec<(String, Vec<u8>, u16, u16)> {
+        let end = archive.len() - 22;
+        assert_eq!(0x06054b50, u32_at(archive, end));
+        let entry_count = u16_at(archive, end + 10) as usize;
+        let mut at = u32_at(archive, end + 16) as usize;
+        let mut entries = Vec::new();
+        for _ in 0..entry_count {
+            assert_eq!(0x02014b50, u32_at(archive, at));
+            let (made_by, flags, crc, size) = (u16_at(archive, at + 4), u16_at(archive, at + 8), u32_at(archive, at + 16), u32_at(archive, at + 20) as usize);
+            let (name_len, extra_len, comment_len) = (u16_at(archive, at + 28) as usize, u16_at(archive, at + 30) as usize, u16_at(archive, at + 32) as usize);
+            let name = archive[at + 46..at + 46 + name_len].to_vec();
+            let local = u32_at(archive, at + 42) as usize;
+            assert_eq!(0x04034b50, u32_at(archive, local));
+            let (local_name_len, local_extra_len) = (u16_at(archive, local + 26) as usize, u16_at(archive, local + 28) as usize);
+            assert_eq!(name, &archive[local + 30..local + 30 + local_name_len]);
+            let data_start = local + 30 + local_name_len + local_extra_len;
+            let data = archive[data_start..data_start + size].to_vec();
+            assert_eq!(crc, crc32fast::hash(&data));
+            entries.push((String::from_utf8(name).unwrap(), data, made_by, flags));
+            at += 46 + name_len + extra_len + comment_len;
+        }
+        entries
+    }
+
+    #[test]
+    fn zip_round_trips_non_ascii_names () {
+        let mut archive = Vec::new();
+        let mut sink = Box::new(ZipSink {writer: &mut archive, position: 0, entries: Vec::new()});
+        sink.add_bytes("Кино/Группа крови — Кино.mp3", b"audio").unwrap();
+        sink.add_bytes("Плейлист ёж.m3u8", b"").unwrap();
+        sink.finish().unwrap();
+        let entries = read_zip(&archive);
+        assert_eq!(2, entries.len());
+        assert_eq!("Кино/Группа крови — Кино.mp3", entries[0].0);
+        assert_eq!(b"audio".to_vec(), entries[0].1);
+        assert_eq!("Плейлист ёж.m3u8", entries[1].0);
+        assert!(entries[1].1.is_empty());
+        for (_, _, made_by, flags) in &entries {
+            assert_eq!(ZIP_VERSION_MADE_BY, *made_by);
+            assert_eq!(3, made_by >> 8);
+            assert_ne!(0, flags & 0x0800);
+        }
+    }
+
+    #[test]
+    fn checksums_are_written_into_archive () {
+        let temp_dir = std::env::temp_dir().join(format!("audials-archive-test-{}-{}", "checksums", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let source = temp_dir.join("song.mp3");
+        std::fs::write(&source, b"audio").unwrap();
+        let audio = AudioEntry {
+            id:       0,
+            title:    String::new(),
+            artist:   String::new(),
+            path:     source.to_string_lossy().into_owned(),
+            metadata: Default::default(),
+        };
+        let options = ExportOptions {
+            format:             crate::playlist_formats::PlaylistOutput::Format(crate::playlist_formats::PlaylistFormat::M3u),
+            relative_locations: true,
+            transfer_mode:      crate::export::TransferMode::Copy,
+            verify_checksums:   true,
+            workers:            1,
+            post_export_hook:   None,
+            progress:           Default::default(),
+        };
+        let mut archive = Vec::new();
+        let report = export_playlist_to_archive("Плейлист", &[audio], &mut archive, ArchiveFormat::Zip, &options);
+        assert_eq!((1, 0), (report.exported, report.failed));
+        assert_eq!("Идёт экспорт: обработано файлов 1 из 1", options.progress.summary());
+        let entries = read_zip(&archive);
+        let sums = entries.iter().find(|entry| entry.0 == checksums::CHECKSUM_FILE_NAME).unwrap();
+        let expected = format!("{}  song.mp3\n", checksums::sha256_file(&source).unwrap());
+        assert_eq!(expected.as_bytes(), sums.1.as_slice());
+        std::fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn dos_datetime_packs_fields () {
+        // 2021-03-04 05:06:08
+        let (dos_time, dos_date) = ZipSink::<Vec<u8>>::dos_datetime(1614834368);
+        assert_eq!((5 << 11) | (6 << 5) | 4, dos_time);
+        assert_eq!((41 << 9) | (3 << 5) | 4, dos_date);
+    }
+}
@@ -0,0 +1,33 @@
+// Долгая работа окна (экспорт, синхронизация, архив) идёт в отдельном потоке,
+// иначе окно замирает до конца копирования. Поток отмечает ход работы в общем
+// export::ExportProgress, окно перерисовывает его по тикам, а по окончании
+// получает сообщение с логом
+
+use std::time::Duration;
+
+use iced::futures::channel::oneshot;
+
+// как часто окно перерисовывает ход работы
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+// work выполняется в своём потоке, его лог приходит сообщением to_message
+pub fn run<M: Send + 'static> (work: impl FnOnce() -> Vec<String> + Send + 'static, to_message: fn(Vec<String>) -> M) -> iced::Command<M> {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(work());
+    });
+    iced::Command::perform(receiver, move |log| to_message(
+        // отправитель пропал без ответа - поток упал с паникой
+        log.unwrap_or_else(|_| vec!["# Ошибка: фоновая работа прервалась аварийно, её лог потерян".to_string()])
+    ))
+}
+
+// Сообщение message через TICK_INTERVAL
+pub fn tick<M: Clone + Send + 'static> (message: M) -> iced::Command<M> {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        std::thread::sleep(TICK_INTERVAL);
+        let _ = sender.send(());
+    });
+    iced::Command::perform(receiver, move |_| message.clone())
+}
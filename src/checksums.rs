@@ -0,0 +1,186 @@
+// Проверка копий по SHA-256: карты памяти иногда портят файлы молча, поэтому
+// после переноса сравниваются суммы исходного файла и копии. Суммы сохраняются
+// в журнале экспорта и в SHA256SUMS рядом с плейлистом (формат sha256sum, его
+// можно проверить и без программы: sha256sum -c SHA256SUMS). По журналу
+// экспорт потом можно перепроверить целиком
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::MyResult;
+use crate::export_manifest::{EntryStatus, ExportManifest, MANIFEST_FILE_NAME, write_atomically};
+use crate::playlist_formats::relative_path;
+
+pub const CHECKSUM_FILE_NAME: &str = "SHA256SUMS";
+
+pub fn sha256_file (path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if 0 == read {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Копия сбрасывается на носитель, а её страницы убираются из кэша, чтобы
+// сумма считалась по тому, что действительно записано на карту, а не по
+// памяти. На linux и android это fsync и posix_fadvise(POSIX_FADV_DONTNEED).
+// На остальных системах проверка лишь по возможности (best-effort): на прочих
+// unix только fsync, в windows - попытка FlushFileBuffers без ошибки при
+// неудаче, и сумма может посчитаться по кэшу
+#[cfg(unix)]
+fn flush_and_evict (path: &Path) -> std::io::Result<()> {
+    let file = std::fs::File::open(path)?;
+    file.sync_all()?;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::io::AsRawFd;
+        // совет ядру, а не требование: ошибка ничего не портит
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+    Ok(())
+}
+#[cfg(not(unix))]
+fn flush_and_evict (path: &Path) -> std::io::Result<()> {
+    if let Ok(file) = std::fs::OpenOptions::new().write(true).open(path) {
+        let _ = file.sync_all();
+    }
+    Ok(())
+}
+
+// Сумма копии, если она совпала с суммой исходного файла
+pub fn verify_copy (source: &Path, dest_path: &Path) -> MyResult<String> {
+    flush_and_evict(dest_path).map_err(|err| format!("не удалось сбросить на диск '{}': {}", dest_path.to_string_lossy(), err))?;
+    let source_sum = sha256_file(source).map_err(|err| format!("не удалось прочитать '{}': {}", source.to_string_lossy(), err))?;
+    let dest_sum = sha256_file(dest_path).map_err(|err| format!("не удалось прочитать '{}': {}", dest_path.to_string_lossy(), err))?;
+    if source_sum != dest_sum {
+        return Err(format!("копия '{}' не совпадает с '{}' (SHA-256 {} вместо {})", dest_path.to_string_lossy(), source.to_string_lossy(), dest_sum, source_sum));
+    }
+    Ok(dest_sum)
+}
+
+// SHA256SUMS с путями относительно папки результата, через '/', как в sha256sum
+pub fn write_checksum_file (output_path: &Path, manifest: &mut ExportManifest) -> MyResult<PathBuf> {
+    let checksum_path = output_path.join(CHECKSUM_FILE_NAME);
+    let mut content = String::new();
+    for entry in manifest.entries.iter().filter(|entry| EntryStatus::Done == entry.status) {
+        if let Some(sha256) = &entry.sha256 {
+            let relative: Vec<String> = relative_path(&entry.dest, output_path).iter().map(|part| part.to_string_lossy().into_owned()).collect();
+            content.push_str(&format!("{}  {}\n", sha256, relative.join("/")));
+        }
+    }
+    manifest.add_playlist_file(&checksum_path)?;
+    write_atomically(&checksum_path, content.as_bytes())
+        .map_err(|err| format!("не удалось записать '{}': {}", checksum_path.to_string_lossy(), err))?;
+    Ok(checksum_path)
+}
+
+// Перепроверка экспорта по журналу: каждый перенесённый трек сверяется с
+// суммой, записанной при экспорте, а если её нет - с исходным файлом
+pub fn verify_export (output_path: &Path) -> Vec<String> {
+    let mut log = Vec::new();
+    let manifest = match ExportManifest::load(output_path) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => {
+            log.push(format!("# Ошибка: в '{}' нет журнала экспорта ({}), проверять не с чем", output_path.to_string_lossy(), MANIFEST_FILE_NAME));
+            return log;
+        },
+        Err(err) => {
+            log.push(format!("# Ошибка: {}", err));
+            return log;
+        },
+    };
+    if !manifest.complete {
+        log.push(format!("# Замечание: экспорт '{}' не завершён, проверяются только перенесённые треки", manifest.name));
+    }
+    let (mut matched, mut mismatched, mut missing, mut skipped) = (0, 0, 0, 0);
+    for entry in &manifest.entries {
+        if EntryStatus::Done != entry.status {
+            skipped += 1;
+            continue;
+        }
+        let dest_sum = match sha256_file(&entry.dest) {
+            Ok(dest_sum) => dest_sum,
+            Err(err) => {
+                log.push(format!("# Ошибка: '{}' не читается: {}", entry.dest.to_string_lossy(), err));
+                missing += 1;
+                continue;
+            },
+        };
+        let expected = match &entry.sha256 {
+            Some(sha256) => sha256.clone(),
+            None => match sha256_file(Path::new(&entry.source)) {
+                Ok(source_sum) => source_sum,
+                Err(err) => {
+                    log.push(format!("# Замечание: для '{}' нет суммы в журнале, а исходный файл не читается: {}", entry.dest.to_string_lossy(), err));
+                    skipped += 1;
+                    continue;
+                },
+            },
+        };
+        if dest_sum == expected {
+            log.push(format!("OK {}", entry.dest.to_string_lossy()));
+            matched += 1;
+        }
+        else {
+            log.push(format!("# Ошибка: '{}' повреждён: SHA-256 {} вместо {} (исходный файл '{}')", entry.dest.to_string_lossy(), dest_sum, expected, entry.source));
+            mismatched += 1;
+        }
+    }
+    log.push(format!(
+        "Проверка экспорта '{}': совпало {}, не совпало {}, не читается {}, не проверено {}",
+        manifest.name, matched, mismatched, missing, skipped
+    ));
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_export_reports_corrupted_copy () {
+        let dir = std::env::temp_dir().join(format!("audials-checksums-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (library, output) = (dir.join("library"), dir.join("output"));
+        std::fs::create_dir_all(&library).unwrap();
+        std::fs::create_dir_all(&output).unwrap();
+        let planned: Vec<(String, PathBuf)> = ["good.mp3", "bad.mp3"].iter().map(|file_name| {
+            let source = library.join(file_name);
+            std::fs::write(&source, file_name.repeat(1000)).unwrap();
+            (source.to_string_lossy().into_owned(), output.join(file_name))
+        }).collect();
+        let plan: Vec<(&str, &Path)> = planned.iter().map(|(source, dest)| (source.as_str(), dest.as_path())).collect();
+        let mut manifest = ExportManifest::begin(&output, "Road Trip", &plan, &mut Vec::new()).unwrap();
+        for (i, (source, dest)) in planned.iter().enumerate() {
+            std::fs::copy(source, dest).unwrap();
+            let sha256 = verify_copy(Path::new(source), dest).unwrap();
+            manifest.set_checksum(i, sha256);
+            manifest.set_status(i, EntryStatus::Done).unwrap();
+        }
+        let checksum_path = write_checksum_file(&output, &mut manifest).unwrap();
+        manifest.finish().unwrap();
+        assert_eq!(2, std::fs::read_to_string(&checksum_path).unwrap().lines().count());
+
+        // карта испортила байт в копии, размер тот же
+        let mut corrupted = std::fs::read(&planned[1].1).unwrap();
+        corrupted[10] ^= 0xff;
+        std::fs::write(&planned[1].1, corrupted).unwrap();
+        assert!(verify_copy(Path::new(&planned[1].0), &planned[1].1).is_err());
+
+        let log = verify_export(&output);
+        assert!(log.iter().any(|line| line.starts_with("OK ") && line.contains("good.mp3")));
+        assert!(log.iter().any(|line| line.starts_with("# Ошибка") && line.contains("bad.mp3") && line.contains("повреждён")));
+        assert_eq!(Some(&"Проверка экспорта 'Road Trip': совпало 1, не совпало 1, не читается 0, не проверено 0".to_string()), log.last());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
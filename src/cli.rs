@@ -0,0 +1,377 @@
+// Режим командной строки: тот же поиск файлов Audials и тот же экспорт, что в
+// окне, но без окна - для скриптов и запуска по расписанию. Включается, если
+// программе переданы аргументы. Лог пишется в stdout, ошибки ещё и в stderr,
+// код возврата 1, если была хоть одна ошибка. Если в stdout идёт архив, весь
+// лог уходит в stderr
+
+use std::path::PathBuf;
+
+use crate::{BaseMenuData, DataFilesMenu, MyFileEntry, MyResult, PlaylistEntry, SelectionMenu};
+use crate::{archive, checksums, export, export_manifest, get_audio_entries_from_ids, get_entries_ids_for_playlist_entry, resolve_playlists, settings, shared_pool, sync, track_selection, volumes};
+use crate::archive::ArchiveFormat;
+use crate::export::TransferMode;
+use crate::playlist_formats::PlaylistOutput;
+
+const USAGE: &str = "\
+Использование:
+  audials-playlist-exporter                       окно программы
+  audials-playlist-exporter --audials ПАПКА --list
+  audials-playlist-exporter --audials ПАПКА --library ПАПКА --output ПАПКА (--playlist ИМЯ ... | --all) [параметры]
+  audials-playlist-exporter --output ПАПКА (--verify-export | --rollback)
+
+Параметры:
+  --audials ПАПКА     папка Audials, файлы плейлистов и база ищутся в ней, как в окне
+  --library ПАПКА     папка библиотеки с музыкой
+  --output ПАПКА      куда экспортировать; при нескольких плейлистах - по подпапке на плейлист
+                      (с --pool - плейлисты рядом, треки в общей папке Music)
+                      (с --archive - по архиву на плейлист, '-' - один архив в stdout)
+  --playlist ИМЯ      имя или id плейлиста, можно указать несколько раз;
+                      если имя у нескольких плейлистов одно - только id
+  --all               все плейлисты, включая умные
+  --list              вывести плейлисты (id и имя) и выйти
+  --format КЛЮЧ       формат плейлиста: m3u8, pls, xspf, wpl, asx
+  --template ФАЙЛ     формат плейлиста по шаблону пользователя
+  --absolute          абсолютные пути в плейлисте
+  --relative          относительные пути в плейлисте
+  --archive ФОРМАТ    упаковать в архив: zip, tar, tar.gz
+  --pool              каждый трек один раз в общую папку Music, плейлисты ссылаются на неё
+  --volumes РАЗМЕР    разбить плейлист на тома Vol 01, Vol 02, ... по порядку треков:
+                      cd (700 МБ), dvd (4.7 ГБ) или размер тома в МБ
+  --mode СПОСОБ       как переносить треки: copy, hardlink, symlink, symlink-abs, reflink;
+                      если ссылку сделать нельзя, файл копируется
+  --sync              синхронизировать папку с плейлистом: показать план (пробный прогон)
+  --apply             вместе с --sync - выполнить план: перенести новое и изменённое,
+                      удалить аудиофайлы, которых нет в плейлисте
+  --jobs N            сколько файлов переносить одновременно (по умолчанию из окна)
+  --hook КОМАНДА      команда после экспорта каждого плейлиста (sh -c, в windows cmd /C),
+                      получает AUDIALS_PLAYLIST_NAME, AUDIALS_OUTPUT_DIR,
+                      AUDIALS_PLAYLIST_FILE, AUDIALS_EXPORTED, AUDIALS_FAILED;
+                      с --archive файлом плейлиста считается архив, в stdout - не запускается
+  --no-hook           не запускать команду из настроек окна
+  --all-tracks        экспортировать и треки, исключённые в окне для этого плейлиста
+  --no-checksums      не сверять копии по SHA-256 и не писать SHA256SUMS
+  --checksums         сверять (по умолчанию, если не выключено в окне)
+  --verify-export     перепроверить экспорт в папке --output по журналу и SHA-256
+  --rollback          откатить последний экспорт в папку --output по её журналу
+                      (удалить созданные им файлы); прерванный экспорт продолжается,
+                      если запустить его снова с теми же параметрами
+  --compare-content   при --sync сравнивать содержимое, а не размер и время
+  --snapshot          читать копию базы (если Audials запущен)
+  --help              эта справка
+
+Формат, вид путей и способ переноса по умолчанию берутся из настроек окна, как и
+исключённые из плейлиста треки.";
+
+struct CliOptions {
+    audials_path:       Option<PathBuf>,
+    library_path:       Option<PathBuf>,
+    output_path:        Option<PathBuf>,
+    playlists:          Vec<String>,
+    all_playlists:      bool,
+    list:               bool,
+    format:             PlaylistOutput,
+    relative_locations: bool,
+    archive_format:     Option<ArchiveFormat>,
+    transfer_mode:      TransferMode,
+    pool:               bool,
+    volume_size:        Option<u64>,
+    rollback:           bool,
+    verify_checksums:   bool,
+    workers:            usize,
+    post_export_hook:   Option<String>,
+    all_tracks:         bool,
+    verify_export:      bool,
+    sync:               bool,
+    apply:              bool,
+    compare_content:    bool,
+    use_snapshot:       bool,
+}
+
+fn parse_args (args: &[String], settings: &settings::Settings) -> MyResult<Option<CliOptions>> {
+    let mut options = CliOptions {
+        audials_path:       None,
+        library_path:       None,
+        output_path:        None,
+        playlists:          Vec::new(),
+        all_playlists:      false,
+        list:               false,
+        format:             settings.playlist_format.clone(),
+        relative_locations: settings.relative_locations,
+        archive_format:     None,
+        transfer_mode:      settings.transfer_mode,
+        pool:               false,
+        volume_size:        None,
+        rollback:           false,
+        verify_checksums:   settings.verify_checksums,
+        workers:            settings.copy_workers,
+        post_export_hook:   Some(settings.post_export_hook.clone()).filter(|command| !command.is_empty()),
+        all_tracks:         false,
+        verify_export:      false,
+        sync:               false,
+        apply:              false,
+        compare_content:    false,
+        use_snapshot:       false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(format!("после {} нужно значение", arg));
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(None),
+            "--audials"  => options.audials_path = Some(value()?.into()),
+            "--library"  => options.library_path = Some(value()?.into()),
+            "--output"   => options.output_path = Some(value()?.into()),
+            "--playlist" => options.playlists.push(value()?),
+            "--all"      => options.all_playlists = true,
+            "--list"     => options.list = true,
+            "--format"   => {
+                let key = value()?;
+                options.format = PlaylistOutput::from_key(&key).ok_or(format!("неизвестный формат '{}'", key))?;
+            },
+            "--template" => options.format = PlaylistOutput::Template(value()?.into()),
+            "--absolute" => options.relative_locations = false,
+            "--relative" => options.relative_locations = true,
+            "--archive"  => {
+                let key = value()?;
+                options.archive_format = Some(ArchiveFormat::from_key(&key).ok_or(format!("неизвестный формат архива '{}'", key))?);
+            },
+            "--mode"     => {
+                let key = value()?;
+                options.transfer_mode = TransferMode::from_key(&key).ok_or(format!("неизвестный способ переноса '{}'", key))?;
+            },
+            "--pool"     => options.pool = true,
+            "--volumes"  => options.volume_size = Some(volumes::parse_volume_size(&value()?)?),
+            "--rollback" => options.rollback = true,
+            "--jobs" | "-j" => {
+                let count = value()?;
+                options.workers = count.parse().ok().filter(|workers| 0 < *workers).ok_or(format!("число потоков '{}' - нужно целое больше нуля", count))?;
+            },
+            "--hook"     => options.post_export_hook = Some(value()?),
+            "--no-hook"  => options.post_export_hook = None,
+            "--all-tracks" => options.all_tracks = true,
+            "--checksums" => options.verify_checksums = true,
+            "--no-checksums" => options.verify_checksums = false,
+            "--verify-export" => options.verify_export = true,
+            "--sync"     => options.sync = true,
+            "--apply"    => options.apply = true,
+            "--compare-content" => options.compare_content = true,
+            "--snapshot" => options.use_snapshot = true,
+            _ => return Err(format!("неизвестный аргумент '{}'", arg)),
+        }
+    }
+    Ok(Some(options))
+}
+
+// to_stderr - stdout занят архивом
+fn print_log (log: &[String], to_stderr: bool, has_errors: &mut bool) {
+    for line in log {
+        let is_error = line.starts_with("# Ошибка");
+        if to_stderr || is_error {
+            eprintln!("{}", line);
+        }
+        if !to_stderr {
+            println!("{}", line);
+        }
+        *has_errors |= is_error;
+    }
+}
+
+pub fn run (args: &[String]) -> i32 {
+    let (settings, settings_log) = settings::Settings::load();
+    print_log(&settings_log, true, &mut false);
+    let options = match parse_args(args, &settings) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return 0;
+        },
+        Err(err) => {
+            eprintln!("# Ошибка: {}\n\n{}", err, USAGE);
+            return 2;
+        },
+    };
+    match run_with_options(&options, &settings) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("# Ошибка: {}", err);
+            1
+        },
+    }
+}
+
+// Ok(false) - экспорт прошёл, но с ошибками
+fn run_with_options (options: &CliOptions, settings: &settings::Settings) -> MyResult<bool> {
+    if options.rollback || options.verify_export {
+        let output_path = options.output_path.clone().ok_or("не указана папка экспорта (--output)".to_string())?;
+        let mut has_errors = false;
+        if options.verify_export {
+            print_log(&checksums::verify_export(&output_path), false, &mut has_errors);
+        }
+        else {
+            print_log(&export_manifest::rollback(&output_path), false, &mut has_errors);
+        }
+        return Ok(!has_errors);
+    }
+    let base_menu_data = BaseMenuData {
+        audilas_path:        options.audials_path.clone().ok_or("не указана папка Audials (--audials)".to_string())?,
+        library_path:        options.library_path.clone().unwrap_or_default(),
+        validation_required: false,
+        is_opened:           false,
+    };
+    let mut data_files_menu_data = DataFilesMenu::new().data;
+    data_files_menu_data.use_database_snapshot = options.use_snapshot;
+    data_files_menu_data.init_auto(&base_menu_data);
+    for file_entry_error in [
+        file_entry_error(&data_files_menu_data.playlists),
+        file_entry_error(&data_files_menu_data.playlist_entries),
+        file_entry_error(&data_files_menu_data.audio_database),
+    ].iter().flatten() {
+        eprintln!("{}", file_entry_error);
+    }
+    if !data_files_menu_data.is_valid() {
+        return Err("файлы Audials не найдены или не прочитаны".into());
+    }
+
+    let mut selection_menu_data = SelectionMenu::new().data;
+    selection_menu_data.init(&data_files_menu_data, settings);
+    let mut has_errors = false;
+    let to_stdout = matches!(&options.output_path, Some(output_path) if output_path.as_os_str() == "-");
+    print_log(&selection_menu_data.playlists_error_log, to_stdout, &mut has_errors);
+    if options.list {
+        for playlist in &selection_menu_data.playlists {
+            println!("{}\t{}", playlist.id, playlist.display_name());
+        }
+        return Ok(!has_errors);
+    }
+
+    let playlists: Vec<PlaylistEntry> = if options.all_playlists {
+        selection_menu_data.playlists.clone()
+    }
+    else {
+        let mut playlists = Vec::new();
+        for wanted in &options.playlists {
+            // id однозначен, а имя у нескольких плейлистов может совпадать
+            let playlist = match selection_menu_data.playlists.iter().find(|playlist| &playlist.id == wanted) {
+                Some(playlist) => playlist,
+                None => {
+                    let named: Vec<&PlaylistEntry> = selection_menu_data.playlists.iter().filter(|playlist| &playlist.name == wanted).collect();
+                    match named.as_slice() {
+                        [] => return Err(format!("плейлист '{}' не найден, список: --list", wanted)),
+                        [playlist] => *playlist,
+                        _ => return Err(format!(
+                            "плейлистов с именем '{}' несколько, укажите нужный как --playlist <id>:\n{}",
+                            wanted,
+                            named.iter().map(|playlist| format!("{}\t{}", playlist.id, playlist.display_name())).collect::<Vec<String>>().join("\n")
+                        )),
+                    }
+                },
+            };
+            playlists.push(playlist.clone());
+        }
+        playlists
+    };
+    if playlists.is_empty() {
+        return Err("не выбраны плейлисты (--playlist или --all)".into());
+    }
+    if options.library_path.is_none() {
+        return Err("не указана папка библиотеки (--library)".into());
+    }
+    let output_path = options.output_path.clone().ok_or("не указана папка для экспорта (--output)".to_string())?;
+    if options.sync && options.archive_format.is_some() {
+        return Err("--sync и --archive несовместимы".into());
+    }
+    if options.pool && (options.sync || options.archive_format.is_some()) {
+        return Err("--pool несовместим с --sync и --archive".into());
+    }
+    if options.volume_size.is_some() && (options.pool || options.sync || options.archive_format.is_some()) {
+        return Err("--volumes несовместим с --pool, --sync и --archive".into());
+    }
+    if to_stdout && (options.archive_format.is_none() || 1 != playlists.len()) {
+        return Err("в stdout (--output -) выводится только архив (--archive) одного плейлиста".into());
+    }
+
+    let export_options = export::ExportOptions {
+        format:             options.format.clone(),
+        relative_locations: options.relative_locations,
+        transfer_mode:      options.transfer_mode,
+        verify_checksums:   options.verify_checksums,
+        workers:            options.workers,
+        post_export_hook:   options.post_export_hook.clone(),
+        progress:           Default::default(),
+    };
+    if options.pool {
+        let mut resolve_log = Vec::new();
+        let mut resolved_playlists = resolve_playlists(&playlists, &base_menu_data, &data_files_menu_data, &mut resolve_log);
+        if !options.all_tracks {
+            track_selection::remove_saved_exclusions_from_all(&mut resolved_playlists, settings, &mut resolve_log);
+        }
+        print_log(&resolve_log, false, &mut has_errors);
+        let report = shared_pool::export_playlists_to_pool(&resolved_playlists, &output_path, &export_options);
+        print_log(&report.log, false, &mut has_errors);
+        return Ok(!has_errors);
+    }
+    for playlist in &playlists {
+        let mut playlist_log = Vec::new();
+        let audio_ids = get_entries_ids_for_playlist_entry(playlist, &data_files_menu_data, &mut playlist_log);
+        let mut audio = get_audio_entries_from_ids(&audio_ids, &base_menu_data, &data_files_menu_data, &mut playlist_log);
+        let not_found = audio_ids.len() - audio.len();
+        if !options.all_tracks {
+            track_selection::remove_saved_exclusions(&playlist.name, &playlist.id, &mut audio, settings, &mut playlist_log);
+        }
+        print_log(&playlist_log, to_stdout, &mut has_errors);
+        if let Some(archive_format) = options.archive_format {
+            if to_stdout {
+                let report = archive::export_playlist_to_archive(&playlist.name, &audio, std::io::stdout().lock(), archive_format, &export_options);
+                print_log(&report.log, to_stdout, &mut has_errors);
+                eprintln!("Плейлист '{}': в архиве {}, ошибок {}, не найдено {}", playlist.name, report.exported, report.failed, not_found);
+                continue;
+            }
+            std::fs::create_dir_all(&output_path)
+                .map_err(|err| format!("не удалось создать папку '{}': {}", output_path.to_string_lossy(), err))?;
+            let archive_path = output_path.join(format!("{}.{}", export::sanitize_file_name(&playlist.name), archive_format.extension()));
+            let archive_file = std::fs::File::create(&archive_path)
+                .map_err(|err| format!("не удалось создать '{}': {}", archive_path.to_string_lossy(), err))?;
+            let report = archive::export_playlist_to_archive(&playlist.name, &audio, std::io::BufWriter::new(archive_file), archive_format, &export_options);
+            print_log(&report.log, to_stdout, &mut has_errors);
+            if let Some(command) = &export_options.post_export_hook {
+                print_log(&archive::run_archive_hook(command, &playlist.name, &archive_path, &report), to_stdout, &mut has_errors);
+            }
+            eprintln!("Плейлист '{}': в архиве {}, ошибок {}, не найдено {}", playlist.name, report.exported, report.failed, not_found);
+            continue;
+        }
+        let playlist_output_path = if 1 == playlists.len() {
+            output_path.clone()
+        }
+        else {
+            output_path.join(export::sanitize_file_name(&playlist.name))
+        };
+        std::fs::create_dir_all(&playlist_output_path)
+            .map_err(|err| format!("не удалось создать папку '{}': {}", playlist_output_path.to_string_lossy(), err))?;
+        if options.sync {
+            let sync_plan = sync::plan_sync(&playlist.name, &audio, &playlist_output_path, options.compare_content);
+            print_log(&sync_plan.diff(), false, &mut has_errors);
+            if options.apply {
+                let report = sync::apply_sync(&playlist.name, &audio, &sync_plan, &export_options);
+                print_log(&report.log, false, &mut has_errors);
+            }
+            else {
+                println!("Пробный прогон, ничего не изменено; для выполнения добавьте --apply");
+            }
+            continue;
+        }
+        let report = match options.volume_size {
+            Some(volume_size) => volumes::export_playlist_volumes(&playlist.name, &audio, &playlist_output_path, volume_size, &export_options),
+            None => export::export_playlist(&playlist.name, &audio, &playlist_output_path, &export_options),
+        };
+        print_log(&report.log, false, &mut has_errors);
+        println!("Плейлист '{}': скопировано {}, ошибок {}, не найдено {}", playlist.name, report.exported, report.failed, not_found);
+    }
+    Ok(!has_errors)
+}
+
+fn file_entry_error<T: ToString> (file_entry: &MyFileEntry<T>) -> Option<String> {
+    match file_entry {
+        MyFileEntry::InvalidWithError(err) => Some(err.full_error.clone()),
+        _ => None,
+    }
+}
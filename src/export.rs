@@ -0,0 +1,476 @@
+// Экспорт плейлиста: треки копируются (или связываются ссылками) в папку
+// результата, рядом пишется файл плейлиста выбранного формата. Всё, что
+// произошло, складывается в лог
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{AudioEntry, MyResult, checksums};
+use crate::export_manifest::{EntryStatus, ExportManifest, part_path, write_atomically};
+use crate::hooks::{HookContext, run_post_export_hook};
+use crate::playlist_formats::{PlaylistContext, PlaylistOutput, PlaylistTrack, relative_path};
+use crate::space_estimate::estimate_space;
+
+// варианты числа одновременных переносов в окне; в командной строке - любое
+pub const COPY_WORKER_CHOICES: [usize; 5] = [1, 2, 4, 8, 16];
+
+pub struct ExportOptions {
+    pub format:             PlaylistOutput,
+    pub relative_locations: bool,
+    pub transfer_mode:      TransferMode,
+    // сверять копии с исходными файлами по SHA-256 и писать SHA256SUMS
+    pub verify_checksums:   bool,
+    // сколько файлов переносится одновременно
+    pub workers:            usize,
+    // команда после экспорта каждого плейлиста, см. hooks
+    pub post_export_hook:   Option<String>,
+    // ход экспорта, окно показывает его, пока экспорт идёт в своём потоке
+    pub progress:           Arc<ExportProgress>,
+}
+
+// Сколько файлов перенесено из скольких. Счётчики только растут: при
+// экспорте по томам и нескольких плейлистов общее число дополняется по мере
+// того, как становится известен план очередной части
+#[derive(Default)]
+pub struct ExportProgress {
+    done:  AtomicUsize,
+    total: AtomicUsize,
+}
+impl ExportProgress {
+    pub fn add_total (&self, count: usize) {
+        self.total.fetch_add(count, Ordering::SeqCst);
+    }
+    pub fn add_done (&self) {
+        self.done.fetch_add(1, Ordering::SeqCst);
+    }
+    pub fn summary (&self) -> String {
+        format!("Идёт экспорт: обработано файлов {} из {}", self.done.load(Ordering::SeqCst), self.total.load(Ordering::SeqCst))
+    }
+}
+
+// Как трек попадает в папку результата. Ссылки экономят место, когда папка
+// результата на той же файловой системе, что и библиотека
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferMode {
+    Copy,
+    Hardlink,
+    SymlinkRelative,
+    SymlinkAbsolute,
+    Reflink,
+}
+impl TransferMode {
+    pub const ALL: [TransferMode; 5] = [
+        TransferMode::Copy, TransferMode::Hardlink, TransferMode::SymlinkRelative, TransferMode::SymlinkAbsolute, TransferMode::Reflink,
+    ];
+    pub fn key (self) -> &'static str {
+        match self {
+            TransferMode::Copy            => "copy",
+            TransferMode::Hardlink        => "hardlink",
+            TransferMode::SymlinkRelative => "symlink",
+            TransferMode::SymlinkAbsolute => "symlink-abs",
+            TransferMode::Reflink         => "reflink",
+        }
+    }
+    pub fn from_key (key: &str) -> Option<TransferMode> {
+        TransferMode::ALL.iter().copied().find(|mode| mode.key() == key)
+    }
+    fn method_name (self) -> &'static str {
+        match self {
+            TransferMode::Copy            => "копия",
+            TransferMode::Hardlink        => "жёсткая ссылка",
+            TransferMode::SymlinkRelative => "относительная символьная ссылка",
+            TransferMode::SymlinkAbsolute => "абсолютная символьная ссылка",
+            TransferMode::Reflink         => "reflink-копия",
+        }
+    }
+}
+impl std::fmt::Display for TransferMode {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TransferMode::Copy            => "Копировать файлы",
+            TransferMode::Hardlink        => "Жёсткие ссылки",
+            TransferMode::SymlinkRelative => "Символьные ссылки (относительные)",
+            TransferMode::SymlinkAbsolute => "Символьные ссылки (абсолютные)",
+            TransferMode::Reflink         => "Reflink (копирование при записи)",
+        })
+    }
+}
+
+#[cfg(unix)]
+fn symlink (target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+#[cfg(windows)]
+fn symlink (target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+// btrfs, XFS и т.п.: файл-клон делит блоки с исходным до первой записи
+#[cfg(target_os = "linux")]
+fn reflink (source: &Path, dest: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    const FICLONE: std::os::raw::c_ulong = 0x40049409;
+    let source_file = std::fs::File::open(source)?;
+    let dest_file = std::fs::OpenOptions::new().write(true).create_new(true).open(dest)?;
+    if 0 != unsafe { libc::ioctl(dest_file.as_raw_fd(), FICLONE as _, source_file.as_raw_fd()) } {
+        let err = std::io::Error::last_os_error();
+        drop(dest_file);
+        let _ = std::fs::remove_file(dest);
+        return Err(err);
+    }
+    Ok(())
+}
+#[cfg(target_os = "macos")]
+fn reflink (source: &Path, dest: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let to_c_string = |path: &Path| std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err));
+    let (source, dest) = (to_c_string(source)?, to_c_string(dest)?);
+    if 0 != unsafe { libc::clonefile(source.as_ptr(), dest.as_ptr(), 0) } {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink (_source: &Path, _dest: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Other, "не поддерживается в этой системе"))
+}
+
+// Кладёт трек в dest_path выбранным способом и возвращает, каким вышло на
+// самом деле: если ссылку сделать нельзя (другая файловая система, FAT на
+// флешке, нет прав на символьные ссылки в windows), файл копируется. Файл
+// сначала пишется под временным именем и переименовывается только целым
+pub fn transfer_file (source: &Path, dest_path: &Path, mode: TransferMode) -> std::io::Result<String> {
+    // при повторном экспорте в ту же папку старый файл заменяется, но папка
+    // результата внутри библиотеки не должна стереть сам трек
+    let dest_is_symlink = matches!(dest_path.symlink_metadata(), Ok(metadata) if metadata.file_type().is_symlink());
+    if let (false, Ok(source_real), Ok(dest_real)) = (dest_is_symlink, source.canonicalize(), dest_path.canonicalize()) {
+        if source_real == dest_real {
+            return Ok("файл уже на месте".to_string());
+        }
+    }
+    let temp_path = part_path(dest_path);
+    if temp_path.symlink_metadata().is_ok() {
+        std::fs::remove_file(&temp_path)?;
+    }
+    let method = transfer_to_path(source, &temp_path, mode)
+        .and_then(|method| std::fs::rename(&temp_path, dest_path).map(|_| method));
+    if method.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    method
+}
+
+fn transfer_to_path (source: &Path, dest_path: &Path, mode: TransferMode) -> std::io::Result<String> {
+    let link_res = match mode {
+        TransferMode::Copy            => std::fs::copy(source, dest_path).map(|_| ()),
+        TransferMode::Hardlink        => std::fs::hard_link(source, dest_path),
+        TransferMode::SymlinkRelative => symlink(&relative_path(source, dest_path.parent().unwrap_or(Path::new(""))), dest_path),
+        TransferMode::SymlinkAbsolute => symlink(source, dest_path),
+        TransferMode::Reflink         => reflink(source, dest_path),
+    };
+    match link_res {
+        Ok(_) => Ok(mode.method_name().to_string()),
+        Err(link_err) if TransferMode::Copy == mode => Err(link_err),
+        Err(link_err) => {
+            std::fs::copy(source, dest_path)?;
+            Ok(format!("копия, {} не создана: {}", mode.method_name(), link_err))
+        },
+    }
+}
+
+pub struct ExportReport {
+    pub log:           Vec<String>,
+    pub exported:      usize,
+    pub failed:        usize,
+    pub playlist_path: Option<PathBuf>,
+}
+
+// Символы, недопустимые в именах файлов на windows, заменяются на '_'
+pub fn sanitize_file_name (name: &str) -> String {
+    let sanitized: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) {'_'} else {c})
+        .collect();
+    let sanitized = sanitized.trim().trim_end_matches('.').to_string();
+    if sanitized.is_empty() {
+        "_".to_string()
+    }
+    else {
+        sanitized
+    }
+}
+
+// Пути назначения для треков: имя файла сохраняется, а при совпадении имён
+// у разных треков к следующим добавляется " (2)", " (3)" и т.д.
+pub fn plan_dest_paths<A: std::borrow::Borrow<AudioEntry>> (audio_in_playlist: &[A], output_dir: &Path) -> Vec<PathBuf> {
+    let mut used_names = std::collections::HashSet::new();
+    audio_in_playlist.iter().map(|audio| {
+        let source_path = Path::new(&audio.borrow().path);
+        let stem = source_path.file_stem().map_or(String::from("_"), |stem| stem.to_string_lossy().into_owned());
+        let extension = source_path.extension().map(|extension| extension.to_string_lossy().into_owned());
+        let mut number = 1;
+        loop {
+            let file_name = match (1 == number, &extension) {
+                (true, Some(extension)) => format!("{}.{}", stem, extension),
+                (true, None) => stem.clone(),
+                (false, Some(extension)) => format!("{} ({}).{}", stem, number, extension),
+                (false, None) => format!("{} ({})", stem, number),
+            };
+            // на windows и в FAT регистр в именах не различается
+            if used_names.insert(file_name.to_lowercase()) {
+                return output_dir.join(file_name);
+            }
+            number += 1;
+        }
+    }).collect()
+}
+
+// Размер для лога: "512 Б", "3.4 МБ", "1.20 ГБ"
+pub fn format_size (bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    let bytes_f = bytes as f64;
+    if bytes_f < KB {
+        format!("{} Б", bytes)
+    }
+    else if bytes_f < KB * KB {
+        format!("{:.1} КБ", bytes_f / KB)
+    }
+    else if bytes_f < KB * KB * KB {
+        format!("{:.1} МБ", bytes_f / KB / KB)
+    }
+    else {
+        format!("{:.2} ГБ", bytes_f / KB / KB / KB)
+    }
+}
+
+pub fn write_playlist_file (playlist_name: &str, tracks: &[PlaylistTrack], playlist_dir: &Path, options: &ExportOptions) -> MyResult<PathBuf> {
+    write_playlist_file_with_manifest(playlist_name, tracks, playlist_dir, options, None)
+}
+
+// Как write_playlist_file, но файл плейлиста заносится в журнал экспорта до
+// записи - для отката
+pub fn write_playlist_file_with_manifest (playlist_name: &str, tracks: &[PlaylistTrack], playlist_dir: &Path, options: &ExportOptions, manifest: Option<&mut ExportManifest>) -> MyResult<PathBuf> {
+    let context = PlaylistContext {
+        name: playlist_name,
+        playlist_dir,
+        relative_locations: options.relative_locations,
+    };
+    let (content, extension) = options.format.render(&context, tracks)?;
+    let playlist_path = playlist_dir.join(format!("{}.{}", sanitize_file_name(playlist_name), extension));
+    if let Some(manifest) = manifest {
+        manifest.add_playlist_file(&playlist_path)?;
+    }
+    write_atomically(&playlist_path, content.as_bytes())
+        .map_err(|err| format!("не удалось записать файл плейлиста '{}': {}", playlist_path.to_string_lossy(), err))?;
+    Ok(playlist_path)
+}
+
+// Способ переноса и сумма копии, если она проверялась
+pub type TransferOutcome = MyResult<(String, Option<String>)>;
+
+// Откуда, куда, уже перенесён, уже проверен
+pub type TransferJob = (PathBuf, PathBuf, bool, bool);
+
+// Перенос одного трека журнала в потоке-исполнителе: перенесённый до
+// прерывания не трогается, копия сверяется с исходным файлом, если это
+// включено. Не совпавшая копия удаляется, чтобы на карте не осталось
+// испорченного файла. Возвращает способ переноса и сумму копии
+fn run_transfer_job (source: &Path, dest_path: &Path, already_done: bool, options: &ExportOptions) -> TransferOutcome {
+    let mut method = if already_done {
+        "перенесён до прерывания".to_string()
+    }
+    else {
+        transfer_file(source, dest_path, options.transfer_mode)
+            .map_err(|err| format!("не удалось перенести '{}' в '{}': {}", source.to_string_lossy(), dest_path.to_string_lossy(), err))?
+    };
+    if !options.verify_checksums {
+        return Ok((method, None));
+    }
+    match checksums::verify_copy(source, dest_path) {
+        Ok(sha256) => {
+            method.push_str(", SHA-256 совпадает");
+            Ok((method, Some(sha256)))
+        },
+        Err(err) => {
+            let _ = std::fs::remove_file(dest_path);
+            Err(format!("{}, копия удалена", err))
+        },
+    }
+}
+
+// Перенос в options.workers потоков. Результаты передаются в on_result
+// строго по порядку jobs, так что лог, плейлист и журнал не зависят от того,
+// какой файл скопировался раньше
+pub fn run_transfer_jobs (jobs: &[TransferJob], options: &ExportOptions, mut on_result: impl FnMut(usize, TransferOutcome)) {
+    options.progress.add_total(jobs.len());
+    let next_job = AtomicUsize::new(0);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..std::cmp::max(1, std::cmp::min(options.workers, jobs.len())) {
+            let (next_job, sender) = (&next_job, sender.clone());
+            scope.spawn(move || loop {
+                let i = next_job.fetch_add(1, Ordering::SeqCst);
+                let (source, dest_path, already_done, already_verified) = match jobs.get(i) {
+                    Some(job) => job,
+                    None => break,
+                };
+                let result = if *already_done && *already_verified {
+                    Ok(("перенесён до прерывания".to_string(), None))
+                }
+                else {
+                    run_transfer_job(source, dest_path, *already_done, options)
+                };
+                if sender.send((i, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        // пришедшие раньше очереди ждут, пока не придут все предыдущие
+        let mut arrived: Vec<Option<TransferOutcome>> = jobs.iter().map(|_| None).collect();
+        let mut next_result = 0;
+        for (i, result) in receiver {
+            options.progress.add_done();
+            arrived[i] = Some(result);
+            while let Some(result) = arrived.get_mut(next_result).and_then(|result| result.take()) {
+                on_result(next_result, result);
+                next_result += 1;
+            }
+        }
+    });
+}
+
+// Перенос всех треков журнала с отметкой состояния и сумм в журнале
+pub fn transfer_planned (manifest: &mut ExportManifest, options: &ExportOptions) -> Vec<MyResult<String>> {
+    let jobs: Vec<TransferJob> = manifest.entries.iter().enumerate()
+        .map(|(i, entry)| (PathBuf::from(&entry.source), entry.dest.clone(), manifest.is_done(i), entry.sha256.is_some()))
+        .collect();
+    let mut results = Vec::with_capacity(jobs.len());
+    run_transfer_jobs(&jobs, options, |i, result| {
+        let status = if result.is_ok() {EntryStatus::Done} else {EntryStatus::Failed};
+        let result = result.map(|(method, sha256)| {
+            if let Some(sha256) = sha256 {
+                manifest.set_checksum(i, sha256);
+            }
+            method
+        });
+        results.push(manifest.set_status(i, status).and(result));
+    });
+    results
+}
+
+pub fn export_playlist (playlist_name: &str, audio_in_playlist: &[AudioEntry], output_path: &Path, options: &ExportOptions) -> ExportReport {
+    let mut report = ExportReport {
+        log:           Vec::new(),
+        exported:      0,
+        failed:        0,
+        playlist_path: None,
+    };
+    let dest_paths = plan_dest_paths(audio_in_playlist, output_path);
+    let planned: Vec<(&str, &Path)> = audio_in_playlist.iter().zip(&dest_paths).map(|(audio, dest_path)| (audio.path.as_str(), dest_path.as_path())).collect();
+    let space_estimate = estimate_space(&planned, output_path);
+    report.log.push(space_estimate.summary());
+    match space_estimate.check(output_path, options.transfer_mode) {
+        Ok(note) => report.log.extend(note.map(|note| format!("# Замечание: {}", note))),
+        Err(err) => {
+            report.log.push(format!("# Ошибка: {}", err));
+            return report;
+        },
+    }
+    let mut manifest = match ExportManifest::begin(output_path, playlist_name, &planned, &mut report.log) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            report.log.push(format!("# Ошибка: {}", err));
+            return report;
+        },
+    };
+    let mut tracks = Vec::new();
+    let results = transfer_planned(&mut manifest, options);
+    for ((audio, dest_path), result) in audio_in_playlist.iter().zip(dest_paths).zip(results) {
+        match result {
+            Ok(method) => {
+                report.log.push(format!("{} => {} ({})", audio.path, dest_path.to_string_lossy(), method));
+                report.exported += 1;
+                tracks.push(PlaylistTrack {audio, dest_path});
+            },
+            Err(err) => {
+                report.log.push(format!("# Ошибка: {}", err));
+                report.failed += 1;
+            },
+        }
+    }
+    match write_playlist_file_with_manifest(playlist_name, &tracks, output_path, options, Some(&mut manifest)) {
+        Ok(playlist_path) => {
+            report.log.push(format!("Записан плейлист {}: {}", options.format, playlist_path.to_string_lossy()));
+            report.playlist_path = Some(playlist_path);
+        },
+        Err(err) => {
+            report.log.push(format!("# Ошибка: {}", err));
+        },
+    }
+    if options.verify_checksums {
+        match checksums::write_checksum_file(output_path, &mut manifest) {
+            Ok(checksum_path) => report.log.push(format!("Записаны контрольные суммы: {}", checksum_path.to_string_lossy())),
+            Err(err) => report.log.push(format!("# Ошибка: {}", err)),
+        }
+    }
+    if let Err(err) = manifest.finish() {
+        report.log.push(format!("# Ошибка: {}", err));
+    }
+    if let Some(command) = &options.post_export_hook {
+        report.log.extend(run_post_export_hook(command, &HookContext {
+            playlist_name,
+            output_path,
+            playlist_path: report.playlist_path.as_deref(),
+            exported:      report.exported,
+            failed:        report.failed,
+        }));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio (path: &str) -> AudioEntry {
+        AudioEntry {
+            id:       0,
+            title:    String::new(),
+            artist:   String::new(),
+            path:     path.to_string(),
+            metadata: Default::default(),
+        }
+    }
+
+    fn file_names (audio_in_playlist: &[AudioEntry]) -> Vec<String> {
+        plan_dest_paths(audio_in_playlist, Path::new("out")).iter()
+            .map(|dest_path| dest_path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn same_names_get_numbers () {
+        let audio_in_playlist = [audio("/a/song.mp3"), audio("/b/song.mp3"), audio("/c/song.mp3"), audio("/c/other")];
+        assert_eq!(vec!["song.mp3", "song (2).mp3", "song (3).mp3", "other"], file_names(&audio_in_playlist));
+    }
+
+    #[test]
+    fn names_differing_only_in_case_collide () {
+        let audio_in_playlist = [audio("/a/Song.MP3"), audio("/b/song.mp3"), audio("/c/SONG.mp3"), audio("/d/Песня.mp3"), audio("/e/ПЕСНЯ.mp3")];
+        assert_eq!(vec!["Song.MP3", "song (2).mp3", "SONG (3).mp3", "Песня.mp3", "ПЕСНЯ (2).mp3"], file_names(&audio_in_playlist));
+    }
+
+    #[test]
+    fn numbered_name_does_not_clash_with_existing_one () {
+        let audio_in_playlist = [audio("/a/song (2).mp3"), audio("/b/song.mp3"), audio("/c/song.mp3")];
+        assert_eq!(vec!["song (2).mp3", "song.mp3", "song (3).mp3"], file_names(&audio_in_playlist));
+    }
+
+    #[test]
+    fn file_names_are_sanitized () {
+        assert_eq!("a_b_c", sanitize_file_name("a/b:c"));
+        assert_eq!("_", sanitize_file_name(" .. "));
+    }
+}
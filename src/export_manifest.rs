@@ -0,0 +1,422 @@
+// Журнал экспорта: перед переносом в папку результата записывается манифест со
+// всеми запланированными файлами (откуда, куда, размер, состояние), и по ходу
+// экспорта состояние обновляется. Если флешку выдернули на середине, повторный
+// экспорт того же плейлиста в ту же папку продолжает с места остановки, а по
+// манифесту последний экспорт можно откатить - удалить всё, что он создал.
+//
+// Сами файлы пишутся под временным именем и переименовываются в конце, так что
+// файл с настоящим именем всегда целый (см. export::transfer_file)
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::MyResult;
+
+pub const MANIFEST_FILE_NAME: &str = ".audials-export.json";
+
+// манифест переписывается целиком, поэтому не после каждого трека, а не чаще
+// раза в секунду; перенесённый, но не отмеченный трек при продолжении
+// узнаётся по размеру файла
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryStatus {
+    Pending,
+    Done,
+    Failed,
+}
+impl EntryStatus {
+    fn key (self) -> &'static str {
+        match self {
+            EntryStatus::Pending => "pending",
+            EntryStatus::Done    => "done",
+            EntryStatus::Failed  => "failed",
+        }
+    }
+    fn from_key (key: &str) -> Option<EntryStatus> {
+        [EntryStatus::Pending, EntryStatus::Done, EntryStatus::Failed].iter().copied().find(|status| status.key() == key)
+    }
+}
+
+pub struct ManifestEntry {
+    pub source:         String,
+    pub dest:           PathBuf,
+    pub size:           u64,
+    pub status:         EntryStatus,
+    // файл был в папке до экспорта - откат его не удаляет
+    pub existed_before: bool,
+    // SHA-256 проверенной копии, см. checksums
+    pub sha256:         Option<String>,
+}
+
+// Файл плейлиста или SHA256SUMS
+pub struct ManifestFile {
+    pub path:           PathBuf,
+    pub existed_before: bool,
+}
+
+pub struct ExportManifest {
+    manifest_path:      PathBuf,
+    pub name:           String,
+    pub started:        u64,
+    pub complete:       bool,
+    pub entries:        Vec<ManifestEntry>,
+    pub playlist_files: Vec<ManifestFile>,
+    last_save:          Instant,
+}
+
+fn get_string (obj: &serde_json::Value, key: &str) -> Option<String> {
+    obj.get(key)?.as_str().map(|value| value.to_string())
+}
+
+fn unix_time_now () -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+impl ExportManifest {
+    pub fn load (output_path: &Path) -> MyResult<Option<ExportManifest>> {
+        let manifest_path = output_path.join(MANIFEST_FILE_NAME);
+        let content = match std::fs::read_to_string(&manifest_path) {
+            Ok(content) => content,
+            Err(err) if std::io::ErrorKind::NotFound == err.kind() => return Ok(None),
+            Err(err) => return Err(format!("не удалось прочитать журнал экспорта '{}': {}", manifest_path.to_string_lossy(), err)),
+        };
+        let root: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|err| format!("журнал экспорта '{}' повреждён: {}", manifest_path.to_string_lossy(), err))?;
+        let mut manifest = ExportManifest {
+            manifest_path,
+            name:           get_string(&root, "name").unwrap_or_default(),
+            started:        root.get("started").and_then(|started| started.as_u64()).unwrap_or(0),
+            complete:       root.get("complete").and_then(|complete| complete.as_bool()).unwrap_or(false),
+            entries:        Vec::new(),
+            playlist_files: Vec::new(),
+            last_save:      Instant::now(),
+        };
+        for entry in root.get("entries").and_then(|entries| entries.as_array()).into_iter().flatten() {
+            let (source, dest) = match (get_string(entry, "source"), get_string(entry, "dest")) {
+                (Some(source), Some(dest)) => (source, dest),
+                _ => continue,
+            };
+            manifest.entries.push(ManifestEntry {
+                source,
+                dest:           dest.into(),
+                size:           entry.get("size").and_then(|size| size.as_u64()).unwrap_or(0),
+                status:         get_string(entry, "status").and_then(|key| EntryStatus::from_key(&key)).unwrap_or(EntryStatus::Pending),
+                existed_before: entry.get("existed_before").and_then(|value| value.as_bool()).unwrap_or(true),
+                sha256:         get_string(entry, "sha256"),
+            });
+        }
+        for playlist_file in root.get("playlist_files").and_then(|files| files.as_array()).into_iter().flatten() {
+            if let Some(path) = get_string(playlist_file, "path") {
+                manifest.playlist_files.push(ManifestFile {
+                    path:           path.into(),
+                    existed_before: playlist_file.get("existed_before").and_then(|value| value.as_bool()).unwrap_or(true),
+                });
+            }
+        }
+        Ok(Some(manifest))
+    }
+
+    // Начало экспорта: незавершённый экспорт с тем же именем и теми же парами
+    // источник -> назначение продолжается, иначе пишется новый манифест
+    pub fn begin (output_path: &Path, name: &str, planned: &[(&str, &Path)], log: &mut Vec<String>) -> MyResult<ExportManifest> {
+        let previous = match ExportManifest::load(output_path) {
+            Ok(previous) => previous,
+            Err(err) => {
+                log.push(format!("# Замечание: {}, он будет перезаписан", err));
+                None
+            },
+        };
+        if let Some(mut previous) = previous {
+            let same_plan = previous.name == name
+                && previous.entries.len() == planned.len()
+                && previous.entries.iter().zip(planned).all(|(entry, (source, dest))| entry.source == *source && entry.dest == *dest);
+            if same_plan && !previous.complete {
+                for entry in previous.entries.iter_mut().filter(|entry| EntryStatus::Done != entry.status) {
+                    // запись в манифест могла не успеть, а сам файл появляется только целым;
+                    // неудачный перенос (в том числе не прошедший проверку суммы) повторяется
+                    let _ = std::fs::remove_file(part_path(&entry.dest));
+                    if EntryStatus::Failed == entry.status {
+                        entry.status = EntryStatus::Pending;
+                        continue;
+                    }
+                    let dest_size = entry.dest.metadata().ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len());
+                    if !entry.existed_before && Some(entry.size) == dest_size {
+                        entry.status = EntryStatus::Done;
+                    }
+                }
+                let done = previous.entries.iter().filter(|entry| EntryStatus::Done == entry.status).count();
+                log.push(format!("Продолжение прерванного экспорта '{}': уже перенесено {} из {}", name, done, previous.entries.len()));
+                previous.save()?;
+                return Ok(previous);
+            }
+            if !previous.complete {
+                log.push(format!("# Замечание: в папке остался незавершённый экспорт '{}' с другим списком треков, он не продолжается", previous.name));
+            }
+        }
+        let mut manifest = ExportManifest {
+            manifest_path:  output_path.join(MANIFEST_FILE_NAME),
+            name:           name.to_string(),
+            started:        unix_time_now(),
+            complete:       false,
+            entries:        planned.iter().map(|(source, dest)| ManifestEntry {
+                source:         source.to_string(),
+                dest:           dest.to_path_buf(),
+                size:           std::fs::metadata(source).map_or(0, |metadata| metadata.len()),
+                status:         EntryStatus::Pending,
+                existed_before: dest.symlink_metadata().is_ok(),
+                sha256:         None,
+            }).collect(),
+            playlist_files: Vec::new(),
+            last_save:      Instant::now(),
+        };
+        manifest.save()?;
+        Ok(manifest)
+    }
+
+    pub fn is_done (&self, i: usize) -> bool {
+        EntryStatus::Done == self.entries[i].status
+    }
+
+    pub fn set_status (&mut self, i: usize, status: EntryStatus) -> MyResult<()> {
+        self.entries[i].status = status;
+        if SAVE_INTERVAL <= self.last_save.elapsed() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_checksum (&mut self, i: usize, sha256: String) {
+        self.entries[i].sha256 = Some(sha256);
+    }
+
+    // Вызывается до записи файла плейлиста, чтобы знать, был ли он раньше
+    pub fn add_playlist_file (&mut self, path: &Path) -> MyResult<()> {
+        if self.playlist_files.iter().any(|playlist_file| playlist_file.path == path) {
+            return Ok(());
+        }
+        self.playlist_files.push(ManifestFile {
+            path:           path.to_path_buf(),
+            existed_before: path.symlink_metadata().is_ok(),
+        });
+        self.save()
+    }
+
+    pub fn finish (&mut self) -> MyResult<()> {
+        self.complete = true;
+        self.save()
+    }
+
+    // Запись через временный файл, чтобы обрыв не оставил полманифеста
+    fn save (&mut self) -> MyResult<()> {
+        let mut root = serde_json::Map::new();
+        root.insert("name".into(), self.name.clone().into());
+        root.insert("started".into(), self.started.into());
+        root.insert("complete".into(), self.complete.into());
+        root.insert("entries".into(), serde_json::Value::Array(
+            self.entries.iter().map(|entry| {
+                let mut obj = serde_json::json!({
+                    "source":         entry.source,
+                    "dest":           entry.dest.to_string_lossy(),
+                    "size":           entry.size,
+                    "status":         entry.status.key(),
+                    "existed_before": entry.existed_before,
+                });
+                if let Some(sha256) = &entry.sha256 {
+                    obj["sha256"] = sha256.clone().into();
+                }
+                obj
+            }).collect()
+        ));
+        root.insert("playlist_files".into(), serde_json::Value::Array(
+            self.playlist_files.iter().map(|playlist_file| serde_json::json!({
+                "path":           playlist_file.path.to_string_lossy(),
+                "existed_before": playlist_file.existed_before,
+            })).collect()
+        ));
+        let content = serde_json::to_string_pretty(&serde_json::Value::Object(root)).unwrap();
+        write_atomically(&self.manifest_path, content.as_bytes())
+            .map_err(|err| format!("не удалось записать журнал экспорта '{}': {}", self.manifest_path.to_string_lossy(), err))?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+}
+
+// Временное имя рядом с файлом: переименование в пределах папки атомарно
+pub fn part_path (path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().map_or(std::ffi::OsString::from("_"), |file_name| file_name.to_os_string());
+    file_name.push(".audials-part");
+    path.with_file_name(file_name)
+}
+
+pub fn write_atomically (path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let temp_path = part_path(path);
+    std::fs::write(&temp_path, content)?;
+    if let Err(err) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Откат последнего экспорта в папку: удаляются файлы, которых не было до
+// него, и сам манифест. Файлы, которые экспорт заменил, остаются - прежнего
+// содержимого уже нет
+pub fn rollback (output_path: &Path) -> Vec<String> {
+    let mut log = Vec::new();
+    let manifest = match ExportManifest::load(output_path) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => {
+            log.push(format!("# Ошибка: в '{}' нет журнала экспорта ({}), откатывать нечего", output_path.to_string_lossy(), MANIFEST_FILE_NAME));
+            return log;
+        },
+        Err(err) => {
+            log.push(format!("# Ошибка: {}", err));
+            return log;
+        },
+    };
+    let mut removed = 0;
+    let mut kept = 0;
+    let mut has_errors = false;
+    let created_paths = manifest.entries.iter()
+        .map(|entry| (&entry.dest, entry.existed_before))
+        .chain(manifest.playlist_files.iter().map(|playlist_file| (&playlist_file.path, playlist_file.existed_before)));
+    for (path, existed_before) in created_paths {
+        let _ = std::fs::remove_file(part_path(path));
+        if existed_before {
+            if path.symlink_metadata().is_ok() {
+                log.push(format!("# Замечание: '{}' был в папке до экспорта и заменён им, оставлен", path.to_string_lossy()));
+                kept += 1;
+            }
+            continue;
+        }
+        match std::fs::remove_file(path) {
+            Ok(_) => {
+                log.push(format!("Удалён {}", path.to_string_lossy()));
+                removed += 1;
+            },
+            Err(err) if std::io::ErrorKind::NotFound == err.kind() => (),
+            Err(err) => {
+                log.push(format!("# Ошибка: не удалось удалить '{}': {}", path.to_string_lossy(), err));
+                has_errors = true;
+            },
+        }
+    }
+    // пустые папки, созданные экспортом (общая папка треков)
+    let mut dirs: Vec<&Path> = manifest.entries.iter().filter_map(|entry| entry.dest.parent()).filter(|dir| *dir != output_path).collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        let _ = std::fs::remove_dir(dir);
+    }
+    if has_errors {
+        log.push("# Замечание: журнал экспорта оставлен, откат можно повторить".into());
+    }
+    else if let Err(err) = std::fs::remove_file(&manifest.manifest_path) {
+        log.push(format!("# Ошибка: не удалось удалить журнал экспорта '{}': {}", manifest.manifest_path.to_string_lossy(), err));
+    }
+    log.push(format!("Откат экспорта '{}': удалено файлов {}, оставлено заменённых {}", manifest.name, removed, kept));
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir (name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audials-manifest-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("library")).unwrap();
+        std::fs::create_dir_all(dir.join("output")).unwrap();
+        dir
+    }
+
+    // два исходных файла и план их переноса в output/Music
+    fn plan (dir: &Path) -> Vec<(String, PathBuf)> {
+        ["a.mp3", "b.mp3"].iter().map(|file_name| {
+            let source = dir.join("library").join(file_name);
+            std::fs::write(&source, file_name.repeat(100)).unwrap();
+            (source.to_string_lossy().into_owned(), dir.join("output").join("Music").join(file_name))
+        }).collect()
+    }
+
+    fn begin (dir: &Path, planned: &[(String, PathBuf)], log: &mut Vec<String>) -> ExportManifest {
+        let planned: Vec<(&str, &Path)> = planned.iter().map(|(source, dest)| (source.as_str(), dest.as_path())).collect();
+        ExportManifest::begin(&dir.join("output"), "Road Trip", &planned, log).unwrap()
+    }
+
+    #[test]
+    fn resume_drops_leftover_part_file_and_keeps_finished_copy () {
+        let dir = test_dir("resume");
+        let planned = plan(&dir);
+        begin(&dir, &planned, &mut Vec::new());
+        // a перенесён, но в манифест не попал; b оборван на середине
+        std::fs::create_dir_all(dir.join("output").join("Music")).unwrap();
+        std::fs::copy(&planned[0].0, &planned[0].1).unwrap();
+        std::fs::write(part_path(&planned[1].1), b"b.mp").unwrap();
+        let mut log = Vec::new();
+        let manifest = begin(&dir, &planned, &mut log);
+        assert!(log[0].starts_with("Продолжение прерванного экспорта 'Road Trip': уже перенесено 1 из 2"));
+        assert_eq!(EntryStatus::Done, manifest.entries[0].status);
+        assert_eq!(EntryStatus::Pending, manifest.entries[1].status);
+        assert!(!part_path(&planned[1].1).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_entry_is_retried () {
+        let dir = test_dir("failed");
+        let planned = plan(&dir);
+        let mut manifest = begin(&dir, &planned, &mut Vec::new());
+        // копия нужного размера, но не прошла проверку суммы
+        std::fs::create_dir_all(dir.join("output").join("Music")).unwrap();
+        std::fs::copy(&planned[0].0, &planned[0].1).unwrap();
+        manifest.set_status(0, EntryStatus::Failed).unwrap();
+        manifest.save().unwrap();
+        let manifest = begin(&dir, &planned, &mut Vec::new());
+        assert_eq!(EntryStatus::Pending, manifest.entries[0].status);
+        assert!(!manifest.is_done(0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn other_plan_starts_new_manifest () {
+        let dir = test_dir("other");
+        let planned = plan(&dir);
+        begin(&dir, &planned, &mut Vec::new());
+        let mut log = Vec::new();
+        let manifest = begin(&dir, &planned[..1], &mut log);
+        assert_eq!(1, manifest.entries.len());
+        assert!(log[0].contains("с другим списком треков"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rollback_keeps_files_that_existed_before () {
+        let dir = test_dir("rollback");
+        let output = dir.join("output");
+        let planned = plan(&dir);
+        std::fs::create_dir_all(output.join("Music")).unwrap();
+        std::fs::write(&planned[0].1, b"old").unwrap();
+        let mut manifest = begin(&dir, &planned, &mut Vec::new());
+        assert!(manifest.entries[0].existed_before);
+        assert!(!manifest.entries[1].existed_before);
+        for (i, (source, dest)) in planned.iter().enumerate() {
+            std::fs::copy(source, dest).unwrap();
+            manifest.set_status(i, EntryStatus::Done).unwrap();
+        }
+        let playlist_path = output.join("Road Trip.m3u8");
+        manifest.add_playlist_file(&playlist_path).unwrap();
+        std::fs::write(&playlist_path, "#EXTM3U\n").unwrap();
+        manifest.finish().unwrap();
+
+        let log = rollback(&output);
+        assert!(planned[0].1.exists());
+        assert!(!planned[1].1.exists());
+        assert!(!playlist_path.exists());
+        assert!(!output.join(MANIFEST_FILE_NAME).exists());
+        assert!(log.iter().any(|line| line.starts_with("# Замечание") && line.contains("a.mp3")));
+        assert_eq!(Some(&"Откат экспорта 'Road Trip': удалено файлов 2, оставлено заменённых 1".to_string()), log.last());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
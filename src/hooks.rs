@@ -0,0 +1,169 @@
+// Команда пользователя после экспорта каждого плейлиста: sync карты, пересканирование
+// MPD, уведомление и т.п. Выполняется через оболочку (sh -c, в windows cmd /C)
+// в папке результата, сведения об экспорте передаются переменными окружения:
+//
+//   AUDIALS_PLAYLIST_NAME   имя плейлиста
+//   AUDIALS_OUTPUT_DIR      папка результата
+//   AUDIALS_PLAYLIST_FILE   записанный файл плейлиста (при экспорте в архив - сам
+//                           архив), пусто - не записан
+//   AUDIALS_EXPORTED        сколько треков перенесено
+//   AUDIALS_FAILED          сколько не удалось
+//
+// Вывод команды и код завершения попадают в лог. В unix команда запускается в
+// своей группе процессов, и по тайм-ауту останавливается вся группа - вместе с
+// тем, что запустила оболочка
+
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+// зависшая команда не должна навсегда остановить экспорт; окно при этом не
+// замирает - в нём экспорт вместе с командой идёт в отдельном потоке
+const HOOK_TIMEOUT: Duration = Duration::from_secs(600);
+// сколько ждать конца вывода после завершения команды: запущенный ею фоновый
+// процесс может держать вывод открытым сколько угодно
+const OUTPUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct HookContext<'a> {
+    pub playlist_name: &'a str,
+    pub output_path:   &'a Path,
+    pub playlist_path: Option<&'a Path>,
+    pub exported:      usize,
+    pub failed:        usize,
+}
+
+fn shell_command (command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    }
+    else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+#[cfg(unix)]
+fn set_own_process_group (command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    unsafe {
+        command.pre_exec(|| {
+            if 0 != libc::setpgid(0, 0) {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+#[cfg(not(unix))]
+fn set_own_process_group (_command: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_process_group (child: &mut std::process::Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+#[cfg(not(unix))]
+fn kill_process_group (child: &mut std::process::Child) {
+    let _ = child.kill();
+}
+
+// Вывод читается в отдельном потоке и приходит кусками по мере чтения;
+// канал закрывается, когда вывод закрыт
+fn read_pipe<R: Read + Send + 'static> (pipe: Option<R>) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut pipe = match pipe {
+            Some(pipe) => pipe,
+            None => return,
+        };
+        let mut buffer = vec![0u8; 1 << 12];
+        loop {
+            match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => if sender.send(buffer[..read].to_vec()).is_err() {
+                    break;
+                },
+                Err(err) if std::io::ErrorKind::Interrupted == err.kind() => continue,
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+// Всё, что пришло до закрытия вывода или до deadline; false - не дочитан
+fn collect_output (receiver: &Receiver<Vec<u8>>, deadline: Instant) -> (String, bool) {
+    let mut output = Vec::new();
+    let complete = loop {
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(chunk) => output.extend(chunk),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break true,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break false,
+        }
+    };
+    (String::from_utf8_lossy(&output).into_owned(), complete)
+}
+
+pub fn run_post_export_hook (command: &str, context: &HookContext) -> Vec<String> {
+    let mut log = Vec::new();
+    log.push(format!("Команда после экспорта '{}': {}", context.playlist_name, command));
+    let mut shell = shell_command(command);
+    set_own_process_group(&mut shell);
+    let mut child = match shell
+        .current_dir(context.output_path)
+        .env("AUDIALS_PLAYLIST_NAME", context.playlist_name)
+        .env("AUDIALS_OUTPUT_DIR", context.output_path)
+        .env("AUDIALS_PLAYLIST_FILE", context.playlist_path.unwrap_or(Path::new("")))
+        .env("AUDIALS_EXPORTED", context.exported.to_string())
+        .env("AUDIALS_FAILED", context.failed.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            log.push(format!("# Ошибка: не удалось запустить команду после экспорта: {}", err));
+            return log;
+        },
+    };
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) if HOOK_TIMEOUT <= started.elapsed() => {
+                kill_process_group(&mut child);
+                let _ = child.wait();
+                break Err(format!("не завершилась за {} с и остановлена", HOOK_TIMEOUT.as_secs()));
+            },
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(err) => break Err(err.to_string()),
+        }
+    };
+    let output_deadline = Instant::now() + OUTPUT_TIMEOUT;
+    for (stream, receiver) in [("stdout", stdout), ("stderr", stderr)] {
+        let (output, complete) = collect_output(&receiver, output_deadline);
+        log.extend(output.lines().map(|line| format!("[{}] {}", stream, line)));
+        // поток чтения остаётся ждать и закончится сам, когда вывод закроют
+        if !complete {
+            log.push(format!("# Замечание: {} команды после экспорта не дочитан - его держит открытым запущенный ею процесс", stream));
+        }
+    }
+    match status {
+        Ok(status) if status.success() => log.push("Команда после экспорта завершилась успешно".into()),
+        Ok(status) => log.push(match status.code() {
+            Some(code) => format!("# Ошибка: команда после экспорта завершилась с кодом {}", code),
+            None => "# Ошибка: команда после экспорта прервана сигналом".to_string(),
+        }),
+        Err(err) => log.push(format!("# Ошибка: команда после экспорта {}", err)),
+    }
+    log
+}
@@ -0,0 +1,412 @@
+// Экспорт в библиотеки других программ: в отличие от файла плейлиста здесь
+// в один файл пишутся сразу несколько плейлистов вместе с общей коллекцией
+// треков, а треки остаются там, где лежат в библиотеке
+
+use crate::{AudioEntry, format_date};
+use crate::playlist_formats::{escape_xml, file_url};
+
+// Плейлист с уже найденными в библиотеке треками
+pub struct ResolvedPlaylist {
+    pub name:  String,
+    pub id:    String,
+    pub audio: Vec<AudioEntry>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LibraryFormat {
+    ItunesXml,
+    RekordboxXml,
+    TraktorNml,
+}
+impl LibraryFormat {
+    pub const ALL: [LibraryFormat; 3] = [
+        LibraryFormat::ItunesXml, LibraryFormat::RekordboxXml, LibraryFormat::TraktorNml,
+    ];
+    pub fn default_file_name (self) -> &'static str {
+        match self {
+            LibraryFormat::ItunesXml    => "Library.xml",
+            LibraryFormat::RekordboxXml => "rekordbox.xml",
+            LibraryFormat::TraktorNml   => "collection.nml",
+        }
+    }
+    pub fn extension (self) -> &'static str {
+        match self {
+            LibraryFormat::ItunesXml    => "xml",
+            LibraryFormat::RekordboxXml => "xml",
+            LibraryFormat::TraktorNml   => "nml",
+        }
+    }
+    pub fn writer (self) -> &'static dyn LibraryWriter {
+        match self {
+            LibraryFormat::ItunesXml    => &ItunesXmlWriter,
+            LibraryFormat::RekordboxXml => &RekordboxXmlWriter,
+            LibraryFormat::TraktorNml   => &TraktorNmlWriter,
+        }
+    }
+}
+impl std::fmt::Display for LibraryFormat {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LibraryFormat::ItunesXml    => "iTunes/Music Library.xml",
+            LibraryFormat::RekordboxXml => "Rekordbox XML",
+            LibraryFormat::TraktorNml   => "Traktor NML",
+        })
+    }
+}
+
+pub trait LibraryWriter {
+    fn write (&self, playlists: &[ResolvedPlaylist]) -> String;
+}
+
+// Треки всех плейлистов без повторов, в порядке первого появления
+pub fn unique_tracks (playlists: &[ResolvedPlaylist]) -> Vec<&AudioEntry> {
+    let mut seen = std::collections::HashSet::new();
+    playlists.iter()
+        .flat_map(|playlist| playlist.audio.iter())
+        .filter(|audio| seen.insert(audio.id))
+        .collect()
+}
+
+// Стабильный 64-битный хеш (FNV-1a), чтобы идентификаторы в экспорте не
+// менялись от запуска к запуску
+pub fn stable_hash (text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn plist_date (unix_secs: u64) -> String {
+    format!("{}T{:02}:{:02}:{:02}Z", format_date(unix_secs), unix_secs % 86400 / 3600, unix_secs % 3600 / 60, unix_secs % 60)
+}
+
+struct ItunesXmlWriter;
+impl ItunesXmlWriter {
+    fn push_string (result: &mut String, indent: &str, key: &str, value: &str) {
+        result.push_str(&format!("{}<key>{}</key><string>{}</string>\n", indent, key, escape_xml(value)));
+    }
+    fn push_integer (result: &mut String, indent: &str, key: &str, value: u64) {
+        result.push_str(&format!("{}<key>{}</key><integer>{}</integer>\n", indent, key, value));
+    }
+}
+impl LibraryWriter for ItunesXmlWriter {
+    fn write (&self, playlists: &[ResolvedPlaylist]) -> String {
+        let tracks = unique_tracks(playlists);
+        let mut result = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE plist PUBLIC \"-//Apple Computer//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+            "<plist version=\"1.0\">\n",
+            "<dict>\n",
+        ));
+        ItunesXmlWriter::push_integer(&mut result, "\t", "Major Version", 1);
+        ItunesXmlWriter::push_integer(&mut result, "\t", "Minor Version", 1);
+        ItunesXmlWriter::push_string(&mut result, "\t", "Application Version", "audials-playlist-exporter");
+        ItunesXmlWriter::push_string(&mut result, "\t", "Library Persistent ID", &format!("{:016X}", stable_hash("library")));
+
+        result.push_str("\t<key>Tracks</key>\n\t<dict>\n");
+        for audio in &tracks {
+            let indent = "\t\t\t";
+            result.push_str(&format!("\t\t<key>{}</key>\n\t\t<dict>\n", audio.id));
+            ItunesXmlWriter::push_integer(&mut result, indent, "Track ID", audio.id as u64);
+            ItunesXmlWriter::push_string(&mut result, indent, "Name", &audio.title);
+            if !audio.artist.is_empty() {
+                ItunesXmlWriter::push_string(&mut result, indent, "Artist", &audio.artist);
+            }
+            if let Some(album_artist) = &audio.metadata.album_artist {
+                ItunesXmlWriter::push_string(&mut result, indent, "Album Artist", album_artist);
+            }
+            if let Some(album) = &audio.metadata.album {
+                ItunesXmlWriter::push_string(&mut result, indent, "Album", album);
+            }
+            if let Some(genre) = &audio.metadata.genre {
+                ItunesXmlWriter::push_string(&mut result, indent, "Genre", genre);
+            }
+            if let Some(file_size) = audio.metadata.file_size {
+                ItunesXmlWriter::push_integer(&mut result, indent, "Size", file_size);
+            }
+            if let Some(duration_secs) = audio.metadata.duration_secs {
+                ItunesXmlWriter::push_integer(&mut result, indent, "Total Time", 1000 * duration_secs);
+            }
+            if let Some(disc_number) = audio.metadata.disc_number {
+                ItunesXmlWriter::push_integer(&mut result, indent, "Disc Number", disc_number);
+            }
+            if let Some(track_number) = audio.metadata.track_number {
+                ItunesXmlWriter::push_integer(&mut result, indent, "Track Number", track_number);
+            }
+            if let Some(year) = audio.metadata.year {
+                ItunesXmlWriter::push_integer(&mut result, indent, "Year", year);
+            }
+            if let Some(bitrate_kbps) = audio.metadata.bitrate_kbps {
+                ItunesXmlWriter::push_integer(&mut result, indent, "Bit Rate", bitrate_kbps);
+            }
+            if let Some(date_added) = audio.metadata.date_added {
+                result.push_str(&format!("{}<key>Date Added</key><date>{}</date>\n", indent, plist_date(date_added)));
+            }
+            ItunesXmlWriter::push_string(&mut result, indent, "Persistent ID", &format!("{:016X}", stable_hash(&format!("track:{}", audio.id))));
+            ItunesXmlWriter::push_string(&mut result, indent, "Track Type", "File");
+            ItunesXmlWriter::push_string(&mut result, indent, "Location", &file_url(std::path::Path::new(&audio.path)));
+            result.push_str("\t\t</dict>\n");
+        }
+        result.push_str("\t</dict>\n");
+
+        // id плейлистов не должны совпадать с id треков
+        let first_playlist_id = 1 + tracks.iter().map(|audio| audio.id).max().unwrap_or(0) as u64;
+        result.push_str("\t<key>Playlists</key>\n\t<array>\n");
+        for (i, playlist) in playlists.iter().enumerate() {
+            let indent = "\t\t\t";
+            result.push_str("\t\t<dict>\n");
+            ItunesXmlWriter::push_string(&mut result, indent, "Name", &playlist.name);
+            ItunesXmlWriter::push_integer(&mut result, indent, "Playlist ID", first_playlist_id + i as u64);
+            ItunesXmlWriter::push_string(&mut result, indent, "Playlist Persistent ID", &format!("{:016X}", stable_hash(&format!("playlist:{}", playlist.id))));
+            result.push_str(&format!("{}<key>All Items</key><true/>\n", indent));
+            result.push_str(&format!("{}<key>Playlist Items</key>\n{}<array>\n", indent, indent));
+            for audio in &playlist.audio {
+                result.push_str(&format!("{}\t<dict><key>Track ID</key><integer>{}</integer></dict>\n", indent, audio.id));
+            }
+            result.push_str(&format!("{}</array>\n", indent));
+            result.push_str("\t\t</dict>\n");
+        }
+        result.push_str("\t</array>\n</dict>\n</plist>\n");
+        result
+    }
+}
+
+struct RekordboxXmlWriter;
+impl RekordboxXmlWriter {
+    // Rekordbox ждёт file://localhost/ и путь с %-кодированием
+    fn location (audio: &AudioEntry) -> String {
+        let url = file_url(std::path::Path::new(&audio.path));
+        format!("file://localhost{}", url.strip_prefix("file://").unwrap_or(&url))
+    }
+    fn kind (audio: &AudioEntry) -> String {
+        let extension = std::path::Path::new(&audio.path).extension()
+            .map_or(String::new(), |extension| extension.to_string_lossy().to_uppercase());
+        format!("{} File", extension)
+    }
+}
+impl LibraryWriter for RekordboxXmlWriter {
+    fn write (&self, playlists: &[ResolvedPlaylist]) -> String {
+        let tracks = unique_tracks(playlists);
+        let mut result = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<DJ_PLAYLISTS Version=\"1.0.0\">\n  <PRODUCT Name=\"audials-playlist-exporter\" Version=\"{}\" Company=\"\"/>\n  <COLLECTION Entries=\"{}\">\n",
+            env!("CARGO_PKG_VERSION"), tracks.len()
+        );
+        for audio in &tracks {
+            let mut attributes = vec![
+                ("TrackID", audio.id.to_string()),
+                ("Name", audio.title.clone()),
+                ("Artist", audio.artist.clone()),
+            ];
+            if let Some(album) = &audio.metadata.album {
+                attributes.push(("Album", album.clone()));
+            }
+            if let Some(genre) = &audio.metadata.genre {
+                attributes.push(("Genre", genre.clone()));
+            }
+            attributes.push(("Kind", RekordboxXmlWriter::kind(audio)));
+            if let Some(file_size) = audio.metadata.file_size {
+                attributes.push(("Size", file_size.to_string()));
+            }
+            if let Some(duration_secs) = audio.metadata.duration_secs {
+                attributes.push(("TotalTime", duration_secs.to_string()));
+            }
+            if let Some(year) = audio.metadata.year {
+                attributes.push(("Year", year.to_string()));
+            }
+            if let Some(bitrate_kbps) = audio.metadata.bitrate_kbps {
+                attributes.push(("BitRate", bitrate_kbps.to_string()));
+            }
+            if let Some(track_number) = audio.metadata.track_number {
+                attributes.push(("TrackNumber", track_number.to_string()));
+            }
+            if let Some(disc_number) = audio.metadata.disc_number {
+                attributes.push(("DiscNumber", disc_number.to_string()));
+            }
+            if let Some(date_added) = audio.metadata.date_added {
+                attributes.push(("DateAdded", format_date(date_added)));
+            }
+            attributes.push(("Location", RekordboxXmlWriter::location(audio)));
+            result.push_str("    <TRACK");
+            for (name, value) in attributes {
+                result.push_str(&format!(" {}=\"{}\"", name, escape_xml(&value)));
+            }
+            result.push_str("/>\n");
+        }
+        result.push_str(&format!("  </COLLECTION>\n  <PLAYLISTS>\n    <NODE Type=\"0\" Name=\"ROOT\" Count=\"{}\">\n", playlists.len()));
+        for playlist in playlists {
+            result.push_str(&format!(
+                "      <NODE Name=\"{}\" Type=\"1\" KeyType=\"0\" Entries=\"{}\">\n",
+                escape_xml(&playlist.name), playlist.audio.len()
+            ));
+            for audio in &playlist.audio {
+                result.push_str(&format!("        <TRACK Key=\"{}\"/>\n", audio.id));
+            }
+            result.push_str("      </NODE>\n");
+        }
+        result.push_str("    </NODE>\n  </PLAYLISTS>\n</DJ_PLAYLISTS>\n");
+        result
+    }
+}
+
+struct TraktorNmlWriter;
+impl TraktorNmlWriter {
+    // Traktor хранит путь как том, папки через "/:" и имя файла:
+    // C:\Music\a.mp3 -> ("C:", "/:Music/:", "a.mp3"), ключ трека - их склейка
+    fn location (audio: &AudioEntry) -> (String, String, String) {
+        let path = std::path::Path::new(&audio.path);
+        let mut volume = String::new();
+        let mut dir = String::from("/:");
+        if let Some(parent) = path.parent() {
+            for component in parent.components() {
+                match component {
+                    std::path::Component::Prefix(prefix) => volume = prefix.as_os_str().to_string_lossy().into_owned(),
+                    std::path::Component::Normal(name) => dir.push_str(&format!("{}/:", name.to_string_lossy())),
+                    _ => (),
+                }
+            }
+        }
+        let file = path.file_name().map_or(String::new(), |file| file.to_string_lossy().into_owned());
+        (volume, dir, file)
+    }
+}
+impl LibraryWriter for TraktorNmlWriter {
+    fn write (&self, playlists: &[ResolvedPlaylist]) -> String {
+        let tracks = unique_tracks(playlists);
+        let mut result = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\" ?>\n<NML VERSION=\"19\">\n<HEAD COMPANY=\"www.native-instruments.com\" PROGRAM=\"Traktor\"></HEAD>\n<COLLECTION ENTRIES=\"{}\">\n",
+            tracks.len()
+        );
+        let mut keys = std::collections::HashMap::new();
+        for audio in &tracks {
+            let (volume, dir, file) = TraktorNmlWriter::location(audio);
+            keys.insert(audio.id, format!("{}{}{}", volume, dir, file));
+            result.push_str(&format!("<ENTRY TITLE=\"{}\" ARTIST=\"{}\">\n", escape_xml(&audio.title), escape_xml(&audio.artist)));
+            result.push_str(&format!(
+                "<LOCATION DIR=\"{}\" FILE=\"{}\" VOLUME=\"{}\" VOLUMEID=\"{}\"></LOCATION>\n",
+                escape_xml(&dir), escape_xml(&file), escape_xml(&volume), escape_xml(&volume)
+            ));
+            if audio.metadata.album.is_some() || audio.metadata.track_number.is_some() {
+                result.push_str("<ALBUM");
+                if let Some(track_number) = audio.metadata.track_number {
+                    result.push_str(&format!(" TRACK=\"{}\"", track_number));
+                }
+                if let Some(album) = &audio.metadata.album {
+                    result.push_str(&format!(" TITLE=\"{}\"", escape_xml(album)));
+                }
+                result.push_str("></ALBUM>\n");
+            }
+            result.push_str("<INFO");
+            if let Some(bitrate_kbps) = audio.metadata.bitrate_kbps {
+                result.push_str(&format!(" BITRATE=\"{}\"", 1000 * bitrate_kbps));
+            }
+            if let Some(genre) = &audio.metadata.genre {
+                result.push_str(&format!(" GENRE=\"{}\"", escape_xml(genre)));
+            }
+            if let Some(duration_secs) = audio.metadata.duration_secs {
+                result.push_str(&format!(" PLAYTIME=\"{}\"", duration_secs));
+            }
+            if let Some(file_size) = audio.metadata.file_size {
+                result.push_str(&format!(" FILESIZE=\"{}\"", file_size / 1024));
+            }
+            if let Some(year) = audio.metadata.year {
+                result.push_str(&format!(" RELEASE_DATE=\"{}/1/1\"", year));
+            }
+            if let Some(date_added) = audio.metadata.date_added {
+                result.push_str(&format!(" IMPORT_DATE=\"{}\"", format_date(date_added).replace('-', "/")));
+            }
+            result.push_str("></INFO>\n</ENTRY>\n");
+        }
+        result.push_str(&format!(
+            "</COLLECTION>\n<PLAYLISTS>\n<NODE TYPE=\"FOLDER\" NAME=\"$ROOT\"><SUBNODES COUNT=\"{}\">\n",
+            playlists.len()
+        ));
+        for playlist in playlists {
+            result.push_str(&format!(
+                "<NODE TYPE=\"PLAYLIST\" NAME=\"{}\"><PLAYLIST ENTRIES=\"{}\" TYPE=\"LIST\" UUID=\"{:016x}\">\n",
+                escape_xml(&playlist.name), playlist.audio.len(), stable_hash(&format!("playlist:{}", playlist.id))
+            ));
+            for audio in &playlist.audio {
+                result.push_str(&format!("<ENTRY><PRIMARYKEY TYPE=\"TRACK\" KEY=\"{}\"></PRIMARYKEY></ENTRY>\n", escape_xml(&keys[&audio.id])));
+            }
+            result.push_str("</PLAYLIST></NODE>\n");
+        }
+        result.push_str("</SUBNODES></NODE>\n</PLAYLISTS>\n</NML>\n");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio (id: crate::AudioEntryId, path: &str) -> AudioEntry {
+        AudioEntry {
+            id,
+            title:    format!("Трек {}", id),
+            artist:   "Artist".to_string(),
+            path:     path.to_string(),
+            metadata: crate::AudioMetadata::default(),
+        }
+    }
+
+    fn playlists () -> Vec<ResolvedPlaylist> {
+        vec![
+            ResolvedPlaylist {name: "A & B".to_string(), id: "pl-1".to_string(), audio: vec![audio(7, "/music/a.mp3"), audio(3, "/music/Ёлка & co.mp3")]},
+            ResolvedPlaylist {name: "Second".to_string(), id: "pl-2".to_string(), audio: vec![audio(3, "/music/Ёлка & co.mp3"), audio(9, "/music/Rock & Roll/b.flac")]},
+        ]
+    }
+
+    #[test]
+    fn itunes_tracks_are_keyed_by_id_and_playlist_items_keep_order () {
+        let xml = LibraryFormat::ItunesXml.writer().write(&playlists());
+        let track_keys: Vec<&str> = xml.lines()
+            .filter(|line| line.starts_with("\t\t<key>"))
+            .map(|line| line.trim_start_matches("\t\t<key>").trim_end_matches("</key>"))
+            .collect();
+        assert_eq!(track_keys, ["7", "3", "9"]);
+        assert!(xml.contains("\t\t<key>3</key>\n\t\t<dict>\n\t\t\t<key>Track ID</key><integer>3</integer>\n"));
+        assert!(xml.contains("<key>Location</key><string>file:///music/%D0%81%D0%BB%D0%BA%D0%B0%20%26%20co.mp3</string>"));
+        assert!(xml.contains("<key>Name</key><string>A &amp; B</string>"));
+        // id плейлистов идут после наибольшего id трека
+        assert!(xml.contains("<key>Playlist ID</key><integer>10</integer>"));
+        assert!(xml.contains("<key>Playlist ID</key><integer>11</integer>"));
+        let items: Vec<&str> = xml.lines()
+            .filter_map(|line| line.trim().strip_prefix("<dict><key>Track ID</key><integer>"))
+            .map(|rest| rest.trim_end_matches("</integer></dict>"))
+            .collect();
+        assert_eq!(items, ["7", "3", "3", "9"]);
+    }
+
+    #[test]
+    fn rekordbox_location_uses_file_localhost () {
+        let xml = LibraryFormat::RekordboxXml.writer().write(&playlists());
+        assert!(xml.contains("<COLLECTION Entries=\"3\">"));
+        assert!(xml.contains(" Location=\"file://localhost/music/%D0%81%D0%BB%D0%BA%D0%B0%20%26%20co.mp3\""));
+        assert!(xml.contains(" Location=\"file://localhost/music/Rock%20%26%20Roll/b.flac\""));
+        assert!(xml.contains(" Kind=\"FLAC File\""));
+        assert!(xml.contains("<NODE Name=\"A &amp; B\" Type=\"1\" KeyType=\"0\" Entries=\"2\">\n        <TRACK Key=\"7\"/>\n        <TRACK Key=\"3\"/>\n"));
+    }
+
+    #[test]
+    fn traktor_location_splits_dirs_with_slash_colon () {
+        let track = audio(9, "/music/Rock & Roll/b.flac");
+        assert_eq!(
+            TraktorNmlWriter::location(&track),
+            (String::new(), "/:music/:Rock & Roll/:".to_string(), "b.flac".to_string())
+        );
+        let xml = LibraryFormat::TraktorNml.writer().write(&playlists());
+        assert!(xml.contains("<LOCATION DIR=\"/:music/:Rock &amp; Roll/:\" FILE=\"b.flac\" VOLUME=\"\" VOLUMEID=\"\"></LOCATION>"));
+        assert!(xml.contains("<PRIMARYKEY TYPE=\"TRACK\" KEY=\"/:music/:Rock &amp; Roll/:b.flac\"></PRIMARYKEY>"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn traktor_volume_is_drive_letter () {
+        assert_eq!(
+            TraktorNmlWriter::location(&audio(1, "C:\\Music\\a.mp3")),
+            ("C:".to_string(), "/:Music/:".to_string(), "a.mp3".to_string())
+        );
+    }
+}
@@ -1,5 +1,30 @@
 use std::path::PathBuf;
 
+mod archive;
+mod background;
+mod checksums;
+mod cli;
+mod modb_schema;
+mod export;
+mod export_manifest;
+mod hooks;
+mod library_formats;
+mod modb_snapshot;
+mod player_libraries;
+mod playlist_formats;
+mod playlist_picker;
+mod playlist_template;
+mod settings;
+mod shared_pool;
+mod smart_playlist;
+mod space_estimate;
+mod sync;
+mod track_list;
+mod track_resolution;
+mod track_selection;
+mod track_table;
+mod volumes;
+
 use iced::{Application, Button, Row, Text, button, button::Style};
 
 //const MyFont: iced::Font = iced::Font::External {
@@ -8,8 +33,14 @@ use iced::{Application, Button, Row, Text, button, button::Style};
 //};
 
 const MY_BASE_FONT_SIZE: u16 = 32;
+// таблица треков мельче, иначе длинные пути не помещаются
+const MY_TABLE_FONT_SIZE: u16 = MY_BASE_FONT_SIZE / 2;
 
 fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
     let mut iced_settings = iced::Settings::default();
     iced_settings.default_font = Some(include_bytes!("../OpenSans-Regular.ttf"));
     iced_settings.antialiasing = true;
@@ -44,10 +75,26 @@ impl ToString for MyJsonEntryValue {
         self.file_path.to_string_lossy().into_owned()
     }
 }
-struct PathBufWrapper(PathBuf);
-impl ToString for PathBufWrapper {
-    fn to_string(&self) -> String {
-        self.0.to_string_lossy().into_owned()
+struct AudioDatabase {
+    file_path: PathBuf,
+    schema:    modb_schema::ModbSchema,
+    snapshot:  Option<modb_snapshot::ModbSnapshot>,
+}
+impl AudioDatabase {
+    // Путь, по которому делаются запросы: копия в режиме снимка или сама база
+    fn query_path (&self) -> &PathBuf {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.file_path(),
+            None => &self.file_path,
+        }
+    }
+}
+impl std::fmt::Display for AudioDatabase {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.snapshot {
+            Some(_) => write!(f, "{} (читается копия)", self.file_path.to_string_lossy()),
+            None => f.write_str(&self.file_path.to_string_lossy()),
+        }
     }
 }
 enum MyFileEntry<T: ToString> {
@@ -160,24 +207,90 @@ fn file_entry_from_json_file (filepath: PathBuf) -> MyFileEntry<MyJsonEntryValue
     }
 }
 
-fn file_entry_from_audio_sqlite_file (filepath: PathBuf) -> MyFileEntry<PathBufWrapper> {
+fn database_locked_error (filepath: &std::path::Path) -> MyError {
+    MyError{
+        full_error: format!(
+            "# Ошибка: база данных '{}' заблокирована и не освободилась за {} с - вероятно, запущен Audials. Закройте его или включите чтение копии базы",
+            filepath.to_string_lossy(), modb_snapshot::BUSY_TIMEOUT.as_secs()
+        ),
+        short_error: "База заблокирована",
+    }
+}
+
+fn file_entry_from_audio_sqlite_file (filepath: PathBuf, use_snapshot: bool) -> MyFileEntry<AudioDatabase> {
+    let snapshot = if use_snapshot {
+        match modb_snapshot::ModbSnapshot::create(&filepath) {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) => {
+                return MyFileEntry::InvalidWithError(MyError{
+                    full_error: format!("# Ошибка: не удалось сделать копию базы данных '{}': {}", filepath.to_string_lossy(), err),
+                    short_error: "Не удалось скопировать базу",
+                });
+            }
+        }
+    }
+    else {
+        None
+    };
     // https://stackoverflow.com/a/21146372
-    let connection = match rusqlite::Connection::open_with_flags(&filepath, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY) {
+    let connection = match modb_snapshot::open_read_only(snapshot.as_ref().map_or(&filepath, |snapshot| snapshot.file_path())) {
         Ok(c) => c,
-        Err(_) => {
+        Err(err) => {
+            if modb_snapshot::is_locked(&err) {
+                return MyFileEntry::InvalidWithError(database_locked_error(&filepath));
+            }
             return MyFileEntry::InvalidWithError(MyError{
                 full_error: format!("# Ошибка: не удалось открыть базы данных '{}'", filepath.to_string_lossy()),
                 short_error: "Не удалось открыть файл",
             });
         }
     };
-    if let Err(_) = connection.execute_batch("pragma schema_version;") {
+    if let Err(err) = connection.execute_batch("pragma schema_version;") {
+        if modb_snapshot::is_locked(&err) {
+            return MyFileEntry::InvalidWithError(database_locked_error(&filepath));
+        }
+        // База в режиме WAL на месте открывается только при возможности
+        // создать -shm, на диске только для чтения это не так
+        if snapshot.is_none() && modb_snapshot::sidecar_path(&filepath, "-wal").is_file() {
+            return MyFileEntry::InvalidWithError(MyError{
+                full_error: format!("# Ошибка: не удалось прочитать базу данных '{}' в режиме WAL (рядом есть -wal), включите чтение копии базы: {}", filepath.to_string_lossy(), err),
+                short_error: "База в режиме WAL",
+            });
+        }
         return MyFileEntry::InvalidWithError(MyError{
             full_error: format!("# Ошибка: нельзя распознать как базу данных SQLite файл '{}'", filepath.to_string_lossy()),
             short_error: "Не база данных",
         });
     }
-    MyFileEntry::Valid(PathBufWrapper(filepath))
+    match modb_schema::ModbSchema::detect(&connection) {
+        Ok(schema) => MyFileEntry::Valid(AudioDatabase {
+            file_path: filepath,
+            schema,
+            snapshot,
+        }),
+        Err(err) => MyFileEntry::InvalidWithError(MyError{
+            full_error: format!("# Ошибка: база данных '{}' не распознана как база музыки Audials: {}", filepath.to_string_lossy(), err),
+            short_error: "Неизвестная схема базы",
+        }),
+    }
+}
+
+// Папка с данными Audials называется по году версии (Audials_2015 и т.п.),
+// берётся самая новая, в которой есть база с музыкой
+fn find_audials_data_dir (audials_path: &std::path::Path) -> Option<String> {
+    let read_dir = audials_path.join("LocalAppDataFolder/RapidSolution").read_dir().ok()?;
+    let mut candidates: Vec<(bool, String)> = read_dir
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+        .filter(|name| name.starts_with("Audials_"))
+        .map(|name| {
+            let has_modb = audials_path.join("LocalAppDataFolder/RapidSolution").join(&name).join("MusicOrganizer/modb").is_file();
+            (has_modb, name)
+        })
+        .collect();
+    candidates.sort();
+    candidates.pop().map(|(_, name)| name)
 }
 
 fn pathbuf_from_pattern (base_dir: &PathBuf, pattern: &str) -> PathBuf {
@@ -211,7 +324,12 @@ struct DataFilesMenuData {
     is_opened:        bool,
     playlists:        MyFileEntry<MyJsonEntryValue>,
     playlist_entries: MyFileEntry<MyJsonEntryValue>,
-    audio_database:   MyFileEntry<PathBufWrapper>,
+    audio_database:   MyFileEntry<AudioDatabase>,
+    audials_data_dir: Option<String>,
+
+    // путь сохраняется отдельно, чтобы переоткрыть базу при смене режима
+    audio_database_path:   PathBuf,
+    use_database_snapshot: bool,
 
     is_valid_prev:    bool,
 }
@@ -226,13 +344,19 @@ impl DataFilesMenuData {
         }
     }
     fn init_auto (&mut self, base_menu_data: &BaseMenuData) {
-        self.playlists = file_entry_from_json_file(pathbuf_from_pattern(&base_menu_data.audilas_path, "LocalAppDataFolder/RapidSolution/Audials_2015/AudialsSync/*_playlists.txt"));
-        self.playlist_entries = file_entry_from_json_file(pathbuf_from_pattern(&base_menu_data.audilas_path, "LocalAppDataFolder/RapidSolution/Audials_2015/AudialsSync/*_playlistentries.txt"));
-        self.audio_database = file_entry_from_audio_sqlite_file({
+        self.audials_data_dir = find_audials_data_dir(&base_menu_data.audilas_path);
+        let data_dir = match &self.audials_data_dir {
+            Some(data_dir) => data_dir.as_str(),
+            None => "Audials_2015",
+        };
+        self.playlists = file_entry_from_json_file(pathbuf_from_pattern(&base_menu_data.audilas_path, format!("LocalAppDataFolder/RapidSolution/{}/AudialsSync/*_playlists.txt", data_dir).as_str()));
+        self.playlist_entries = file_entry_from_json_file(pathbuf_from_pattern(&base_menu_data.audilas_path, format!("LocalAppDataFolder/RapidSolution/{}/AudialsSync/*_playlistentries.txt", data_dir).as_str()));
+        self.audio_database_path = {
             let mut audio_path = base_menu_data.audilas_path.clone();
-            audio_path.push("LocalAppDataFolder/RapidSolution/Audials_2015/MusicOrganizer/modb");
+            audio_path.push(format!("LocalAppDataFolder/RapidSolution/{}/MusicOrganizer/modb", data_dir));
             audio_path
-        });
+        };
+        self.audio_database = file_entry_from_audio_sqlite_file(self.audio_database_path.clone(), self.use_database_snapshot);
     }
 }
 struct DataFilesMenuIcedStates {
@@ -254,6 +378,9 @@ impl DataFilesMenu {
                 playlists:        MyFileEntry::new(),
                 playlist_entries: MyFileEntry::new(),
                 audio_database:   MyFileEntry::new(),
+                audials_data_dir: None,
+                audio_database_path:   PathBuf::new(),
+                use_database_snapshot: false,
                 is_valid_prev:    false,
             },
             states: DataFilesMenuIcedStates {
@@ -265,9 +392,31 @@ impl DataFilesMenu {
         }
     }
     fn reset_me (&mut self) {
+        let use_database_snapshot = self.data.use_database_snapshot;
         *self = DataFilesMenu::new();
+        self.data.use_database_snapshot = use_database_snapshot;
     }
     fn update_if_acceptable (&mut self, message: &MyMessage) {
+        // копия базы снимается заново, если Audials с тех пор записал в базу;
+        // не удалось - остаётся прежняя, её возраст виден в логе проверки
+        if let MyMessage::SelectPlaylist(_) | MyMessage::TestPlaylist = message {
+            if let MyFileEntry::Valid(AudioDatabase {snapshot: Some(snapshot), ..}) = &self.data.audio_database {
+                if snapshot.is_stale() {
+                    let refreshed = file_entry_from_audio_sqlite_file(self.data.audio_database_path.clone(), true);
+                    if refreshed.is_valid() {
+                        self.data.audio_database = refreshed;
+                    }
+                }
+            }
+            return;
+        }
+        if let MyMessage::SetDatabaseSnapshot(use_database_snapshot) = message {
+            self.data.use_database_snapshot = *use_database_snapshot;
+            if !self.data.audio_database_path.as_os_str().is_empty() {
+                self.data.audio_database = file_entry_from_audio_sqlite_file(self.data.audio_database_path.clone(), self.data.use_database_snapshot);
+            }
+            return;
+        }
         if [
             MyMessage::SetPlaylistsPath, MyMessage::SetPlaylistEntriesPath, MyMessage::SetAudioDatabasePath
         ].contains(&message) {
@@ -280,7 +429,8 @@ impl DataFilesMenu {
                         self.data.playlist_entries = file_entry_from_json_file(new_file_path);
                     },
                     MyMessage::SetAudioDatabasePath => {
-                        self.data.audio_database = file_entry_from_audio_sqlite_file(new_file_path);
+                        self.data.audio_database_path = new_file_path.clone();
+                        self.data.audio_database = file_entry_from_audio_sqlite_file(new_file_path, self.data.use_database_snapshot);
                     },
                     _ => unreachable!(),
                 }
@@ -320,7 +470,12 @@ impl DataFilesMenu {
                             iced::Button::new(&mut states.btn_update_audio_database_path, gen_text("Обновить")).on_press(MyMessage::SetAudioDatabasePath)
                         )
                         .push(data.audio_database.to_iced_short_text())
-                );
+                )
+                .push(iced::Checkbox::new(
+                    data.use_database_snapshot,
+                    "Читать копию базы (если Audials запущен или база на диске только для чтения)",
+                    MyMessage::SetDatabaseSnapshot
+                ).text_size(MY_BASE_FONT_SIZE));
         }
         column.push(iced::Row::new().push(iced::Button::new(&mut states.btn_validate_or_open_close, gen_text(
                 if data.is_opened {
@@ -451,17 +606,191 @@ impl BaseMenu {
 //    }
 //}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct AudioEntry {
+    id: AudioEntryId,
     title: String,
     artist: String,
     path: String,
+    metadata: AudioMetadata,
+}
+// Дополнительные поля file_t, есть не в каждой базе и не у каждого файла
+#[derive(Debug, Clone, Default)]
+struct AudioMetadata {
+    album:         Option<String>,
+    album_artist:  Option<String>,
+    duration_secs: Option<u64>,
+    genre:         Option<String>,
+    year:          Option<u64>,
+    track_number:  Option<u64>,
+    disc_number:   Option<u64>,
+    bitrate_kbps:  Option<u64>,
+    file_size:     Option<u64>,
+    // unix-время добавления в библиотеку
+    date_added:    Option<u64>,
 }
+impl AudioMetadata {
+    fn set_from_db_column (&mut self, column: &str, value: rusqlite::types::Value) {
+        match column {
+            "ft_album"       => self.album = metadata_text(value),
+            "ft_albumartist" => self.album_artist = metadata_text(value),
+            "ft_duration"    => self.duration_secs = metadata_number(value),
+            "ft_genre"       => self.genre = metadata_text(value),
+            "ft_year"        => self.year = metadata_number(value),
+            "ft_tracknumber" => self.track_number = metadata_number(value),
+            "ft_discnumber"  => self.disc_number = metadata_number(value),
+            "ft_bitrate"     => self.bitrate_kbps = metadata_number(value),
+            "ft_size"        => self.file_size = metadata_number(value),
+            "ft_dateadded"   => self.date_added = metadata_number(value),
+            _ => (),
+        }
+    }
+    // Известные поля в виде (ключ, значение) - ключи используются форматами
+    // экспорта и шаблонами имён, для показа есть metadata_label
+    fn named_values (&self) -> Vec<(&'static str, String)> {
+        let mut result = Vec::new();
+        if let Some(album) = &self.album {
+            result.push(("album", album.clone()));
+        }
+        if let Some(album_artist) = &self.album_artist {
+            result.push(("album_artist", album_artist.clone()));
+        }
+        if let Some(duration_secs) = self.duration_secs {
+            result.push(("duration", format_duration(duration_secs)));
+        }
+        if let Some(genre) = &self.genre {
+            result.push(("genre", genre.clone()));
+        }
+        if let Some(year) = self.year {
+            result.push(("year", year.to_string()));
+        }
+        if let Some(track_number) = self.track_number {
+            result.push(("track_number", track_number.to_string()));
+        }
+        if let Some(disc_number) = self.disc_number {
+            result.push(("disc_number", disc_number.to_string()));
+        }
+        if let Some(bitrate_kbps) = self.bitrate_kbps {
+            result.push(("bitrate", bitrate_kbps.to_string()));
+        }
+        if let Some(file_size) = self.file_size {
+            result.push(("size", file_size.to_string()));
+        }
+        if let Some(date_added) = self.date_added {
+            result.push(("added", format_date(date_added)));
+        }
+        result
+    }
+}
+// Ключи named_values в постоянном порядке - для форматов с фиксированными колонками
+const METADATA_KEYS: [&str; 10] = [
+    "album", "album_artist", "duration", "genre", "year", "track_number", "disc_number", "bitrate", "size", "added",
+];
+fn metadata_label (key: &str) -> &'static str {
+    match key {
+        "album"        => "альбом",
+        "album_artist" => "исполнитель альбома",
+        "duration"     => "длительность",
+        "genre"        => "жанр",
+        "year"         => "год",
+        "track_number" => "трек",
+        "disc_number"  => "диск",
+        "bitrate"      => "битрейт, кбит/с",
+        "size"         => "размер, байт",
+        "added"        => "добавлен",
+        _              => "",
+    }
+}
+// Пустые строки и нули в базе означают "неизвестно"
+fn metadata_text (value: rusqlite::types::Value) -> Option<String> {
+    match value {
+        rusqlite::types::Value::Text(text) if !text.trim().is_empty() => Some(text),
+        rusqlite::types::Value::Integer(number) if 0 != number => Some(number.to_string()),
+        _ => None,
+    }
+}
+fn metadata_number (value: rusqlite::types::Value) -> Option<u64> {
+    match value {
+        rusqlite::types::Value::Integer(number) if 0 < number => Some(number as u64),
+        rusqlite::types::Value::Real(number) if 0.0 < number => Some(number as u64),
+        // номер трека встречается и в виде "3/12"
+        rusqlite::types::Value::Text(text) => text.split('/').next().unwrap().trim().parse().ok().filter(|number| 0 < *number),
+        _ => None,
+    }
+}
+// Дата по unix-времени и обратно, без часовых поясов: для фильтров вида
+// "добавлено в этом году" точности до суток хватает.
+// Алгоритм: http://howardhinnant.github.io/date_algorithms.html
+fn format_date (unix_secs: u64) -> String {
+    let days = (unix_secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_shifted = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_shifted + 2) / 5 + 1;
+    let month = if month_shifted < 10 {month_shifted + 3} else {month_shifted - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+// Принимает "ГГГГ", "ГГГГ-ММ" и "ГГГГ-ММ-ДД"
+fn parse_date (date: &str) -> Option<u64> {
+    let mut parts = date.trim().split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next().map_or(Some(1), |month| month.parse().ok())?;
+    let day: i64 = parts.next().map_or(Some(1), |day| day.parse().ok())?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    let shifted_year = if month <= 2 {year - 1} else {year};
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 {month - 3} else {month + 9}) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let unix_secs = ((era * 146097 + day_of_era - 719468) * 86400) as u64;
+    // 2026-02-31 алгоритм молча превратил бы в 3 марта: такой даты нет
+    if format_date(unix_secs) != format!("{:04}-{:02}-{:02}", year, month, day) {
+        return None;
+    }
+    Some(unix_secs)
+}
+fn format_duration (duration_secs: u64) -> String {
+    if 3600 <= duration_secs {
+        format!("{}:{:02}:{:02}", duration_secs / 3600, duration_secs % 3600 / 60, duration_secs % 60)
+    }
+    else {
+        format!("{}:{:02}", duration_secs / 60, duration_secs % 60)
+    }
+}
+#[derive(Clone)]
 struct PlaylistEntry {
     name: String,
     id: String,
+    // для умного плейлиста - его фильтр, треки берутся из file_t по нему
+    smart_expression: Option<String>,
+    // сколько вхождений в файле содержимого плейлистов; у умного - None
+    entry_count: Option<usize>,
+    // поля payload с датами и временем, как есть
+    timestamps: Vec<(String, String)>,
 }
 impl PlaylistEntry {
+    fn from_smart_preset (preset: &settings::SmartPlaylistPreset) -> PlaylistEntry {
+        PlaylistEntry {
+            name: preset.name.clone(),
+            id: format!("smart:{}", preset.name),
+            smart_expression: Some(preset.expression.clone()),
+            entry_count: None,
+            timestamps: Vec::new(),
+        }
+    }
+    // Имя в списке выбора: умные плейлисты помечены, чтобы не путать их с
+    // одноимёнными плейлистами Audials
+    fn display_name (&self) -> String {
+        match self.smart_expression {
+            Some(_) => format!("★ {}", self.name),
+            None => self.name.clone(),
+        }
+    }
     fn from_json_object (obj: &serde_json::Map<String, serde_json::Value>, playlist_error_log: &mut Vec<String>) -> Option<PlaylistEntry> {
         // Это какой-то бред: audials по полу payload указывает не на объект, а
         // на Строковое Представление объекта, то есть нужно лишний раз гонять
@@ -475,7 +804,10 @@ impl PlaylistEntry {
                                 //println!("name: {}, [{}]", name_string, name_string.bytes().map(|b|format!("{:02x} ", b)).collect::<String>());
                                 return Some(PlaylistEntry{
                                     name: name_string,
-                                    id: id_string
+                                    id: id_string,
+                                    smart_expression: None,
+                                    entry_count: None,
+                                    timestamps: playlist_picker::payload_timestamps(&payload_obj),
                                 });
                             }
                             else {
@@ -514,19 +846,118 @@ struct SelectionMenuData {
     is_validation_required: bool,
 
     playlists: Vec<PlaylistEntry>,
-    selected_playlist: Option<String>,
+    selected_playlist: Option<String>, // id плейлиста, имена могут повторяться
+    playlist_filter: String,
     playlists_error_log: Vec<String>, // ошибки в формировании списка плейлистов
     playlist_test_error_log: Vec<String>, // ошибки в формировании содержимого плейлиста и существования файлов
 
     audio_in_playlist: Vec<AudioEntry>,
+    // как искался файл каждого вхождения плейлиста, и найденного, и нет
+    track_resolutions: Vec<track_resolution::TrackResolution>,
+    // строки таблицы с раскрытыми подробностями поиска
+    expanded_tracks: std::collections::BTreeSet<usize>,
+    // размеры треков и место в папке результата для предпросмотра
+    space_estimate: Option<space_estimate::SpaceEstimate>,
+    // фильтр и сортировка таблицы треков
+    track_filter: String,
+    track_sort: track_table::TrackSort,
+    // пути треков этого плейлиста, которые не переносятся; хранятся в настройках
+    excluded_tracks: std::collections::BTreeSet<String>,
+    bulk_artist: Option<String>,
+    bulk_format: Option<String>,
     //audio_count: u32,
 
     output_path: PathBuf,
     is_exported: bool,
+    playlist_format: playlist_formats::PlaylistOutput,
+    // встроенные форматы и шаблоны из настроек - варианты для выбора
+    playlist_outputs: Vec<playlist_formats::PlaylistOutput>,
+    relative_locations: bool,
+    archive_format: archive::ArchiveFormat,
+    transfer_mode: export::TransferMode,
+    verify_checksums: bool,
+    copy_workers: usize,
+    // команда после экспорта, как введена; пустая - не запускать
+    post_export_hook_text: String,
+    volume_preset: volumes::VolumePreset,
+    // размер тома в мегабайтах для VolumePreset::Custom, как введён
+    volume_size_text: String,
+    sync_compare_content: bool,
+    // план синхронизации, показанный как пробный прогон и ждущий подтверждения
+    sync_plan: Option<sync::SyncPlan>,
+    library_format: library_formats::LibraryFormat,
+    player_library: player_libraries::PlayerLibrary,
+    track_list_format: track_list::TrackListFormat,
+    export_log: Vec<String>, // что скопировано и куда, ошибки копирования
+    // ход экспорта, который идёт в фоне; None - экспорт не идёт
+    export_progress: Option<std::sync::Arc<export::ExportProgress>>,
+
+    // редактор умного плейлиста
+    smart_name: String,
+    smart_expression: String,
+    smart_error: Option<String>,
 }
 impl SelectionMenuData {
-    fn init (&mut self, data_files_menu_data: &DataFilesMenuData) {
+    // Треки для экспорта - без исключённых в таблице
+    fn export_audio (&self) -> Vec<AudioEntry> {
+        track_selection::included_audio(&self.audio_in_playlist, &self.excluded_tracks)
+    }
+    fn refresh_space_estimate (&mut self) {
+        self.space_estimate = Some(space_estimate::estimate_playlist_space(&self.export_audio(), &self.output_path));
+    }
+    // Исключения сразу попадают в настройки, чтобы повторный экспорт их помнил
+    fn save_excluded_tracks (&mut self, settings: &mut settings::Settings) {
+        let playlist_id = self.find_selected_playlist().unwrap().id.clone();
+        settings.set_excluded_tracks(&playlist_id, &self.excluded_tracks);
+        if let Err(err) = settings.save() {
+            self.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+        }
+        self.sync_plan = None;
+        self.refresh_space_estimate();
+    }
+    fn post_export_hook (&self) -> Option<String> {
+        Some(self.post_export_hook_text.trim().to_string()).filter(|command| !command.is_empty())
+    }
+    // Экспорт в фоне с параметрами из окна: work получает их и возвращает
+    // лог, который по окончании допишется в export_log. Команда после
+    // экспорта сохраняется в настройки здесь, а не на каждое нажатие клавиши
+    fn start_export (&mut self, settings: &mut settings::Settings, work: impl FnOnce(export::ExportOptions) -> Vec<String> + Send + 'static) -> iced::Command<MyMessage> {
+        let post_export_hook = self.post_export_hook().unwrap_or_default();
+        if settings.post_export_hook != post_export_hook {
+            settings.post_export_hook = post_export_hook;
+            if let Err(err) = settings.save() {
+                self.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+            }
+        }
+        let progress = std::sync::Arc::new(export::ExportProgress::default());
+        self.export_progress = Some(progress.clone());
+        self.is_exported = false;
+        let options = export::ExportOptions {
+            format:             self.playlist_format.clone(),
+            relative_locations: self.relative_locations,
+            transfer_mode:      self.transfer_mode,
+            verify_checksums:   self.verify_checksums,
+            workers:            self.copy_workers,
+            post_export_hook:   self.post_export_hook(),
+            progress,
+        };
+        iced::Command::batch(vec![
+            background::run(move || work(options), MyMessage::ExportFinished),
+            background::tick(MyMessage::ExportTick),
+        ])
+    }
+    fn init (&mut self, data_files_menu_data: &DataFilesMenuData, settings: &settings::Settings) {
         self.playlists_error_log.clear();
+        self.playlist_format = settings.playlist_format.clone();
+        self.refresh_playlist_outputs(settings);
+        self.relative_locations = settings.relative_locations;
+        self.archive_format = settings.archive_format;
+        self.transfer_mode = settings.transfer_mode;
+        self.verify_checksums = settings.verify_checksums;
+        self.copy_workers = settings.copy_workers;
+        self.post_export_hook_text = settings.post_export_hook.clone();
+        self.volume_preset = settings.volume_preset;
+        self.volume_size_text = settings.volume_custom_mb.map_or(String::new(), |megabytes| megabytes.to_string());
         for arr_entry in match &data_files_menu_data.playlists {
             MyFileEntry::Valid(structure) => &structure.parsed_value,
             _ => unreachable!()
@@ -542,14 +973,76 @@ impl SelectionMenuData {
                 }
             }
         }
+        if let MyFileEntry::Valid(entries) = &data_files_menu_data.playlist_entries {
+            let counts = playlist_picker::count_entries(&entries.parsed_value);
+            for entry in &mut self.playlists {
+                entry.entry_count = Some(counts.get(&entry.id).copied().unwrap_or(0));
+            }
+        }
+        self.refresh_smart_playlists(settings);
+    }
+    fn refresh_smart_playlists (&mut self, settings: &settings::Settings) {
+        self.playlists.retain(|entry| entry.smart_expression.is_none());
+        self.playlists.extend(settings.smart_playlists.iter().map(PlaylistEntry::from_smart_preset));
+    }
+    fn refresh_playlist_outputs (&mut self, settings: &settings::Settings) {
+        self.playlist_outputs = playlist_formats::PlaylistFormat::ALL.iter().copied().map(playlist_formats::PlaylistOutput::Format)
+            .chain(settings.playlist_templates.iter().cloned().map(playlist_formats::PlaylistOutput::Template))
+            .collect();
+    }
+    fn find_selected_playlist (&self) -> Option<&PlaylistEntry> {
+        let selected_playlist = self.selected_playlist.as_ref()?;
+        self.playlists.iter().find(|entry| &entry.id == selected_playlist)
     }
 }
 struct SelectionMenuIcedStates {
-    pck_playlist_select: iced::widget::pick_list::State<String>,
+    txt_playlist_filter: iced::widget::text_input::State,
+    scrl_playlists: iced::widget::scrollable::State,
+    btn_playlists: Vec<iced::widget::button::State>,
     btn_update_output_path: iced::widget::button::State,
+    pck_playlist_format: iced::widget::pick_list::State<playlist_formats::PlaylistOutput>,
+    btn_add_template: iced::widget::button::State,
+    pck_archive_format: iced::widget::pick_list::State<archive::ArchiveFormat>,
+    pck_transfer_mode: iced::widget::pick_list::State<export::TransferMode>,
+    pck_copy_workers: iced::widget::pick_list::State<usize>,
+    txt_post_export_hook: iced::widget::text_input::State,
+    pck_volume_preset: iced::widget::pick_list::State<volumes::VolumePreset>,
+    txt_volume_size: iced::widget::text_input::State,
+    btn_export_volumes: iced::widget::button::State,
+    btn_split_to_fit: iced::widget::button::State,
+    btn_export_archive: iced::widget::button::State,
+    btn_export_shared_pool: iced::widget::button::State,
+    btn_rollback_export: iced::widget::button::State,
+    btn_verify_export: iced::widget::button::State,
+    btn_remove_template: iced::widget::button::State,
+    pck_library_format: iced::widget::pick_list::State<library_formats::LibraryFormat>,
+    btn_export_library_selected: iced::widget::button::State,
+    btn_export_library_all: iced::widget::button::State,
+    pck_player_library: iced::widget::pick_list::State<player_libraries::PlayerLibrary>,
+    btn_write_player_selected: iced::widget::button::State,
+    btn_write_player_all: iced::widget::button::State,
+    pck_track_list_format: iced::widget::pick_list::State<track_list::TrackListFormat>,
+    btn_track_list_selected: iced::widget::button::State,
+    btn_track_list_all: iced::widget::button::State,
     scrl_audios: iced::widget::scrollable::State,
+    txt_track_filter: iced::widget::text_input::State,
+    btn_track_headers: [iced::widget::button::State; 7],
+    btn_track_resolutions: Vec<iced::widget::button::State>,
+    pck_bulk_artist: iced::widget::pick_list::State<String>,
+    btn_exclude_artist: iced::widget::button::State,
+    btn_include_artist: iced::widget::button::State,
+    pck_bulk_format: iced::widget::pick_list::State<String>,
+    btn_exclude_format: iced::widget::button::State,
+    btn_include_format: iced::widget::button::State,
+    btn_include_all_tracks: iced::widget::button::State,
     btn_test: iced::widget::button::State,
+    btn_plan_sync: iced::widget::button::State,
+    btn_apply_sync: iced::widget::button::State,
     btn_open_close: iced::widget::button::State,
+    txt_smart_name: iced::widget::text_input::State,
+    txt_smart_expression: iced::widget::text_input::State,
+    btn_smart_save: iced::widget::button::State,
+    btn_smart_delete: iced::widget::button::State,
 }
 struct SelectionMenu {
     data:   SelectionMenuData,
@@ -563,19 +1056,90 @@ impl SelectionMenu {
                 is_opened: true,
                 playlists: Vec::new(),
                 selected_playlist: None,
+                playlist_filter: String::new(),
                 playlists_error_log: Vec::new(),
                 playlist_test_error_log: Vec::new(),
                 output_path: dirs_next::home_dir().unwrap(),
                 audio_in_playlist: Vec::new(),
+                track_resolutions: Vec::new(),
+                expanded_tracks: std::collections::BTreeSet::new(),
+                space_estimate: None,
+                track_filter: String::new(),
+                track_sort: track_table::TrackSort::new(),
+                excluded_tracks: std::collections::BTreeSet::new(),
+                bulk_artist: None,
+                bulk_format: None,
                 //audio_count: 0,
                 is_exported: false,
+                playlist_format: playlist_formats::PlaylistOutput::Format(playlist_formats::PlaylistFormat::M3u),
+                playlist_outputs: Vec::new(),
+                relative_locations: true,
+                archive_format: archive::ArchiveFormat::Zip,
+                transfer_mode: export::TransferMode::Copy,
+                verify_checksums: true,
+                copy_workers: 1,
+                post_export_hook_text: String::new(),
+                volume_preset: volumes::VolumePreset::Cd,
+                volume_size_text: String::new(),
+                sync_compare_content: false,
+                sync_plan: None,
+                library_format: library_formats::LibraryFormat::ItunesXml,
+                player_library: player_libraries::PlayerLibrary::Rhythmbox,
+                track_list_format: track_list::TrackListFormat::Csv,
+                export_log: Vec::new(),
+                export_progress: None,
+                smart_name: String::new(),
+                smart_expression: String::new(),
+                smart_error: None,
             },
             states: SelectionMenuIcedStates {
-                pck_playlist_select: iced::widget::pick_list::State::default(),
+                txt_playlist_filter: iced::widget::text_input::State::new(),
+                scrl_playlists: iced::widget::scrollable::State::new(),
+                btn_playlists: Vec::new(),
                 btn_update_output_path: iced::widget::button::State::new(),
+                pck_playlist_format: iced::widget::pick_list::State::default(),
+                btn_add_template: iced::widget::button::State::new(),
+                pck_archive_format: iced::widget::pick_list::State::default(),
+                pck_transfer_mode: iced::widget::pick_list::State::default(),
+                pck_copy_workers: iced::widget::pick_list::State::default(),
+                txt_post_export_hook: iced::widget::text_input::State::new(),
+                pck_volume_preset: iced::widget::pick_list::State::default(),
+                txt_volume_size: iced::widget::text_input::State::new(),
+                btn_export_volumes: iced::widget::button::State::new(),
+                btn_split_to_fit: iced::widget::button::State::new(),
+                btn_export_archive: iced::widget::button::State::new(),
+                btn_export_shared_pool: iced::widget::button::State::new(),
+                btn_rollback_export: iced::widget::button::State::new(),
+                btn_verify_export: iced::widget::button::State::new(),
+                btn_remove_template: iced::widget::button::State::new(),
+                pck_library_format: iced::widget::pick_list::State::default(),
+                btn_export_library_selected: iced::widget::button::State::new(),
+                btn_export_library_all: iced::widget::button::State::new(),
+                pck_player_library: iced::widget::pick_list::State::default(),
+                btn_write_player_selected: iced::widget::button::State::new(),
+                btn_write_player_all: iced::widget::button::State::new(),
+                pck_track_list_format: iced::widget::pick_list::State::default(),
+                btn_track_list_selected: iced::widget::button::State::new(),
+                btn_track_list_all: iced::widget::button::State::new(),
                 scrl_audios: iced::widget::scrollable::State::new(),
+                txt_track_filter: iced::widget::text_input::State::new(),
+                btn_track_headers: Default::default(),
+                btn_track_resolutions: Vec::new(),
+                pck_bulk_artist: iced::widget::pick_list::State::default(),
+                btn_exclude_artist: iced::widget::button::State::new(),
+                btn_include_artist: iced::widget::button::State::new(),
+                pck_bulk_format: iced::widget::pick_list::State::default(),
+                btn_exclude_format: iced::widget::button::State::new(),
+                btn_include_format: iced::widget::button::State::new(),
+                btn_include_all_tracks: iced::widget::button::State::new(),
                 btn_test: iced::widget::button::State::new(),
+                btn_plan_sync: iced::widget::button::State::new(),
+                btn_apply_sync: iced::widget::button::State::new(),
                 btn_open_close: iced::widget::button::State::new(),
+                txt_smart_name: iced::widget::text_input::State::new(),
+                txt_smart_expression: iced::widget::text_input::State::new(),
+                btn_smart_save: iced::widget::button::State::new(),
+                btn_smart_delete: iced::widget::button::State::new(),
             },
         }
     }
@@ -585,32 +1149,123 @@ impl SelectionMenu {
     fn view<'a> (data: &'a SelectionMenuData, states: &'a mut SelectionMenuIcedStates) -> iced::Element<'a, MyMessage> {
         let mut column = iced::widget::Column::new();
         if data.is_opened {
-            let mut audio_scroll = iced::widget::Scrollable::new(&mut states.scrl_audios);
+            let mut audio_column = iced::widget::Column::new().width(iced::Length::Fill);
             if !data.is_validation_required {
-                audio_scroll = audio_scroll.max_height((6*MY_BASE_FONT_SIZE).into()).push(gen_text(
-                    format!("Будет экспортирован{} {} {}",
-                        if 1 == data.audio_in_playlist.len() %10 {""} else {"о"},
-                        data.audio_in_playlist.len(),
-                        match data.audio_in_playlist.len() % 10 {
+                let export_count = data.audio_in_playlist.len() - data.audio_in_playlist.iter().filter(|audio| data.excluded_tracks.contains(&audio.path)).count();
+                audio_column = audio_column.push(gen_text(
+                    format!("Будет экспортирован{} {} {}{}",
+                        if 1 == export_count %10 {""} else {"о"},
+                        export_count,
+                        match export_count % 10 {
                             1 => "файл",
                             2..=4 => "файла",
                             _ => "файлов",
-                        }
+                        },
+                        if export_count < data.audio_in_playlist.len() {format!(", исключено {}", data.audio_in_playlist.len() - export_count)} else {String::new()}
                     ).as_str()
                 ));
-                if 50 < data.audio_in_playlist.len() {
-                    audio_scroll = audio_scroll.push(gen_text("Первые 50 файлов:"));
-                }
-                for (i, audio) in data.audio_in_playlist.iter().enumerate() {
-                    if 50 == i {
-                        break;
+                if let Some(space_estimate) = &data.space_estimate {
+                    let summary = gen_text(space_estimate.summary().as_str()).size(MY_TABLE_FONT_SIZE);
+                    audio_column = audio_column.push(if space_estimate.fits() {summary} else {summary.color([1.0, 0.0, 0.0])});
+                    if let (false, Some((_, max_bytes))) = (space_estimate.fits(), space_estimate.fitting_volume()) {
+                        audio_column = audio_column.push(
+                            iced::Button::new(&mut states.btn_split_to_fit, gen_text(format!("Не помещается: разбить на тома по {}", export::format_size(max_bytes)).as_str()).size(MY_TABLE_FONT_SIZE))
+                                .on_press(MyMessage::SplitToFit)
+                        );
                     }
-                    audio_scroll = audio_scroll.push(iced::widget::Row::new()
-                        .push(gen_text(format!("{} # ", audio.title).as_str()))
-                        .push(gen_text(format!("{} # ", audio.artist).as_str()))
-                        .push(gen_text(std::path::Path::new(&audio.path).file_name().unwrap().to_str().unwrap()))
+                }
+                let mut btn_exclude_artist = iced::Button::new(&mut states.btn_exclude_artist, gen_text("Исключить").size(MY_TABLE_FONT_SIZE));
+                let mut btn_include_artist = iced::Button::new(&mut states.btn_include_artist, gen_text("Вернуть").size(MY_TABLE_FONT_SIZE));
+                if data.bulk_artist.is_some() {
+                    btn_exclude_artist = btn_exclude_artist.on_press(MyMessage::SetArtistExcluded(true));
+                    btn_include_artist = btn_include_artist.on_press(MyMessage::SetArtistExcluded(false));
+                }
+                let mut btn_exclude_format = iced::Button::new(&mut states.btn_exclude_format, gen_text("Исключить").size(MY_TABLE_FONT_SIZE));
+                let mut btn_include_format = iced::Button::new(&mut states.btn_include_format, gen_text("Вернуть").size(MY_TABLE_FONT_SIZE));
+                if data.bulk_format.is_some() {
+                    btn_exclude_format = btn_exclude_format.on_press(MyMessage::SetFormatExcluded(true));
+                    btn_include_format = btn_include_format.on_press(MyMessage::SetFormatExcluded(false));
+                }
+                audio_column = audio_column
+                    .push(iced::Row::new().spacing(MY_TABLE_FONT_SIZE / 2)
+                        .push(gen_text("Исполнитель:").size(MY_TABLE_FONT_SIZE))
+                        .push(iced::widget::PickList::new(&mut states.pck_bulk_artist, track_selection::artists(&data.audio_in_playlist), data.bulk_artist.clone(), MyMessage::SelectBulkArtist).text_size(MY_TABLE_FONT_SIZE))
+                        .push(btn_exclude_artist)
+                        .push(btn_include_artist)
+                        .push(gen_text("Формат:").size(MY_TABLE_FONT_SIZE))
+                        .push(iced::widget::PickList::new(&mut states.pck_bulk_format, track_selection::formats(&data.audio_in_playlist), data.bulk_format.clone(), MyMessage::SelectBulkFormat).text_size(MY_TABLE_FONT_SIZE))
+                        .push(btn_exclude_format)
+                        .push(btn_include_format)
+                        .push(iced::Button::new(&mut states.btn_include_all_tracks, gen_text("Вернуть все").size(MY_TABLE_FONT_SIZE)).on_press(MyMessage::IncludeAllTracks))
+                    );
+                let track_rows = track_table::track_rows(&data.track_resolutions, &data.audio_in_playlist, data.space_estimate.as_ref(), &data.excluded_tracks);
+                let rows = track_table::visible_rows(&track_rows, &data.track_filter, data.track_sort);
+                audio_column = audio_column
+                    .push(iced::TextInput::new(&mut states.txt_track_filter, "Фильтр: название, исполнитель, путь, альбом, \"нет файла\"...", &data.track_filter, MyMessage::TrackFilterChanged).size(MY_TABLE_FONT_SIZE))
+                    .push(gen_text(format!("Показано {} из {}; щелчок по колонке \"{}\" - как искался файл", rows.len(), track_rows.len(), track_table::TrackColumn::Resolution.title()).as_str()).size(MY_TABLE_FONT_SIZE));
+                let mut header = iced::Row::new().width(iced::Length::Fill);
+                for (column, state) in track_table::TrackColumn::ALL.iter().zip(states.btn_track_headers.iter_mut()) {
+                    header = header.push(iced::Button::new(state, gen_text(data.track_sort.header(*column).as_str()).size(MY_TABLE_FONT_SIZE))
+                        .width(iced::Length::FillPortion(column.width_portion()))
+                        .on_press(MyMessage::SortTracks(*column))
                     );
                 }
+                audio_column = audio_column.push(header);
+                states.btn_track_resolutions.resize_with(track_rows.len(), Default::default);
+                // строки показываются в порядке сортировки, а состояния кнопок - по строкам
+                let mut resolution_states: Vec<Option<&mut iced::widget::button::State>> = states.btn_track_resolutions.iter_mut().map(Some).collect();
+                let mut audio_scroll = iced::widget::Scrollable::new(&mut states.scrl_audios).max_height((12*MY_BASE_FONT_SIZE).into());
+                for r in rows {
+                    let track_row = &track_rows[r];
+                    let resolution = track_row.resolution;
+                    let cell = |column: track_table::TrackColumn, text: &str| gen_text(text).size(MY_TABLE_FONT_SIZE).width(iced::Length::FillPortion(column.width_portion()));
+                    let mut title_cell = iced::Column::new().width(iced::Length::FillPortion(track_table::TrackColumn::Title.width_portion()))
+                        .push(gen_text(resolution.title.as_str()).size(MY_TABLE_FONT_SIZE));
+                    let metadata_values = track_row.audio.map_or(Vec::new(), |audio| audio.metadata.named_values());
+                    if !metadata_values.is_empty() {
+                        title_cell = title_cell.push(gen_text(
+                            metadata_values.iter().map(|(key, value)| format!("{}: {}", metadata_label(key), value)).collect::<Vec<String>>().join(", ").as_str()
+                        ).size(MY_TABLE_FONT_SIZE * 3 / 4).color([0.3, 0.3, 0.3]));
+                    }
+                    let index_cell: iced::Element<MyMessage> = match track_row.audio_index {
+                        Some(i) => iced::Checkbox::new(track_row.included, (1 + r).to_string(), move |included| MyMessage::SetTrackIncluded(i, included))
+                            .size(MY_TABLE_FONT_SIZE)
+                            .text_size(MY_TABLE_FONT_SIZE)
+                            .width(iced::Length::FillPortion(track_table::TrackColumn::Index.width_portion()))
+                            .into(),
+                        None => cell(track_table::TrackColumn::Index, (1 + r).to_string().as_str()).into(),
+                    };
+                    let resolution_cell = iced::Button::new(
+                        resolution_states[r].take().unwrap(),
+                        gen_text(format!("{} {}", resolution.status.icon(), resolution.status.label()).as_str()).size(MY_TABLE_FONT_SIZE).color(resolution.status.color())
+                    )
+                        .width(iced::Length::FillPortion(track_table::TrackColumn::Resolution.width_portion()))
+                        .on_press(MyMessage::ToggleTrackDetails(r));
+                    let table_row = iced::Row::new().width(iced::Length::Fill).spacing(MY_TABLE_FONT_SIZE / 2)
+                        .push(index_cell)
+                        .push(title_cell)
+                        .push(cell(track_table::TrackColumn::Artist, resolution.artist.as_str()))
+                        .push(cell(track_table::TrackColumn::Path, resolution.shown_path()))
+                        .push(cell(track_table::TrackColumn::Size, track_row.space.and_then(|space| space.size).map_or(String::new(), export::format_size).as_str()))
+                        .push(resolution_cell)
+                        .push(cell(track_table::TrackColumn::Status, track_table::track_status(track_row)));
+                    if data.expanded_tracks.contains(&r) {
+                        let mut details_column = iced::Column::new();
+                        for line in resolution.details() {
+                            details_column = details_column.push(gen_text(line.as_str()).size(MY_TABLE_FONT_SIZE * 3 / 4).color([0.3, 0.3, 0.3]));
+                        }
+                        audio_scroll = audio_scroll
+                            .push(table_row)
+                            .push(iced::Row::new()
+                                .push(iced::Space::with_width(iced::Length::Units(2 * MY_TABLE_FONT_SIZE)))
+                                .push(details_column)
+                            );
+                    }
+                    else {
+                        audio_scroll = audio_scroll.push(table_row);
+                    }
+                }
+                audio_column = audio_column.push(audio_scroll);
             }
 
             let mut menu_column = iced::widget::Column::new()
@@ -622,21 +1277,143 @@ impl SelectionMenu {
                         .push(
                             gen_text(data.output_path.as_path().to_string_lossy().into_owned().as_str())
                 ))
+                .push(gen_text("Формат файла плейлиста:"))
+                .push(iced::widget::PickList::new(&mut states.pck_playlist_format, data.playlist_outputs.clone(), Some(data.playlist_format.clone()), MyMessage::SelectPlaylistFormat))
+                .push({
+                    let mut btn_remove_template = iced::Button::new(&mut states.btn_remove_template, gen_text("Убрать шаблон"));
+                    if let playlist_formats::PlaylistOutput::Template(_) = data.playlist_format {
+                        btn_remove_template = btn_remove_template.on_press(MyMessage::RemovePlaylistTemplate);
+                    }
+                    iced::Row::new()
+                        .push(iced::Button::new(&mut states.btn_add_template, gen_text("Добавить шаблон...")).on_press(MyMessage::AddPlaylistTemplate))
+                        .push(btn_remove_template)
+                })
+                .push(iced::Checkbox::new(
+                    data.relative_locations,
+                    "Относительные пути в плейлисте",
+                    MyMessage::SetRelativeLocations
+                ).text_size(MY_BASE_FONT_SIZE))
+                .push(gen_text("Как переносить треки:"))
+                .push(iced::widget::PickList::new(&mut states.pck_transfer_mode, &export::TransferMode::ALL[..], Some(data.transfer_mode), MyMessage::SelectTransferMode))
+                .push(iced::Row::new()
+                    .push(gen_text("Файлов одновременно (больше - быстрее с сетевого диска):"))
+                    .push(iced::widget::PickList::new(&mut states.pck_copy_workers, &export::COPY_WORKER_CHOICES[..], Some(data.copy_workers), MyMessage::SelectCopyWorkers))
+                )
+                .push(gen_text("Команда после экспорта каждого плейлиста (переменные AUDIALS_PLAYLIST_NAME, AUDIALS_OUTPUT_DIR, AUDIALS_PLAYLIST_FILE, AUDIALS_EXPORTED, AUDIALS_FAILED):"))
+                .push(iced::TextInput::new(&mut states.txt_post_export_hook, "например: sync", &data.post_export_hook_text, MyMessage::PostExportHookChanged).size(MY_BASE_FONT_SIZE))
+                .push(iced::Checkbox::new(
+                    data.verify_checksums,
+                    "Сверять копии с оригиналами по SHA-256 и писать SHA256SUMS",
+                    MyMessage::SetVerifyChecksums
+                ).text_size(MY_BASE_FONT_SIZE))
                 .push(gen_text("Плейлист:"))
-                .push(iced::widget::PickList::new(&mut states.pck_playlist_select, data.playlists.iter().map(|entry| entry.name.clone()).collect::<Vec<String>>(), data.selected_playlist.clone(), MyMessage::SelectPlaylist));
+                .push(iced::TextInput::new(&mut states.txt_playlist_filter, "Поиск: имя, id, дата...", &data.playlist_filter, MyMessage::PlaylistFilterChanged).size(MY_TABLE_FONT_SIZE));
+            states.btn_playlists.resize_with(data.playlists.len(), Default::default);
+            let mut playlists_scroll = iced::widget::Scrollable::new(&mut states.scrl_playlists).max_height((6*MY_BASE_FONT_SIZE).into());
+            let mut shown_playlists = 0;
+            for (entry, state) in data.playlists.iter().zip(states.btn_playlists.iter_mut()) {
+                if !playlist_picker::matches_filter(entry, &data.playlist_filter) {
+                    continue;
+                }
+                shown_playlists += 1;
+                let label = gen_text(playlist_picker::label(entry).as_str()).size(MY_TABLE_FONT_SIZE);
+                let label = if data.selected_playlist.as_ref() == Some(&entry.id) {label.color([0.0, 0.3, 0.8])} else {label};
+                playlists_scroll = playlists_scroll.push(iced::Button::new(state, label)
+                    .width(iced::Length::Fill)
+                    .on_press(MyMessage::SelectPlaylist(entry.id.clone()))
+                );
+            }
+            menu_column = menu_column
+                .push(gen_text(format!("Показано {} из {}", shown_playlists, data.playlists.len()).as_str()).size(MY_TABLE_FONT_SIZE))
+                .push(playlists_scroll);
             if let Some(_) = data.selected_playlist {
                 menu_column = if data.is_validation_required {
                     menu_column.push(iced::Button::new(&mut states.btn_test, gen_text("Проверить")).on_press(MyMessage::TestPlaylist))
                 }
                 else {
-                    menu_column.push(iced::Button::new(&mut states.btn_test, gen_text("Экспортировать")).on_press(MyMessage::Export))
+                    let mut btn_apply_sync = iced::Button::new(&mut states.btn_apply_sync, gen_text("Применить синхронизацию"));
+                    if data.sync_plan.is_some() {
+                        btn_apply_sync = btn_apply_sync.on_press(MyMessage::ApplySync);
+                    }
+                    menu_column
+                        .push(iced::Button::new(&mut states.btn_test, gen_text("Экспортировать")).on_press(MyMessage::Export))
+                        .push(iced::Row::new()
+                            .push(iced::Button::new(&mut states.btn_plan_sync, gen_text("Синхронизировать (пробный прогон)")).on_press(MyMessage::PlanSync))
+                            .push(btn_apply_sync)
+                        )
+                        .push(iced::Checkbox::new(
+                            data.sync_compare_content,
+                            "Сравнивать содержимое файлов, а не размер и время",
+                            MyMessage::SetSyncCompareContent
+                        ).text_size(MY_BASE_FONT_SIZE))
+                        .push(iced::Row::new()
+                            .push(iced::Button::new(&mut states.btn_export_archive, gen_text("В архив...")).on_press(MyMessage::ExportArchive))
+                            .push(iced::widget::PickList::new(&mut states.pck_archive_format, &archive::ArchiveFormat::ALL[..], Some(data.archive_format), MyMessage::SelectArchiveFormat))
+                        )
+                        .push({
+                            let mut volumes_row = iced::Row::new()
+                                .push(iced::Button::new(&mut states.btn_export_volumes, gen_text("По томам (Vol 01, Vol 02, ...)")).on_press(MyMessage::ExportVolumes))
+                                .push(iced::widget::PickList::new(&mut states.pck_volume_preset, &volumes::VolumePreset::ALL[..], Some(data.volume_preset), MyMessage::SelectVolumePreset));
+                            if volumes::VolumePreset::Custom == data.volume_preset {
+                                volumes_row = volumes_row.push(iced::TextInput::new(&mut states.txt_volume_size, "МБ", &data.volume_size_text, MyMessage::VolumeSizeChanged).size(MY_BASE_FONT_SIZE).width(iced::Length::Units(8 * MY_BASE_FONT_SIZE)));
+                            }
+                            volumes_row
+                        })
                 }
             }
+            menu_column = menu_column
+                .push(gen_text(&format!("Все плейлисты в папку результата, треки один раз в общей папке {}/:", shared_pool::POOL_DIR_NAME)))
+                .push(iced::Button::new(&mut states.btn_export_shared_pool, gen_text("Экспортировать все плейлисты")).on_press(MyMessage::ExportSharedPool))
+                .push(gen_text("Прерванный экспорт продолжается, если повторить его в ту же папку."))
+                .push(iced::Row::new()
+                    .push(iced::Button::new(&mut states.btn_verify_export, gen_text("Проверить экспорт в папке результата")).on_press(MyMessage::VerifyExport))
+                    .push(iced::Button::new(&mut states.btn_rollback_export, gen_text("Откатить последний экспорт")).on_press(MyMessage::RollbackExport))
+                );
+            let mut btn_export_library_selected = iced::Button::new(&mut states.btn_export_library_selected, gen_text("Выбранный плейлист"));
+            let mut btn_write_player_selected = iced::Button::new(&mut states.btn_write_player_selected, gen_text("Выбранный плейлист"));
+            if data.selected_playlist.is_some() {
+                btn_export_library_selected = btn_export_library_selected.on_press(MyMessage::ExportLibrary(false));
+                btn_write_player_selected = btn_write_player_selected.on_press(MyMessage::WriteToPlayer(false));
+            }
+            let mut btn_track_list_selected = iced::Button::new(&mut states.btn_track_list_selected, gen_text("Выбранный плейлист"));
+            if data.selected_playlist.is_some() {
+                btn_track_list_selected = btn_track_list_selected.on_press(MyMessage::ExportTrackList(false));
+            }
+            menu_column = menu_column
+                .push(gen_text("Экспорт в библиотеку другой программы:"))
+                .push(iced::widget::PickList::new(&mut states.pck_library_format, &library_formats::LibraryFormat::ALL[..], Some(data.library_format), MyMessage::SelectLibraryFormat))
+                .push(iced::Row::new()
+                    .push(btn_export_library_selected)
+                    .push(iced::Button::new(&mut states.btn_export_library_all, gen_text("Все плейлисты")).on_press(MyMessage::ExportLibrary(true)))
+                )
+                .push(gen_text("Записать в плеер (оригинал сохраняется в .bak-*, плеер нужно закрыть):"))
+                .push(iced::widget::PickList::new(&mut states.pck_player_library, &player_libraries::PlayerLibrary::ALL[..], Some(data.player_library), MyMessage::SelectPlayerLibrary))
+                .push(iced::Row::new()
+                    .push(btn_write_player_selected)
+                    .push(iced::Button::new(&mut states.btn_write_player_all, gen_text("Все плейлисты")).on_press(MyMessage::WriteToPlayer(true)))
+                )
+                .push(gen_text("Только список треков, без файлов (и ненайденные треки):"))
+                .push(iced::widget::PickList::new(&mut states.pck_track_list_format, &track_list::TrackListFormat::ALL[..], Some(data.track_list_format), MyMessage::SelectTrackListFormat))
+                .push(iced::Row::new()
+                    .push(btn_track_list_selected)
+                    .push(iced::Button::new(&mut states.btn_track_list_all, gen_text("Все плейлисты")).on_press(MyMessage::ExportTrackList(true)))
+                );
+            menu_column = menu_column
+                .push(gen_text("Умный плейлист (условия через ';', например: artist ~ Queen; year >= 1980; path glob */Rock/*; added >= 2026):"))
+                .push(iced::TextInput::new(&mut states.txt_smart_name, "Имя", &data.smart_name, MyMessage::SmartPlaylistNameChanged).size(MY_BASE_FONT_SIZE))
+                .push(iced::TextInput::new(&mut states.txt_smart_expression, "Фильтр", &data.smart_expression, MyMessage::SmartPlaylistExpressionChanged).size(MY_BASE_FONT_SIZE))
+                .push(iced::Row::new()
+                    .push(iced::Button::new(&mut states.btn_smart_save, gen_text("Сохранить")).on_press(MyMessage::SaveSmartPlaylist))
+                    .push(iced::Button::new(&mut states.btn_smart_delete, gen_text("Удалить")).on_press(MyMessage::DeleteSmartPlaylist))
+                );
+            if let Some(smart_error) = &data.smart_error {
+                menu_column = menu_column.push(gen_text(smart_error.as_str()).color([1.0, 0.0, 0.0]));
+            }
 
             let mut row = iced::widget::Row::new();
             row = row
                 .push(menu_column)
-                .push(audio_scroll);
+                .push(audio_column);
             column = column.push(row);
         }
         column.push(iced::Row::new().push(iced::Button::new(&mut states.btn_open_close, gen_text(
@@ -656,7 +1433,17 @@ impl SelectionMenu {
             ))).push(iced::Rule::horizontal(MY_BASE_FONT_SIZE))
             .into()
     }
-    fn update_if_acceptable (&mut self, message: &MyMessage, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData) {
+    fn update_if_acceptable (&mut self, message: &MyMessage, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, settings: &mut settings::Settings) -> iced::Command<MyMessage> {
+        // пока идёт экспорт, папку результата и её журнал трогать нельзя
+        let touches_output = matches!(message,
+            MyMessage::Export | MyMessage::ApplySync | MyMessage::ExportArchive | MyMessage::ExportSharedPool
+            | MyMessage::ExportVolumes | MyMessage::SplitToFit | MyMessage::VerifyExport | MyMessage::RollbackExport
+        );
+        if touches_output && self.data.export_progress.is_some() {
+            self.data.export_log.push("# Замечание: экспорт ещё идёт, дождитесь его окончания".to_string());
+            return iced::Command::none();
+        }
+        let mut command = iced::Command::none();
         match message {
             MyMessage::SetOutputPath =>
                 if let Some(new_dir_path) = rfd::FileDialog::new().pick_folder() {
@@ -664,48 +1451,471 @@ impl SelectionMenu {
                         self.data.output_path = new_dir_path;
                         self.data.is_validation_required = true;
                         self.data.is_exported = false;
+                        self.data.export_log.clear();
+                        self.data.sync_plan = None;
                     }
                 },
-            MyMessage::SelectPlaylist(playlist_name) => {
+            MyMessage::SelectPlaylistFormat(playlist_format) => {
+                self.data.playlist_format = playlist_format.clone();
+                settings.playlist_format = playlist_format.clone();
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                }
+            },
+            MyMessage::AddPlaylistTemplate => {
+                if let Some(template_path) = rfd::FileDialog::new().pick_file() {
+                    // шаблон с ошибкой в список не попадает
+                    if let Err(err) = playlist_template::PlaylistTemplate::load(&template_path) {
+                        self.data.export_log.push(format!("# Ошибка: {}", err));
+                        return iced::Command::none();
+                    }
+                    if !settings.playlist_templates.contains(&template_path) {
+                        settings.playlist_templates.push(template_path.clone());
+                    }
+                    settings.playlist_format = playlist_formats::PlaylistOutput::Template(template_path);
+                    self.data.playlist_format = settings.playlist_format.clone();
+                    self.data.refresh_playlist_outputs(settings);
+                    if let Err(err) = settings.save() {
+                        self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                    }
+                }
+            },
+            MyMessage::RemovePlaylistTemplate => {
+                if let playlist_formats::PlaylistOutput::Template(template_path) = &self.data.playlist_format {
+                    settings.playlist_templates.retain(|path| path != template_path);
+                    settings.playlist_format = playlist_formats::PlaylistOutput::Format(playlist_formats::PlaylistFormat::M3u);
+                    self.data.playlist_format = settings.playlist_format.clone();
+                    self.data.refresh_playlist_outputs(settings);
+                    if let Err(err) = settings.save() {
+                        self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                    }
+                }
+            },
+            MyMessage::SetRelativeLocations(relative_locations) => {
+                self.data.relative_locations = *relative_locations;
+                settings.relative_locations = *relative_locations;
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                }
+            },
+            MyMessage::PlaylistFilterChanged(playlist_filter) => {
+                self.data.playlist_filter = playlist_filter.clone();
+            },
+            MyMessage::SelectPlaylist(playlist_id) => {
                 self.data.playlist_test_error_log.clear();
-                self.data.selected_playlist = Some(playlist_name.clone());
+                self.data.track_filter.clear();
+                self.data.sync_plan = None;
+                self.data.selected_playlist = Some(playlist_id.clone());
                 self.data.is_validation_required = true;
                 self.data.is_exported = false;
+                self.data.export_log.clear();
+                if let Some(PlaylistEntry{name, smart_expression: Some(expression), ..}) = self.data.find_selected_playlist() {
+                    let (name, expression) = (name.clone(), expression.clone());
+                    self.data.smart_name = name;
+                    self.data.smart_expression = expression;
+                    self.data.smart_error = None;
+                }
                 //println!("selected {}", pl);
             },
             MyMessage::TestPlaylist => {
                 self.data.playlist_test_error_log.clear();
+                self.data.sync_plan = None;
+                self.data.export_log.clear();
                 self.data.is_exported = false;
-                let audio_ids = get_entries_ids_for_playlist(
-                    &self.data.playlists.iter().find(|entry| &entry.name == self.data.selected_playlist.as_ref().unwrap()).unwrap().id,
-                    match &data_files_menu_data.playlist_entries {
-                        MyFileEntry::Valid(entries) => {
-                            //println!("доступно в списке: {:?}", entries.parsed_value);
-                            &entries.parsed_value
-                        },
-                        _ => unreachable!(),
-                    },
-                    &mut self.data.playlist_test_error_log
-                );
+                let selected_playlist = self.data.find_selected_playlist().unwrap().clone();
+                if let MyFileEntry::Valid(AudioDatabase {snapshot: Some(snapshot), ..}) = &data_files_menu_data.audio_database {
+                    self.data.playlist_test_error_log.push(format!(
+                        "# Замечание: читается копия базы, снятая {} с назад{}",
+                        snapshot.age().as_secs(),
+                        if snapshot.is_stale() {", с тех пор база изменилась, а обновить копию не удалось"} else {""}
+                    ));
+                }
+                let audio_ids = get_entries_ids_for_playlist_entry(&selected_playlist, data_files_menu_data, &mut self.data.playlist_test_error_log);
                 //println!("Найдены id: {:?}", audio_ids);
-                self.data.audio_in_playlist = get_audio_entries_from_ids(&audio_ids, &base_menu_data, &data_files_menu_data, &mut self.data.playlist_test_error_log);
+                let (audio_in_playlist, track_resolutions) = resolve_audio_entries(&audio_ids, base_menu_data, data_files_menu_data, &mut self.data.playlist_test_error_log);
+                self.data.audio_in_playlist = audio_in_playlist;
+                self.data.track_resolutions = track_resolutions;
+                self.data.expanded_tracks.clear();
+                self.data.excluded_tracks = settings.excluded_tracks.get(&selected_playlist.id).cloned().unwrap_or_default();
+                self.data.bulk_artist = None;
+                self.data.bulk_format = None;
                 if !self.data.audio_in_playlist.is_empty() {
                     self.data.is_validation_required = false;
+                    self.data.refresh_space_estimate();
                 }
                 //println!("Найдены песни({}): {:?}", self.data.audio_in_playlist.len(), self.data.audio_in_playlist);
                 //println!("пести validation_required: {}", self.data.is_validation_required);
             },
             MyMessage::Export => {
-                for audio in &self.data.audio_in_playlist {
-                    let audio_path = std::path::Path::new(&audio.path);
-                    let dest_path = {
-                        let mut p = self.data.output_path.clone();
-                        p.push(audio_path.file_name().unwrap());
-                        p
+                let playlist_name = self.data.find_selected_playlist().unwrap().name.clone();
+                let (audio_in_playlist, output_path) = (self.data.export_audio(), self.data.output_path.clone());
+                self.data.export_log.clear();
+                self.data.sync_plan = None;
+                command = self.data.start_export(settings, move |options| export::export_playlist(&playlist_name, &audio_in_playlist, &output_path, &options).log);
+            },
+            MyMessage::ExportTick if self.data.export_progress.is_some() => {
+                command = background::tick(MyMessage::ExportTick);
+            },
+            // без export_progress меню сбросили, пока экспорт шёл, - лог
+            // показывать уже негде
+            MyMessage::ExportFinished(log) if self.data.export_progress.is_some() => {
+                self.data.export_progress = None;
+                self.data.export_log.extend(log.iter().cloned());
+                self.data.is_exported = true;
+                self.data.sync_plan = None;
+                if !self.data.audio_in_playlist.is_empty() {
+                    self.data.refresh_space_estimate();
+                }
+            },
+            MyMessage::SetSyncCompareContent(sync_compare_content) => {
+                self.data.sync_compare_content = *sync_compare_content;
+                self.data.sync_plan = None;
+            },
+            MyMessage::PlanSync => {
+                let sync_plan = sync::plan_sync(&self.data.find_selected_playlist().unwrap().name, &self.data.export_audio(), &self.data.output_path, self.data.sync_compare_content);
+                self.data.export_log = sync_plan.diff();
+                self.data.export_log.push("Это пробный прогон, ничего не изменено. Проверьте удаления и нажмите \"Применить синхронизацию\"".into());
+                self.data.sync_plan = Some(sync_plan);
+                self.data.is_exported = false;
+            },
+            MyMessage::ApplySync => {
+                if let Some(sync_plan) = self.data.sync_plan.take() {
+                    let (playlist_name, audio_in_playlist) = (self.data.find_selected_playlist().unwrap().name.clone(), self.data.export_audio());
+                    self.data.export_log.clear();
+                    command = self.data.start_export(settings, move |options| sync::apply_sync(&playlist_name, &audio_in_playlist, &sync_plan, &options).log);
+                }
+            },
+            MyMessage::SelectTransferMode(transfer_mode) => {
+                self.data.transfer_mode = *transfer_mode;
+                settings.transfer_mode = *transfer_mode;
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                }
+            },
+            MyMessage::SelectArchiveFormat(archive_format) => {
+                self.data.archive_format = *archive_format;
+                settings.archive_format = *archive_format;
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                }
+            },
+            MyMessage::ExportArchive => {
+                let archive_format = self.data.archive_format;
+                let playlist_name = self.data.find_selected_playlist().unwrap().name.clone();
+                let archive_path = rfd::FileDialog::new()
+                    .set_directory(&self.data.output_path)
+                    .set_file_name(&format!("{}.{}", export::sanitize_file_name(&playlist_name), archive_format.extension()))
+                    .add_filter(&archive_format.to_string(), &[archive_format.extension()])
+                    .save_file();
+                if let Some(archive_path) = archive_path {
+                    self.data.export_log.clear();
+                    let archive_file = match std::fs::File::create(&archive_path) {
+                        Ok(archive_file) => archive_file,
+                        Err(err) => {
+                            self.data.export_log.push(format!("# Ошибка: не удалось создать '{}': {}", archive_path.to_string_lossy(), err));
+                            return iced::Command::none();
+                        },
                     };
-                    println!("{} => {}", audio.path, dest_path.to_string_lossy());
+                    let audio_in_playlist = self.data.export_audio();
+                    command = self.data.start_export(settings, move |options| {
+                        let report = archive::export_playlist_to_archive(&playlist_name, &audio_in_playlist, std::io::BufWriter::new(archive_file), archive_format, &options);
+                        let hook_log = match &options.post_export_hook {
+                            Some(hook) => archive::run_archive_hook(hook, &playlist_name, &archive_path, &report),
+                            None => Vec::new(),
+                        };
+                        let mut log = report.log;
+                        log.push(format!("Записан архив: {}", archive_path.to_string_lossy()));
+                        log.extend(hook_log);
+                        log
+                    });
+                }
+            },
+            MyMessage::ExportSharedPool => {
+                self.data.export_log.clear();
+                self.data.sync_plan = None;
+                let mut resolved_playlists = resolve_playlists(&self.data.playlists, base_menu_data, data_files_menu_data, &mut self.data.export_log);
+                track_selection::remove_saved_exclusions_from_all(&mut resolved_playlists, settings, &mut self.data.export_log);
+                let output_path = self.data.output_path.clone();
+                command = self.data.start_export(settings, move |options| shared_pool::export_playlists_to_pool(&resolved_playlists, &output_path, &options).log);
+            },
+            MyMessage::PostExportHookChanged(post_export_hook) => {
+                self.data.post_export_hook_text = post_export_hook.clone();
+            },
+            MyMessage::SelectCopyWorkers(copy_workers) => {
+                self.data.copy_workers = *copy_workers;
+                settings.copy_workers = *copy_workers;
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
                 }
-                self.data.is_exported = true;
+            },
+            MyMessage::SetVerifyChecksums(verify_checksums) => {
+                self.data.verify_checksums = *verify_checksums;
+                settings.verify_checksums = *verify_checksums;
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                }
+            },
+            MyMessage::VerifyExport => {
+                self.data.export_log = checksums::verify_export(&self.data.output_path);
+                self.data.is_exported = false;
+            },
+            MyMessage::RollbackExport => {
+                self.data.sync_plan = None;
+                self.data.export_log = export_manifest::rollback(&self.data.output_path);
+                self.data.is_exported = false;
+            },
+            MyMessage::SelectLibraryFormat(library_format) => {
+                self.data.library_format = *library_format;
+            },
+            MyMessage::ExportLibrary(all_playlists) => {
+                let library_format = self.data.library_format;
+                let library_path = rfd::FileDialog::new()
+                    .set_directory(&self.data.output_path)
+                    .set_file_name(library_format.default_file_name())
+                    .add_filter(&library_format.to_string(), &[library_format.extension()])
+                    .save_file();
+                if let Some(library_path) = library_path {
+                    self.data.export_log.clear();
+                    self.data.is_exported = false;
+                    let playlists: Vec<PlaylistEntry> = if *all_playlists {
+                        self.data.playlists.clone()
+                    }
+                    else {
+                        self.data.find_selected_playlist().into_iter().cloned().collect()
+                    };
+                    let mut resolved_playlists = resolve_playlists(&playlists, base_menu_data, data_files_menu_data, &mut self.data.export_log);
+                    track_selection::remove_saved_exclusions_from_all(&mut resolved_playlists, settings, &mut self.data.export_log);
+                    match std::fs::write(&library_path, library_format.writer().write(&resolved_playlists)) {
+                        Ok(_) => {
+                            self.data.export_log.push(format!("Записана библиотека {}: {}", library_format, library_path.to_string_lossy()));
+                            self.data.is_exported = true;
+                        },
+                        Err(err) => {
+                            self.data.export_log.push(format!("# Ошибка: не удалось записать '{}': {}", library_path.to_string_lossy(), err));
+                        },
+                    }
+                }
+            },
+            MyMessage::SelectPlayerLibrary(player_library) => {
+                self.data.player_library = *player_library;
+            },
+            MyMessage::WriteToPlayer(all_playlists) => {
+                let player_library = self.data.player_library;
+                let mut dialog = rfd::FileDialog::new();
+                if let Some(dir) = player_library.default_path().as_ref().and_then(|default_path| default_path.parent()) {
+                    dialog = dialog.set_directory(dir);
+                }
+                if let Some(library_path) = dialog.pick_file() {
+                    self.data.export_log.clear();
+                    self.data.is_exported = false;
+                    let playlists: Vec<PlaylistEntry> = if *all_playlists {
+                        self.data.playlists.clone()
+                    }
+                    else {
+                        self.data.find_selected_playlist().into_iter().cloned().collect()
+                    };
+                    let mut resolved_playlists = resolve_playlists(&playlists, base_menu_data, data_files_menu_data, &mut self.data.export_log);
+                    track_selection::remove_saved_exclusions_from_all(&mut resolved_playlists, settings, &mut self.data.export_log);
+                    match player_library.write_playlists(&library_path, &resolved_playlists) {
+                        Ok(log) => {
+                            self.data.export_log.extend(log);
+                            self.data.export_log.push(format!("Обновлена библиотека {}: {}", player_library, library_path.to_string_lossy()));
+                            self.data.is_exported = true;
+                        },
+                        Err(err) => {
+                            self.data.export_log.push(format!("# Ошибка: {}", err));
+                        },
+                    }
+                }
+            },
+            MyMessage::SelectTrackListFormat(track_list_format) => {
+                self.data.track_list_format = *track_list_format;
+            },
+            MyMessage::ExportTrackList(all_playlists) => {
+                let track_list_format = self.data.track_list_format;
+                let playlists: Vec<PlaylistEntry> = if *all_playlists {
+                    self.data.playlists.clone()
+                }
+                else {
+                    self.data.find_selected_playlist().into_iter().cloned().collect()
+                };
+                let default_name = match (*all_playlists, playlists.first()) {
+                    (false, Some(playlist)) => export::sanitize_file_name(&playlist.name),
+                    _ => String::from("playlists"),
+                };
+                let track_list_path = rfd::FileDialog::new()
+                    .set_directory(&self.data.output_path)
+                    .set_file_name(&format!("{}.{}", default_name, track_list_format.extension()))
+                    .add_filter(&track_list_format.to_string(), &[track_list_format.extension()])
+                    .save_file();
+                if let Some(track_list_path) = track_list_path {
+                    self.data.export_log.clear();
+                    self.data.is_exported = false;
+                    let mut track_list_playlists = Vec::new();
+                    for playlist in &playlists {
+                        let mut playlist_log = Vec::new();
+                        let audio_ids = get_entries_ids_for_playlist_entry(playlist, data_files_menu_data, &mut playlist_log);
+                        let mut tracks = get_track_records_from_ids(&audio_ids, base_menu_data, data_files_menu_data, &mut playlist_log);
+                        track_selection::remove_saved_exclusions_from_records(&playlist.name, &playlist.id, &mut tracks, settings, &mut self.data.export_log);
+                        self.data.export_log.extend(playlist_log.into_iter().map(|line| format!("{} (плейлист '{}')", line, playlist.name)));
+                        self.data.export_log.push(format!(
+                            "Плейлист '{}': в списке {} треков, из них файл найден у {}",
+                            playlist.name, tracks.len(), tracks.iter().filter(|track| track.real_path.is_some()).count()
+                        ));
+                        track_list_playlists.push(track_list::TrackListPlaylist {
+                            name: playlist.name.clone(),
+                            tracks,
+                        });
+                    }
+                    match std::fs::write(&track_list_path, track_list_format.write(&track_list_playlists)) {
+                        Ok(_) => {
+                            self.data.export_log.push(format!("Записан список треков {}: {}", track_list_format, track_list_path.to_string_lossy()));
+                            self.data.is_exported = true;
+                        },
+                        Err(err) => {
+                            self.data.export_log.push(format!("# Ошибка: не удалось записать '{}': {}", track_list_path.to_string_lossy(), err));
+                        },
+                    }
+                }
+            },
+            MyMessage::SelectVolumePreset(volume_preset) => {
+                self.data.volume_preset = *volume_preset;
+                settings.volume_preset = *volume_preset;
+                if let Err(err) = settings.save() {
+                    self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                }
+            },
+            MyMessage::VolumeSizeChanged(volume_size_text) => {
+                self.data.volume_size_text = volume_size_text.clone();
+            },
+            // Предложение из оценки места: том, который помещается на диск,
+            // и дальше обычный экспорт по томам
+            MyMessage::SplitToFit => {
+                let fitting_volume = self.data.space_estimate.as_ref().and_then(|space_estimate| space_estimate.fitting_volume());
+                if let Some((volume_preset, max_bytes)) = fitting_volume {
+                    self.data.volume_preset = volume_preset;
+                    if volumes::VolumePreset::Custom == volume_preset {
+                        self.data.volume_size_text = (max_bytes / volumes::MB).to_string();
+                    }
+                    command = self.update_if_acceptable(&MyMessage::ExportVolumes, base_menu_data, data_files_menu_data, settings);
+                }
+            },
+            MyMessage::ExportVolumes => {
+                let max_bytes = match self.data.volume_preset.max_bytes() {
+                    Some(max_bytes) => Ok(max_bytes),
+                    None => volumes::parse_volume_size(&self.data.volume_size_text),
+                };
+                let max_bytes = match max_bytes {
+                    Ok(max_bytes) => max_bytes,
+                    Err(err) => {
+                        self.data.export_log = vec![format!("# Ошибка: {}", err)];
+                        return iced::Command::none();
+                    },
+                };
+                self.data.export_log.clear();
+                if volumes::VolumePreset::Custom == self.data.volume_preset {
+                    settings.volume_custom_mb = self.data.volume_size_text.trim().parse().ok();
+                    if let Err(err) = settings.save() {
+                        self.data.export_log.push(format!("# Ошибка: настройки не сохранены: {}", err));
+                    }
+                }
+                let playlist_name = self.data.find_selected_playlist().unwrap().name.clone();
+                let (audio_in_playlist, output_path) = (self.data.export_audio(), self.data.output_path.clone());
+                self.data.sync_plan = None;
+                command = self.data.start_export(settings, move |options| volumes::export_playlist_volumes(&playlist_name, &audio_in_playlist, &output_path, max_bytes, &options).log);
+            },
+            MyMessage::SetTrackIncluded(i, included) => {
+                let path = self.data.audio_in_playlist[*i].path.clone();
+                if *included {
+                    self.data.excluded_tracks.remove(&path);
+                }
+                else {
+                    self.data.excluded_tracks.insert(path);
+                }
+                self.data.save_excluded_tracks(settings);
+            },
+            MyMessage::SelectBulkArtist(artist) => {
+                self.data.bulk_artist = Some(artist.clone());
+            },
+            MyMessage::SelectBulkFormat(format) => {
+                self.data.bulk_format = Some(format.clone());
+            },
+            MyMessage::SetArtistExcluded(exclude) => {
+                if let Some(artist) = self.data.bulk_artist.clone() {
+                    track_selection::set_excluded_where(&mut self.data.excluded_tracks, &self.data.audio_in_playlist, |audio| audio.artist == artist, *exclude);
+                    self.data.save_excluded_tracks(settings);
+                }
+            },
+            MyMessage::SetFormatExcluded(exclude) => {
+                if let Some(format) = self.data.bulk_format.clone() {
+                    track_selection::set_excluded_where(&mut self.data.excluded_tracks, &self.data.audio_in_playlist, |audio| track_selection::track_format(audio) == format, *exclude);
+                    self.data.save_excluded_tracks(settings);
+                }
+            },
+            MyMessage::IncludeAllTracks => {
+                self.data.excluded_tracks.clear();
+                self.data.save_excluded_tracks(settings);
+            },
+            MyMessage::ToggleTrackDetails(r) if self.data.expanded_tracks.contains(r) => {
+                self.data.expanded_tracks.remove(r);
+            },
+            MyMessage::ToggleTrackDetails(r) => {
+                self.data.expanded_tracks.insert(*r);
+            },
+            MyMessage::TrackFilterChanged(track_filter) => {
+                self.data.track_filter = track_filter.clone();
+            },
+            MyMessage::SortTracks(column) => {
+                self.data.track_sort = self.data.track_sort.toggled(*column);
+            },
+            MyMessage::SmartPlaylistNameChanged(name) => {
+                self.data.smart_name = name.clone();
+            },
+            MyMessage::SmartPlaylistExpressionChanged(expression) => {
+                self.data.smart_expression = expression.clone();
+            },
+            MyMessage::SaveSmartPlaylist => {
+                let name = self.data.smart_name.trim().to_string();
+                self.data.smart_error = if name.is_empty() {
+                    Some("# Ошибка: у умного плейлиста должно быть имя".to_string())
+                }
+                else if let Err(err) = smart_playlist::SmartFilter::parse(&self.data.smart_expression) {
+                    Some(format!("# Ошибка в фильтре: {}", err))
+                }
+                else {
+                    let preset = settings::SmartPlaylistPreset {
+                        name,
+                        expression: self.data.smart_expression.clone(),
+                    };
+                    match settings.smart_playlists.iter_mut().find(|existing| existing.name == preset.name) {
+                        Some(existing) => *existing = preset.clone(),
+                        None => settings.smart_playlists.push(preset.clone()),
+                    }
+                    self.data.refresh_smart_playlists(settings);
+                    self.data.selected_playlist = Some(PlaylistEntry::from_smart_preset(&preset).id);
+                    self.data.playlist_test_error_log.clear();
+                    self.data.is_validation_required = true;
+                    self.data.is_exported = false;
+                    settings.save().err().map(|err| format!("# Ошибка: умный плейлист не сохранён на диск: {}", err))
+                };
+            },
+            MyMessage::DeleteSmartPlaylist => {
+                let name = self.data.smart_name.trim().to_string();
+                let count_before = settings.smart_playlists.len();
+                settings.smart_playlists.retain(|preset| preset.name != name);
+                self.data.smart_error = if count_before == settings.smart_playlists.len() {
+                    Some(format!("# Ошибка: нет умного плейлиста с именем '{}'", name))
+                }
+                else {
+                    self.data.refresh_smart_playlists(settings);
+                    if self.data.find_selected_playlist().is_none() {
+                        self.data.selected_playlist = None;
+                        self.data.audio_in_playlist.clear();
+                        self.data.track_resolutions.clear();
+                        self.data.is_validation_required = true;
+                    }
+                    settings.save().err().map(|err| format!("# Ошибка: удаление умного плейлиста не сохранено на диск: {}", err))
+                };
             },
             MyMessage::OpenSelectionMenu => {
                 self.data.is_opened = true;
@@ -715,130 +1925,230 @@ impl SelectionMenu {
             },
             _ => (),
         }
+        command
     }
 }
 
 type AudioEntryId = u32;
 
-// Перемещается назад, идя вперёд на N-i позиций
-// Да, это сумашествие, но Path лишён ExactSizeIterator, а итерироваться по
-// пути доверенным образом хочется
-struct MyPathRevIterator<'a> {
-    path_it: std::iter::Enumerate<std::path::Iter<'a>>,
-    current_position_plus_1: usize,
+// Кэш содержимого папок библиотеки: каждая папка читается с диска не более
+// одного раза, а дальше проверка существования файла - поиск в памяти.
+// Проверка is_file() на каждого кандидата на медленном USB-диске обходилась
+// слишком дорого
+struct LibraryIndex {
+    library_path: PathBuf,
+    dirs: std::collections::HashMap<PathBuf, Option<std::collections::HashSet<std::ffi::OsString>>>,
+    // На windows файловая система не различает регистр, is_file() тоже не
+    // различал - индекс должен вести себя так же
+    case_insensitive: bool,
 }
-impl<'a> Iterator for MyPathRevIterator<'a> {
-    type Item = (usize, &'a std::ffi::OsStr);
-    fn next(&mut self) -> Option<(usize, &'a std::ffi::OsStr)> {
-        //println!("i to skip = {}", self.current_position_plus_1);
-        if 0 == self.current_position_plus_1 {
-            None
+impl LibraryIndex {
+    fn new (library_path: &std::path::Path) -> LibraryIndex {
+        LibraryIndex {
+            library_path: library_path.to_path_buf(),
+            dirs: std::collections::HashMap::new(),
+            case_insensitive: cfg!(windows),
         }
-        else {
-            self.current_position_plus_1 = self.current_position_plus_1 - 1;
-            return self.path_it.clone().skip(self.current_position_plus_1).next();
+    }
+
+    fn contains_file (&mut self, relative_path: &std::path::Path) -> bool {
+        let (dir, file_name) = match (relative_path.parent(), relative_path.file_name()) {
+            (Some(dir), Some(file_name)) => (dir, file_name),
+            _ => return false,
+        };
+        let (library_path, case_insensitive) = (&self.library_path, self.case_insensitive);
+        let files = self.dirs.entry(index_key(dir.as_os_str(), case_insensitive).into()).or_insert_with_key(|dir| {
+            LibraryIndex::read_files(&library_path.join(dir), case_insensitive)
+        });
+        match files {
+            Some(files) => files.contains(&index_key(file_name, case_insensitive)),
+            None => false,
         }
     }
-}
-trait MyPathRevIteratorTrait<'a> {
-    fn  my_rev_from_skip(self) -> MyPathRevIterator<'a>;
-}
-impl<'a> MyPathRevIteratorTrait<'a> for std::iter::Enumerate<std::path::Iter<'a>> {
-    fn  my_rev_from_skip(self) -> MyPathRevIterator<'a> {
-        let total = self.clone().count();
-        MyPathRevIterator {
-            path_it:                 self,
-            current_position_plus_1: total,
+    fn read_files (dir_path: &std::path::Path, case_insensitive: bool) -> Option<std::collections::HashSet<std::ffi::OsString>> {
+        let mut files = std::collections::HashSet::new();
+        for entry in dir_path.read_dir().ok()? {
+            match entry {
+                Ok(entry) => {
+                    let is_file = match entry.file_type() {
+                        Ok(file_type) if file_type.is_symlink() => entry.path().is_file(),
+                        Ok(file_type) => file_type.is_file(),
+                        Err(_) => false,
+                    };
+                    if is_file {
+                        files.insert(index_key(&entry.file_name(), case_insensitive));
+                    }
+                },
+                Err(_) => break,
+            }
         }
+        Some(files)
     }
 }
 
-fn path_from_db_to_real (path_from_db_string: &String, path_to_library: &PathBuf) -> Option<String> {
+fn index_key (name: &std::ffi::OsStr, case_insensitive: bool) -> std::ffi::OsString {
+    if case_insensitive {
+        name.to_string_lossy().to_lowercase().into()
+    }
+    else {
+        name.to_os_string()
+    }
+}
+
+// Путь из базы в путь в библиотеке; проверенные пути складываются в tried -
+// для объяснения в таблице треков
+fn resolve_db_path (path_from_db_string: &str, library_index: &mut LibraryIndex, tried: &mut Vec<track_resolution::Candidate>) -> Option<String> {
     let mut b = [0; 2];
-    let path_from_db_string_copy = path_from_db_string.clone().replace('\\', std::path::MAIN_SEPARATOR.encode_utf8(&mut b));
+    let path_from_db_string_copy = path_from_db_string.replace('\\', std::path::MAIN_SEPARATOR.encode_utf8(&mut b));
     let path_from_db = std::path::Path::new(&path_from_db_string_copy);
+    let components: Vec<&std::ffi::OsStr> = path_from_db.iter().collect();
+    let dir_name_in_library_path = library_index.library_path.file_name()?.to_os_string();
 
-    //println!("Исходные: path_from_db={:?}, path_to_library={:?}", std::path::Path::new(&path_from_db_string_copy).to_string_lossy(), path_to_library.to_string_lossy());
-    //println!("len for db={}, len for lib={}", path_from_db.iter().count(), path_to_library.iter().count());
+    // Последний компонент - имя файла, среди папок идём с конца
+    for i in (0..components.len().saturating_sub(1)).rev() {
+        if components[i] == dir_name_in_library_path {
+            let tail: PathBuf = components[1 + i..].iter().collect();
+            let found = library_index.contains_file(&tail);
+            let path = library_index.library_path.join(&tail).to_string_lossy().into_owned();
+            tried.push(track_resolution::Candidate {path: path.clone(), found});
+            if found {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
 
-    let mut path_component_from_db_it = match path_from_db.parent(){
-        Some(p) => p,
-        _ => return None,
-    }.iter().enumerate().my_rev_from_skip().skip(1);
-    let dir_name_in_library_path = path_to_library.file_name().unwrap();
+// Строка file_t, ошибка извлечения сохраняется для лога
+type AudioDbRow = rusqlite::Result<(String, String, String, AudioMetadata)>;
 
-    while let Some((i, path_from_db_component)) = path_component_from_db_it.next() {
-        if path_from_db_component == dir_name_in_library_path {
-            let tail_it = path_from_db.iter().skip(1 + i);
+// Все нужные строки file_t одним запросом: id из плейлиста складываются во
+// временную таблицу, по ней и делается выборка. Временная таблица создаётся и
+// при открытии базы только для чтения
+fn query_audio_rows (connection: &rusqlite::Connection, schema: &modb_schema::ModbSchema, audio_ids: &Vec<AudioEntryId>) -> rusqlite::Result<std::collections::HashMap<AudioEntryId, Vec<AudioDbRow>>> {
+    connection.execute_batch("CREATE TEMP TABLE IF NOT EXISTS wanted_id_t (wanted_id INTEGER PRIMARY KEY); DELETE FROM wanted_id_t;")?;
+    let transaction = connection.unchecked_transaction()?;
+    {
+        let mut insert_statement = transaction.prepare("INSERT OR IGNORE INTO wanted_id_t (wanted_id) VALUES (?1)")?;
+        for audio_id in audio_ids {
+            insert_statement.execute([audio_id])?;
+        }
+    }
+    transaction.commit()?;
 
-            let dbg_tail = tail_it.clone().fold(PathBuf::new(), |mut accumulation, component|{accumulation.push(component);accumulation}).to_string_lossy().into_owned();
-            let path_candidate = tail_it.fold(path_to_library.clone(), |mut accumulation, component| {accumulation.push(component); accumulation});
+    let optional_columns = schema.optional_columns();
 
-            //println!("tail: {}, canditate: {}", dbg_tail, path_candidate.to_string_lossy());
-            if path_candidate.is_file() {
-                return Some(path_candidate.to_string_lossy().into_owned());
+    let mut result = std::collections::HashMap::new();
+    let mut statement = connection.prepare(format!(
+        "SELECT * FROM (SELECT {} FROM {}) WHERE ft_id IN (SELECT wanted_id FROM wanted_id_t)",
+        schema.select_list(), schema.table
+    ).as_str())?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let audio_id: AudioEntryId = row.get("ft_id")?;
+        let extraction_res = (|| {
+            let mut metadata = AudioMetadata::default();
+            for column in &optional_columns {
+                metadata.set_from_db_column(column, row.get(*column)?);
             }
-        }
+            Ok((row.get("ft_title")?, row.get("ft_artist")?, row.get("ft_path")?, metadata))
+        })();
+        result.entry(audio_id).or_insert_with(Vec::new).push(extraction_res);
     }
-    None
+    Ok(result)
 }
 
-fn get_audio_entries_from_ids (audio_ids: &Vec<AudioEntryId>, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> Vec<AudioEntry> {
-    let mut result = Vec::new();
-    let connection = match rusqlite::Connection::open_with_flags(match &data_files_menu_data.audio_database {
-        MyFileEntry::Valid(filepath) => &filepath.0,
+// Открывает базу и делает query_audio_rows, ошибки уходят в лог
+fn fetch_audio_rows (audio_ids: &Vec<AudioEntryId>, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> Option<std::collections::HashMap<AudioEntryId, Vec<AudioDbRow>>> {
+    let audio_database = match &data_files_menu_data.audio_database {
+        MyFileEntry::Valid(audio_database) => audio_database,
         _ => unreachable!(),
-    }, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY) {
+    };
+    let connection = match modb_snapshot::open_read_only(audio_database.query_path()) {
         Ok(c) => c,
         Err(_) => {
             playlist_test_error_log.push("# Ошибка: повторное открытие файла базы данных не удалось".into());
-            return Vec::new();
+            return None;
+        }
+    };
+    match query_audio_rows(&connection, &audio_database.schema, audio_ids) {
+        Ok(rows) => Some(rows),
+        Err(err) if modb_snapshot::is_locked(&err) => {
+            playlist_test_error_log.push(database_locked_error(&audio_database.file_path).full_error);
+            None
+        },
+        Err(err) => {
+            playlist_test_error_log.push(format!("# Ошибка при запросе к базе данных: {}", err));
+            None
         }
+    }
+}
+
+fn get_audio_entries_from_ids (audio_ids: &Vec<AudioEntryId>, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> Vec<AudioEntry> {
+    resolve_audio_entries(audio_ids, base_menu_data, data_files_menu_data, playlist_test_error_log).0
+}
+
+// Найденные треки и разбор поиска для каждого вхождения плейлиста, в том числе
+// ненайденного; порядок разборов - как у audio_ids
+fn resolve_audio_entries (audio_ids: &Vec<AudioEntryId>, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> (Vec<AudioEntry>, Vec<track_resolution::TrackResolution>) {
+    let mut result = Vec::new();
+    let mut resolutions = Vec::new();
+    let rows_by_id = match fetch_audio_rows(audio_ids, data_files_menu_data, playlist_test_error_log) {
+        Some(rows) => rows,
+        None => return (Vec::new(), Vec::new()),
     };
-    let mut statement = connection.prepare("SELECT ft_title, ft_artist, ft_path FROM file_t WHERE ft_id = ?1").unwrap();
+    let mut library_index = LibraryIndex::new(&base_menu_data.library_path);
     for audio_id in audio_ids {
-        let output_rows = match statement.query_map(&[audio_id], |row| {
-            Ok((row.get("ft_title")?, row.get("ft_artist")?, row.get("ft_path")?))
-        }) {
-            Ok(rows) => rows,
-            Err(_) => {
-                playlist_test_error_log.push(format!("# Ошибка при формировании запроса к базе данных на id={}", audio_id));
+        let mut resolution = track_resolution::TrackResolution::new(*audio_id);
+        let output_rows = match rows_by_id.get(audio_id) {
+            Some(rows) => rows,
+            None => {
+                playlist_test_error_log.push(format!("# Ошибка: в базе данных нет записи для audio_id={}", audio_id));
+                resolutions.push(resolution);
                 continue;
             }
         };
         let mut succeed = false;
         let mut used_path = String::new();
-        for (i, extraction_res) in output_rows.enumerate() {
+        for (i, extraction_res) in output_rows.iter().enumerate() {
+            resolution.db_paths.push(extraction_res.as_ref().ok().map(|(_, _, raw_path, _)| raw_path.clone()));
             match extraction_res {
-                Ok((raw_title, raw_artist, raw_path)) => {
-                    //println!("\n\n# Начало подгона");
-                    if let Some(true_path) = path_from_db_to_real(&raw_path, &base_menu_data.library_path) {
+                Ok((raw_title, raw_artist, raw_path, metadata)) => {
+                    if resolution.title.is_empty() && resolution.artist.is_empty() {
+                        resolution.title = raw_title.clone();
+                        resolution.artist = raw_artist.clone();
+                        resolution.metadata = metadata.clone();
+                    }
+                    if let Some(true_path) = resolve_db_path(raw_path, &mut library_index, &mut resolution.candidates) {
                         if succeed {
                             playlist_test_error_log.push(format!("# Замечание: в базе обнаружено {}-е вхождение audio_id={} ('{}') с путём '{}', используемое вхождение было раньше и вело к '{}'", i, audio_id, raw_title, true_path, used_path));
                             continue;
                         }
                         else {
                             used_path = true_path.clone();
+                            resolution.chosen = Some(true_path.clone());
                             result.push(AudioEntry{
-                                title:  raw_title,
-                                artist: raw_artist,
-                                path:   true_path
+                                id:     *audio_id,
+                                title:  raw_title.clone(),
+                                artist: raw_artist.clone(),
+                                path:   true_path,
+                                metadata: metadata.clone(),
                             });
                             succeed = true;
                         }
                     }
                     else {
                         if succeed {
-                            playlist_test_error_log.push(format!("# Замечание: в базе обнаружено {}-е НЕвалидное вхождение audio_id={} ('{}') (ошибка исправления подгона под окружение пути '{}')", audio_id, i, raw_title, raw_path));
+                            playlist_test_error_log.push(format!("# Замечание: в базе обнаружено {}-е НЕвалидное вхождение audio_id={} ('{}') (ошибка исправления подгона под окружение пути '{}')", i, audio_id, raw_title, raw_path));
                         }
                         else {
-                            playlist_test_error_log.push(format!("# Ошибка при при подгоне под окружение пути '{}' для audio_id={} ('{}'), {}-го результата", raw_path, raw_title, audio_id, i));
+                            playlist_test_error_log.push(format!("# Ошибка при при подгоне под окружение пути '{}' для audio_id={} ('{}'), {}-го результата", raw_path, audio_id, raw_title, i));
                         }
                     }
                 },
                 Err(_) => {
                     if succeed {
-                        playlist_test_error_log.push(format!("# Замечание: в базе обнаружено {}-е НЕвалидное вхождение audio_id={} (ошибка при извлечении результата из базы данных)", audio_id, i));
+                        playlist_test_error_log.push(format!("# Замечание: в базе обнаружено {}-е НЕвалидное вхождение audio_id={} (ошибка при извлечении результата из базы данных)", i, audio_id));
                     }
                     else {
                         playlist_test_error_log.push(format!("# Ошибка при извлечении результата из базы данных для audio_id={}, {}-го результата", audio_id, i));
@@ -846,10 +2156,107 @@ fn get_audio_entries_from_ids (audio_ids: &Vec<AudioEntryId>, base_menu_data: &B
                 }
             }
         }
+        resolution.set_status();
+        resolutions.push(resolution);
+    }
+    (result, resolutions)
+}
+
+// Треки плейлиста для выгрузки только метаданных: файлы ищутся тем же
+// resolve_audio_entries, но сюда попадают и треки, файл которых не нашёлся,
+// их путь в библиотеке остаётся пустым. Порядок и повторы как в плейлисте
+fn get_track_records_from_ids (audio_ids: &Vec<AudioEntryId>, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> Vec<track_list::TrackRecord> {
+    let (_, resolutions) = resolve_audio_entries(audio_ids, base_menu_data, data_files_menu_data, playlist_test_error_log);
+    let mut result = Vec::new();
+    for resolution in resolutions {
+        // ни одна запись не прочиталась - ошибку уже записал resolve_audio_entries
+        let db_path = match resolution.db_paths.iter().flatten().next() {
+            Some(db_path) => db_path.clone(),
+            None => continue,
+        };
+        if resolution.chosen.is_none() {
+            playlist_test_error_log.push(format!("# Замечание: файл для audio_id={} ('{}') не найден, в выгрузку попадут только метаданные", resolution.audio_id, resolution.title));
+        }
+        result.push(track_list::TrackRecord {
+            id:        resolution.audio_id,
+            title:     resolution.title,
+            artist:    resolution.artist,
+            db_path,
+            real_path: resolution.chosen,
+            metadata:  resolution.metadata,
+        });
+    }
+    result
+}
+
+fn get_entries_ids_for_playlist_entry (playlist: &PlaylistEntry, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> Vec<AudioEntryId> {
+    match &playlist.smart_expression {
+        Some(expression) => get_entries_ids_for_smart_playlist(expression, data_files_menu_data, playlist_test_error_log),
+        None => get_entries_ids_for_playlist(
+            &playlist.id,
+            match &data_files_menu_data.playlist_entries {
+                MyFileEntry::Valid(entries) => {
+                    //println!("доступно в списке: {:?}", entries.parsed_value);
+                    &entries.parsed_value
+                },
+                _ => unreachable!(),
+            },
+            playlist_test_error_log
+        ),
+    }
+}
+
+// Плейлисты для экспорта в библиотеку: треки ищутся так же, как при проверке
+// одного плейлиста, ошибки помечаются именем плейлиста
+fn resolve_playlists (playlists: &[PlaylistEntry], base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, log: &mut Vec<String>) -> Vec<library_formats::ResolvedPlaylist> {
+    let mut result = Vec::new();
+    for playlist in playlists {
+        let mut playlist_log = Vec::new();
+        let audio_ids = get_entries_ids_for_playlist_entry(playlist, data_files_menu_data, &mut playlist_log);
+        let audio = get_audio_entries_from_ids(&audio_ids, base_menu_data, data_files_menu_data, &mut playlist_log);
+        log.extend(playlist_log.into_iter().map(|line| format!("{} (плейлист '{}')", line, playlist.name)));
+        log.push(format!("Плейлист '{}': найдено {} из {} треков", playlist.name, audio.len(), audio_ids.len()));
+        result.push(library_formats::ResolvedPlaylist {
+            name: playlist.name.clone(),
+            id:   playlist.id.clone(),
+            audio,
+        });
     }
     result
 }
 
+fn get_entries_ids_for_smart_playlist (expression: &str, data_files_menu_data: &DataFilesMenuData, playlist_test_error_log: &mut Vec<String>) -> Vec<AudioEntryId> {
+    let filter = match smart_playlist::SmartFilter::parse(expression) {
+        Ok(filter) => filter,
+        Err(err) => {
+            playlist_test_error_log.push(format!("# Ошибка в фильтре умного плейлиста: {}", err));
+            return Vec::new();
+        }
+    };
+    let audio_database = match &data_files_menu_data.audio_database {
+        MyFileEntry::Valid(audio_database) => audio_database,
+        _ => unreachable!(),
+    };
+    let ids_res = modb_snapshot::open_read_only(audio_database.query_path())
+        .and_then(|connection| filter.query_ids(&connection, &audio_database.schema));
+    match ids_res {
+        Ok(ids) => {
+            if ids.is_empty() {
+                playlist_test_error_log.push("# Замечание: под фильтр умного плейлиста не подошла ни одна запись базы".to_string());
+            }
+            ids
+        },
+        Err(err) if modb_snapshot::is_locked(&err) => {
+            playlist_test_error_log.push(database_locked_error(&audio_database.file_path).full_error);
+            Vec::new()
+        },
+        Err(err) => {
+            playlist_test_error_log.push(format!("# Ошибка при запросе умного плейлиста к базе данных: {}", err));
+            Vec::new()
+        },
+    }
+}
+
 fn get_entries_ids_for_playlist (playlist_id: &String, playlist_entries_array: &Vec<serde_json::Value>, playlist_test_error_log: &mut Vec<String>) -> Vec<AudioEntryId> {
     let mut result = Vec::new();
     for arr_entry in playlist_entries_array {
@@ -916,17 +2323,20 @@ struct MainApp {
     data_files_menu: DataFilesMenu,
     selection_menu: SelectionMenu,
     log: Log,
+    settings: settings::Settings,
 
     states: MainAppIcedStates,
 }
 
 impl MainApp {
     fn new () -> MainApp {
+        let (settings, settings_log) = settings::Settings::load();
         MainApp {
             base_menu:       BaseMenu::new(),
             data_files_menu: DataFilesMenu::new(),
             selection_menu:  SelectionMenu::new(),
-            log:             Log::new(),
+            log:             Log::new(settings_log),
+            settings,
             states: MainAppIcedStates{
                 scrl_menus: iced::scrollable::State::new(),
             }
@@ -945,13 +2355,57 @@ enum MyMessage {
     SetPlaylistsPath,
     SetPlaylistEntriesPath,
     SetAudioDatabasePath,
+    SetDatabaseSnapshot(bool),
     CloseDataFilesMenu,
     OpenDataFilesMenu,
 
-    SelectPlaylist(String),
+    SelectPlaylist(String), // id плейлиста
+    PlaylistFilterChanged(String),
+    SelectVolumePreset(volumes::VolumePreset),
+    VolumeSizeChanged(String),
+    ExportVolumes,
+    SplitToFit,
+    // ход фонового экспорта и его лог по окончании
+    ExportTick,
+    ExportFinished(Vec<String>),
+    TrackFilterChanged(String),
+    SetTrackIncluded(usize, bool), // индекс в audio_in_playlist
+    SelectBulkArtist(String),
+    SelectBulkFormat(String),
+    SetArtistExcluded(bool), // true - исключить, false - вернуть
+    SetFormatExcluded(bool),
+    IncludeAllTracks,
+    ToggleTrackDetails(usize), // индекс в track_resolutions
+    SortTracks(track_table::TrackColumn),
+    SmartPlaylistNameChanged(String),
+    SmartPlaylistExpressionChanged(String),
+    SaveSmartPlaylist,
+    DeleteSmartPlaylist,
     TestPlaylist,
     Export,
     SetOutputPath,
+    SelectPlaylistFormat(playlist_formats::PlaylistOutput),
+    AddPlaylistTemplate,
+    RemovePlaylistTemplate,
+    SetRelativeLocations(bool),
+    SelectTransferMode(export::TransferMode),
+    SetSyncCompareContent(bool),
+    PlanSync,
+    ApplySync,
+    SelectArchiveFormat(archive::ArchiveFormat),
+    ExportArchive,
+    ExportSharedPool,
+    RollbackExport,
+    VerifyExport,
+    SetVerifyChecksums(bool),
+    SelectCopyWorkers(usize),
+    PostExportHookChanged(String),
+    SelectLibraryFormat(library_formats::LibraryFormat),
+    ExportLibrary(bool), // true - все плейлисты, false - выбранный
+    SelectPlayerLibrary(player_libraries::PlayerLibrary),
+    WriteToPlayer(bool), // true - все плейлисты, false - выбранный
+    SelectTrackListFormat(track_list::TrackListFormat),
+    ExportTrackList(bool), // true - все плейлисты, false - выбранный
     CloseSelectionMenu,
     OpenSelectionMenu,
 }
@@ -962,21 +2416,40 @@ fn gen_text (s: &str) -> iced::Text {
 
 struct Log {
     scrl_state: iced::scrollable::State,
+    // сообщения при загрузке настроек, видны всегда
+    settings_log: Vec<String>,
 }
 
 impl Log {
-    fn new () -> Log {
+    fn new (settings_log: Vec<String>) -> Log {
         Log {
-            scrl_state: iced::scrollable::State::new()
+            scrl_state: iced::scrollable::State::new(),
+            settings_log,
         }
     }
     fn view_scroll (&mut self, base_menu_data: &BaseMenuData, data_files_menu_data: &DataFilesMenuData, selection_menu_data: &SelectionMenuData) -> iced::Scrollable<MyMessage> {
         let mut scroll = iced::Scrollable::new(&mut self.scrl_state);
+        for line in &self.settings_log {
+            scroll = scroll.push(gen_text(line.as_str()).color([1.0, 0.0, 0.0]));
+        }
         if !base_menu_data.validation_required {
             if data_files_menu_data.is_valid() {
+                if let Some(export_progress) = &selection_menu_data.export_progress {
+                    scroll = scroll.push(gen_text(export_progress.summary().as_str()));
+                }
                 if selection_menu_data.is_exported {
                     scroll = scroll.push(gen_text("Готово!").color([0.0, 1.0, 0.0]));
                 }
+                for line in &selection_menu_data.export_log {
+                    scroll = scroll.push(
+                        if line.starts_with("# Ошибка") {
+                            gen_text(line.as_str()).color([1.0, 0.0, 0.0])
+                        }
+                        else {
+                            gen_text(line.as_str())
+                        }
+                    );
+                }
                 for err in &selection_menu_data.playlist_test_error_log {
                     scroll = scroll.push(
                         gen_text(err.as_str()).color([1.0, 0.0, 0.0])
@@ -989,11 +2462,11 @@ impl Log {
                             .push(gen_text(selection_menu_data.audio_in_playlist.len().to_string().as_str()))
                         )
                 }
-                if let Some(selected_playlist_string) = &selection_menu_data.selected_playlist {
+                if let Some(selected_playlist) = selection_menu_data.find_selected_playlist() {
                     scroll = scroll
                         .push(iced::Row::new()
                             .push(gen_text("Выбранный плейлист: "))
-                            .push(gen_text(selected_playlist_string.as_str()))
+                            .push(gen_text(playlist_picker::label(selected_playlist).as_str()))
                         );
                 }
                 scroll = scroll
@@ -1013,6 +2486,13 @@ impl Log {
                         .push(gen_text(selection_menu_data.output_path.to_string_lossy().into_owned().as_str()))
                     );
             }
+            if let MyFileEntry::Valid(audio_database) = &data_files_menu_data.audio_database {
+                scroll = scroll
+                    .push(iced::Row::new()
+                        .push(gen_text("Схема базы данных: "))
+                        .push(gen_text(audio_database.schema.description().as_str()))
+                    );
+            }
             scroll = scroll
                 .push(iced::Row::new()
                     .push(gen_text("Путь до базы данных с музыкой: "))
@@ -1025,7 +2505,14 @@ impl Log {
                 .push(iced::Row::new()
                     .push(gen_text("Путь до файла с плейлистами: "))
                     .push(data_files_menu_data.playlists.to_iced_full_text())
-                );
+                )
+                .push(match &data_files_menu_data.audials_data_dir {
+                    Some(data_dir) => iced::Row::new()
+                        .push(gen_text("Обнаружена версия данных Audials: "))
+                        .push(gen_text(data_dir.as_str())),
+                    None => iced::Row::new()
+                        .push(gen_text("# Ошибка: в папке Audials не найдена LocalAppDataFolder/RapidSolution/Audials_*, пробуется Audials_2015").color([1.0, 0.0, 0.0])),
+                });
 
             scroll = scroll
                 .push(iced::Row::new()
@@ -1064,10 +2551,10 @@ impl iced::Application for MainApp {
         self.data_files_menu.update_if_acceptable(&message);
 
         if self.data_files_menu.data.is_valid() && !self.data_files_menu.data.is_valid_prev {
-            self.selection_menu.data.init(&self.data_files_menu.data);
+            self.selection_menu.data.init(&self.data_files_menu.data, &self.settings);
         }
         self.data_files_menu.data.is_valid_prev = self.data_files_menu.data.is_valid();
-        self.selection_menu.update_if_acceptable(&message, &self.base_menu.data, &self.data_files_menu.data);
+        let command = self.selection_menu.update_if_acceptable(&message, &self.base_menu.data, &self.data_files_menu.data, &mut self.settings);
 
         if self.base_menu.data.validation_required {
             self.data_files_menu.reset_me();
@@ -1076,7 +2563,7 @@ impl iced::Application for MainApp {
         else if !self.data_files_menu.data.is_valid() {
             self.selection_menu.reset_me();
         }
-        iced::Command::none()
+        command
     }
     fn view (&mut self) -> iced::Element<Self::Message> {
         let mut menus = iced::Scrollable::new(&mut self.states.scrl_menus);
@@ -1099,3 +2586,77 @@ impl iced::Application for MainApp {
             .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // Библиотека во временной папке: пути относительно неё, файлы пустые
+    fn library_with_files (name: &str, files: &[&str]) -> PathBuf {
+        let library_path = std::env::temp_dir().join(format!("audials-index-test-{}-{}", name, std::process::id())).join("Audials Music");
+        let _ = std::fs::remove_dir_all(&library_path);
+        for file in files {
+            let path = library_path.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"").unwrap();
+        }
+        library_path
+    }
+
+    #[test]
+    fn db_path_is_remapped_into_library () {
+        let library_path = library_with_files("remap", &["Rock/Queen/a.mp3"]);
+        let mut library_index = LibraryIndex::new(&library_path);
+        let mut tried = Vec::new();
+        let resolved = resolve_db_path("C:\\Users\\old\\Music\\Audials Music\\Rock\\Queen\\a.mp3", &mut library_index, &mut tried);
+        assert_eq!(Some(library_path.join("Rock").join("Queen").join("a.mp3").to_string_lossy().into_owned()), resolved);
+        assert_eq!(1, tried.len());
+        assert!(tried[0].found);
+        assert_eq!(None, resolve_db_path("C:\\Music\\Other\\a.mp3", &mut library_index, &mut Vec::new()));
+        let _ = std::fs::remove_dir_all(library_path.parent().unwrap());
+    }
+
+    #[test]
+    fn nested_library_name_falls_back_to_outer_candidate () {
+        let library_path = library_with_files("fallback", &["old/Audials Music/b.mp3"]);
+        let mut library_index = LibraryIndex::new(&library_path);
+        let mut tried = Vec::new();
+        let resolved = resolve_db_path("D:\\Audials Music\\old\\Audials Music\\b.mp3", &mut library_index, &mut tried);
+        assert_eq!(Some(library_path.join("old").join("Audials Music").join("b.mp3").to_string_lossy().into_owned()), resolved);
+        assert_eq!(vec![false, true], tried.iter().map(|candidate| candidate.found).collect::<Vec<bool>>());
+        let _ = std::fs::remove_dir_all(library_path.parent().unwrap());
+    }
+
+    #[test]
+    fn case_insensitive_index_ignores_case_of_file_names () {
+        let library_path = library_with_files("case", &["rock/Song.MP3"]);
+        let mut library_index = LibraryIndex::new(&library_path);
+        library_index.case_insensitive = true;
+        assert!(library_index.contains_file(Path::new("rock/song.mp3")));
+        assert!(library_index.contains_file(Path::new("ROCK/SONG.mp3")));
+        assert!(!library_index.contains_file(Path::new("rock/other.mp3")));
+        let mut library_index = LibraryIndex::new(&library_path);
+        library_index.case_insensitive = false;
+        assert!(library_index.contains_file(Path::new("rock/Song.MP3")));
+        assert!(!library_index.contains_file(Path::new("rock/song.mp3")));
+        let _ = std::fs::remove_dir_all(library_path.parent().unwrap());
+    }
+
+    // Цель индекса - тысячи треков быстро: каждая папка читается один раз,
+    // а дальше проверки идут в памяти
+    #[test]
+    fn thousands_of_tracks_read_each_dir_once () {
+        let files: Vec<String> = (0..3000).map(|i| format!("Album {}/{}.mp3", i % 10, i)).collect();
+        let library_path = library_with_files("many", &files.iter().map(|file| file.as_str()).collect::<Vec<&str>>());
+        let mut library_index = LibraryIndex::new(&library_path);
+        let started = std::time::Instant::now();
+        for i in 0..3000 {
+            let db_path = format!("C:\\Old\\Audials Music\\Album {}\\{}.mp3", i % 10, i);
+            assert!(resolve_db_path(&db_path, &mut library_index, &mut Vec::new()).is_some());
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(10, library_index.dirs.len());
+        let _ = std::fs::remove_dir_all(library_path.parent().unwrap());
+    }
+}
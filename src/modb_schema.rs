@@ -0,0 +1,221 @@
+// Распознавание схемы базы MusicOrganizer/modb. В разных версиях Audials
+// таблица и колонки могут называться по-разному, поэтому имена не зашиты в
+// запросы, а определяются по sqlite_master и pragma table_info. Дальше все
+// запросы идут через ModbSchema::select_list, где найденные колонки
+// переименованы в канонические ft_*
+//
+// Откуда имена. Audials схему modb нигде не описывает. На настоящей базе
+// (Audials 2015, папка данных Audials_2015) встречались только таблица file_t и
+// колонки ft_id, ft_title, ft_artist, ft_path - их программа читала с самого
+// начала. Все остальные имена - и необязательные колонки, и альтернативные
+// варианты - выведены по тому же соглашению ft_<поле> и на реальной базе не
+// проверены: необязательная колонка, которой нет, просто не показывается, а
+// альтернативы обязательных - догадка на случай переименования в другой
+// версии. Непроверенные имена помечаются в описании схемы
+
+use crate::MyResult;
+
+// Каноническое имя колонки (под ним значение читается из строки результата)
+// и известные варианты: имя колонки в базе и выражение для SELECT.
+// verified - первый вариант встречался на настоящей базе
+struct KnownColumn {
+    canonical: &'static str,
+    required:  bool,
+    verified:  bool,
+    variants:  &'static [(&'static str, &'static str)],
+}
+
+// file_t наблюдалась, files_t - догадка
+const KNOWN_TABLES: [&str; 2] = ["file_t", "files_t"];
+
+// Остальные варианты всегда не проверены (см. начало файла)
+const KNOWN_COLUMNS: [KnownColumn; 14] = [
+    KnownColumn {canonical: "ft_id",          required: true,  verified: true,  variants: &[("ft_id", "ft_id"), ("ft_fileid", "ft_fileid")]},
+    KnownColumn {canonical: "ft_title",       required: true,  verified: true,  variants: &[("ft_title", "ft_title"), ("ft_name", "ft_name")]},
+    KnownColumn {canonical: "ft_artist",      required: true,  verified: true,  variants: &[("ft_artist", "ft_artist"), ("ft_interpret", "ft_interpret")]},
+    KnownColumn {canonical: "ft_path",        required: true,  verified: true,  variants: &[("ft_path", "ft_path"), ("ft_filepath", "ft_filepath"), ("ft_location", "ft_location")]},
+    KnownColumn {canonical: "ft_album",       required: false, verified: false, variants: &[("ft_album", "ft_album"), ("ft_albumname", "ft_albumname")]},
+    KnownColumn {canonical: "ft_albumartist", required: false, verified: false, variants: &[("ft_albumartist", "ft_albumartist"), ("ft_album_artist", "ft_album_artist")]},
+    KnownColumn {canonical: "ft_duration",    required: false, verified: false, variants: &[("ft_duration", "ft_duration"), ("ft_length", "ft_length"), ("ft_durationms", "ft_durationms / 1000")]},
+    KnownColumn {canonical: "ft_genre",       required: false, verified: false, variants: &[("ft_genre", "ft_genre")]},
+    KnownColumn {canonical: "ft_year",        required: false, verified: false, variants: &[("ft_year", "ft_year"), ("ft_releaseyear", "ft_releaseyear")]},
+    KnownColumn {canonical: "ft_tracknumber", required: false, verified: false, variants: &[("ft_tracknumber", "ft_tracknumber"), ("ft_track", "ft_track")]},
+    KnownColumn {canonical: "ft_discnumber",  required: false, verified: false, variants: &[("ft_discnumber", "ft_discnumber"), ("ft_disc", "ft_disc")]},
+    KnownColumn {canonical: "ft_bitrate",     required: false, verified: false, variants: &[("ft_bitrate", "ft_bitrate")]},
+    KnownColumn {canonical: "ft_size",        required: false, verified: false, variants: &[("ft_size", "ft_size"), ("ft_filesize", "ft_filesize")]},
+    KnownColumn {canonical: "ft_dateadded",   required: false, verified: false, variants: &[("ft_dateadded", "ft_dateadded"), ("ft_added", "ft_added"), ("ft_createdate", "ft_createdate")]},
+];
+
+// Отпечатки версий Audials: таблица, user_version и полный набор колонок
+// таблицы, снятые с настоящей базы этой версии. Версия считается
+// распознанной, только если совпало всё. Отпечатков пока нет ни одного: у
+// базы, по которой писалась программа, они не записаны, а наличие file_t само
+// по себе версию не определяет. Описание схемы выводит отпечаток базы - по
+// нему список и пополняется
+// (версия Audials, таблица, user_version, колонки таблицы по алфавиту)
+type VersionFingerprint = (&'static str, &'static str, i64, &'static [&'static str]);
+const KNOWN_VERSIONS: [VersionFingerprint; 0] = [];
+
+pub struct ModbSchema {
+    pub table: String,
+    // (выражение, каноническое имя) для каждой найденной колонки
+    columns: Vec<(&'static str, &'static str)>,
+    // все колонки таблицы по алфавиту - для отпечатка версии
+    table_columns: Vec<String>,
+    user_version: i64,
+    schema_version: i64,
+}
+
+fn get_tables (connection: &rusqlite::Connection) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare("SELECT name FROM sqlite_master WHERE type = 'table'")?;
+    let tables = statement.query_map([], |row| row.get("name"))?.collect();
+    tables
+}
+
+fn get_table_columns (connection: &rusqlite::Connection, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare(format!("PRAGMA table_info({})", table).as_str())?;
+    let columns = statement.query_map([], |row| row.get("name"))?.collect();
+    columns
+}
+
+impl ModbSchema {
+    pub fn detect (connection: &rusqlite::Connection) -> MyResult<ModbSchema> {
+        let tables = get_tables(connection).map_err(|err| format!("не удалось прочитать sqlite_master: {}", err))?;
+        let table = match KNOWN_TABLES.iter().find(|known| tables.iter().any(|table| table == *known)) {
+            Some(table) => table.to_string(),
+            None => return Err(format!(
+                "нет таблицы с файлами, ожидалась одна из: {}; в базе есть таблицы: {}",
+                KNOWN_TABLES.join(", "), tables.join(", ")
+            )),
+        };
+        let mut table_columns = get_table_columns(connection, &table).map_err(|err| format!("не удалось прочитать колонки таблицы {}: {}", table, err))?;
+        table_columns.sort();
+
+        let mut columns = Vec::new();
+        for known in &KNOWN_COLUMNS {
+            match known.variants.iter().find(|(name, _)| table_columns.iter().any(|column| column == name)) {
+                Some((_, expression)) => columns.push((*expression, known.canonical)),
+                None if known.required => return Err(format!(
+                    "в таблице {} нет колонки {}, ожидалась одна из: {}; в таблице есть колонки: {}",
+                    table, known.canonical,
+                    known.variants.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", "),
+                    table_columns.join(", ")
+                )),
+                None => (),
+            }
+        }
+
+        let user_version = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap_or(0);
+        let schema_version = connection.query_row("PRAGMA schema_version", [], |row| row.get(0)).unwrap_or(0);
+        Ok(ModbSchema {
+            table,
+            columns,
+            table_columns,
+            user_version,
+            schema_version,
+        })
+    }
+    // Список для SELECT, где все найденные колонки названы каноническими именами
+    pub fn select_list (&self) -> String {
+        self.columns.iter()
+            .map(|(expression, canonical)| format!("{} AS {}", expression, canonical))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+    // Найденные необязательные колонки, канонические имена
+    pub fn optional_columns (&self) -> Vec<&'static str> {
+        KNOWN_COLUMNS.iter()
+            .filter(|known| !known.required)
+            .map(|known| known.canonical)
+            .filter(|canonical| self.columns.iter().any(|(_, column)| column == canonical))
+            .collect()
+    }
+    // Версия Audials по отпечатку: таблица, user_version и набор колонок
+    pub fn audials_version (&self) -> Option<&'static str> {
+        KNOWN_VERSIONS.iter()
+            .find(|(_, table, user_version, columns)| {
+                *table == self.table && *user_version == self.user_version && columns.iter().eq(self.table_columns.iter())
+            })
+            .map(|(audials, _, _, _)| *audials)
+    }
+    pub fn description (&self) -> String {
+        let missing: Vec<&str> = KNOWN_COLUMNS.iter()
+            .map(|known| known.canonical)
+            .filter(|canonical| !self.columns.iter().any(|(_, column)| column == canonical))
+            .collect();
+        format!(
+            "{}; таблица {}, user_version={}, schema_version={}, колонки: {}{}{}",
+            match self.audials_version() {
+                Some(audials_version) => format!("схема как у {}", audials_version),
+                None => "версия неизвестна".to_string(),
+            },
+            self.table, self.user_version, self.schema_version,
+            self.columns.iter().map(|(expression, canonical)| {
+                let known = KNOWN_COLUMNS.iter().find(|known| known.canonical == *canonical).unwrap();
+                let name = if expression == canonical {
+                    canonical.to_string()
+                }
+                else {
+                    format!("{} <- {}", canonical, expression)
+                };
+                if known.verified && *expression == known.variants[0].1 {
+                    name
+                }
+                else {
+                    format!("{} (непроверенное имя)", name)
+                }
+            }).collect::<Vec<String>>().join(", "),
+            if missing.is_empty() {
+                String::new()
+            }
+            else {
+                format!("; нет колонок: {}", missing.join(", "))
+            },
+            match self.audials_version() {
+                Some(_) => String::new(),
+                None => format!("; отпечаток для распознавания версии: все колонки {}: {}", self.table, self.table_columns.join(", ")),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema_of (create_table: &str) -> MyResult<ModbSchema> {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        connection.execute_batch(create_table).unwrap();
+        ModbSchema::detect(&connection)
+    }
+
+    #[test]
+    fn file_t_alone_does_not_identify_version () {
+        let schema = schema_of("CREATE TABLE file_t (ft_id INTEGER, ft_title TEXT, ft_artist TEXT, ft_path TEXT, ft_album TEXT);").unwrap();
+        assert_eq!(None, schema.audials_version());
+        let description = schema.description();
+        assert!(description.starts_with("версия неизвестна"));
+        assert!(description.contains("ft_path, ft_album (непроверенное имя)"));
+        assert!(description.contains("все колонки file_t: ft_album, ft_artist, ft_id, ft_path, ft_title"));
+    }
+
+    #[test]
+    fn alternative_names_are_mapped_and_marked () {
+        let schema = schema_of("CREATE TABLE files_t (ft_fileid INTEGER, ft_name TEXT, ft_artist TEXT, ft_location TEXT, ft_durationms INTEGER);").unwrap();
+        assert_eq!("files_t", schema.table);
+        assert!(schema.select_list().contains("ft_fileid AS ft_id"));
+        assert!(schema.select_list().contains("ft_durationms / 1000 AS ft_duration"));
+        assert_eq!(vec!["ft_duration"], schema.optional_columns());
+        let description = schema.description();
+        assert!(description.contains("ft_id <- ft_fileid (непроверенное имя)"));
+        assert!(description.contains("ft_artist, "));
+    }
+
+    #[test]
+    fn missing_table_or_column_is_named () {
+        let err = schema_of("CREATE TABLE other_t (x INTEGER);").err().unwrap();
+        assert!(err.contains("other_t"));
+        let err = schema_of("CREATE TABLE file_t (ft_id INTEGER, ft_title TEXT, ft_artist TEXT);").err().unwrap();
+        assert!(err.contains("нет колонки ft_path"));
+    }
+}
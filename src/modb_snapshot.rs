@@ -0,0 +1,152 @@
+// Открытие modb и чтение её копии. Пока Audials запущен, он держит базу и её
+// -wal, а на смонтированном только для чтения диске SQLite вообще не может
+// открыть базу в режиме WAL на месте. Поэтому есть режим снимка: база вместе
+// с -wal копируется во временную папку, там WAL сливается в основной файл, и
+// дальше все запросы идут к копии
+
+use std::path::{Path, PathBuf};
+
+use crate::MyResult;
+
+// Сколько SQLite ждёт освобождения блокировки, прежде чем вернуть SQLITE_BUSY
+pub const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+const COPY_ATTEMPTS: usize = 3;
+
+static SNAPSHOT_COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+pub fn sidecar_path (modb_path: &Path, suffix: &str) -> PathBuf {
+    let mut sidecar = modb_path.as_os_str().to_os_string();
+    sidecar.push(suffix);
+    sidecar.into()
+}
+
+pub fn open_read_only (path: &Path) -> rusqlite::Result<rusqlite::Connection> {
+    let connection = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(connection)
+}
+
+pub fn is_locked (err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error {code: rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked, ..}, _)
+    )
+}
+
+// Копия базы во временной папке, папка удаляется вместе со снимком. Копия
+// не обновляется сама, пока Audials пишет в базу, поэтому запоминается, с
+// какого состояния базы и -wal она снята и когда
+pub struct ModbSnapshot {
+    dir:       PathBuf,
+    file_path: PathBuf,
+    modb_path: PathBuf,
+    // размер и время изменения базы и её -wal на момент копирования
+    source_stamps: Vec<Option<(u64, std::time::SystemTime)>>,
+    created:   std::time::SystemTime,
+}
+impl ModbSnapshot {
+    pub fn create (modb_path: &Path) -> MyResult<ModbSnapshot> {
+        let dir = std::env::temp_dir().join(format!(
+            "audials-playlist-exporter-{}-{}",
+            std::process::id(), SNAPSHOT_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        ));
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            return Err(format!("не удалось создать временную папку '{}': {}", dir.to_string_lossy(), err));
+        }
+        let mut snapshot = ModbSnapshot {
+            file_path: dir.join("modb"),
+            dir,
+            modb_path: modb_path.to_path_buf(),
+            source_stamps: Vec::new(),
+            created:   std::time::SystemTime::now(),
+        };
+        snapshot.copy_from(modb_path)?;
+
+        // После слияния WAL копия переводится в обычный журнал, чтобы её
+        // можно было открывать только для чтения без -shm
+        let connection = match rusqlite::Connection::open(&snapshot.file_path) {
+            Ok(connection) => connection,
+            Err(err) => return Err(format!("не удалось открыть копию базы '{}': {}", snapshot.file_path.to_string_lossy(), err)),
+        };
+        let checkpoint_res = connection.busy_timeout(BUSY_TIMEOUT)
+            .and_then(|_| connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())))
+            .and_then(|_| connection.query_row("PRAGMA journal_mode=DELETE", [], |_| Ok(())));
+        if let Err(err) = checkpoint_res {
+            return Err(format!("не удалось слить -wal в копию базы: {}", err));
+        }
+        Ok(snapshot)
+    }
+    pub fn file_path (&self) -> &PathBuf {
+        &self.file_path
+    }
+    // База или её -wal изменились после копирования - копия устарела
+    pub fn is_stale (&self) -> bool {
+        let sources = [self.modb_path.clone(), sidecar_path(&self.modb_path, "-wal")];
+        sources.iter().map(|source| file_stamp(source)).collect::<Vec<_>>() != self.source_stamps
+    }
+    pub fn age (&self) -> std::time::Duration {
+        self.created.elapsed().unwrap_or_default()
+    }
+    // Audials может писать в базу прямо во время копирования, тогда копия
+    // выйдет несогласованной - такое копирование повторяется. -shm не
+    // копируется: индекс WAL SQLite восстанавливает по самому -wal, а -shm
+    // работающего Audials мог устареть ещё до окончания копирования
+    fn copy_from (&mut self, modb_path: &Path) -> MyResult<()> {
+        let sources = [
+            (modb_path.to_path_buf(), self.file_path.clone()),
+            (sidecar_path(modb_path, "-wal"), sidecar_path(&self.file_path, "-wal")),
+        ];
+        for _ in 0..COPY_ATTEMPTS {
+            let stamps_before: Vec<_> = sources.iter().map(|(source, _)| file_stamp(source)).collect();
+            for (source, destination) in &sources {
+                if !source.is_file() {
+                    let _ = std::fs::remove_file(destination);
+                    continue;
+                }
+                if let Err(err) = std::fs::copy(source, destination) {
+                    return Err(format!("не удалось скопировать '{}' во временную папку: {}", source.to_string_lossy(), err));
+                }
+            }
+            let stamps_after: Vec<_> = sources.iter().map(|(source, _)| file_stamp(source)).collect();
+            if stamps_before == stamps_after {
+                self.source_stamps = stamps_after;
+                return Ok(());
+            }
+        }
+        Err(format!("база '{}' менялась во время каждой из {} попыток копирования", modb_path.to_string_lossy(), COPY_ATTEMPTS))
+    }
+}
+impl Drop for ModbSnapshot {
+    fn drop (&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn file_stamp (path: &Path) -> Option<(u64, std::time::SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_goes_stale_when_wal_appears () {
+        let dir = std::env::temp_dir().join(format!("audials-snapshot-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let modb_path = dir.join("modb");
+        rusqlite::Connection::open(&modb_path).unwrap().execute_batch("CREATE TABLE file_t (ft_id INTEGER);").unwrap();
+        let snapshot = ModbSnapshot::create(&modb_path).unwrap();
+        assert!(!snapshot.is_stale());
+        assert!(snapshot.file_path().is_file());
+        std::fs::write(sidecar_path(&modb_path, "-wal"), b"").unwrap();
+        assert!(snapshot.is_stale());
+        let snapshot_dir = snapshot.dir.clone();
+        drop(snapshot);
+        assert!(!snapshot_dir.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,402 @@
+// Запись плейлистов прямо в библиотеки плееров для linux. В отличие от
+// library_formats здесь дописывается уже существующий файл плеера, поэтому:
+// оригинал сначала сохраняется в резервную копию, изменения делаются в
+// рабочей копии, которая потом заменяет оригинал, а плейлист с тем же именем
+// не добавляется второй раз, а заменяется - повторный запуск не плодит дубли
+
+use std::path::{Path, PathBuf};
+
+use crate::MyResult;
+use crate::library_formats::ResolvedPlaylist;
+use crate::playlist_formats::escape_xml;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayerLibrary {
+    Rhythmbox,
+    Strawberry,
+    Clementine,
+}
+impl PlayerLibrary {
+    pub const ALL: [PlayerLibrary; 3] = [
+        PlayerLibrary::Rhythmbox, PlayerLibrary::Strawberry, PlayerLibrary::Clementine,
+    ];
+    pub fn default_path (self) -> Option<PathBuf> {
+        match self {
+            PlayerLibrary::Rhythmbox  => Some(dirs_next::data_dir()?.join("rhythmbox/playlists.xml")),
+            PlayerLibrary::Strawberry => Some(dirs_next::data_dir()?.join("strawberry/strawberry/strawberry.db")),
+            PlayerLibrary::Clementine => Some(dirs_next::config_dir()?.join("Clementine/clementine.db")),
+        }
+    }
+    // Возвращает строки для лога
+    pub fn write_playlists (self, library_path: &Path, playlists: &[ResolvedPlaylist]) -> MyResult<Vec<String>> {
+        let mut log = Vec::new();
+        if PlayerLibrary::Rhythmbox == self && is_process_running("rhythmbox") {
+            log.push("# Замечание: Rhythmbox запущен - закройте его до проверки плейлистов, при выходе он перезапишет playlists.xml своим списком, и записанные плейлисты пропадут".to_string());
+        }
+        if library_path.exists() {
+            prepare_original(self, library_path)?;
+            let backup_path = backup(library_path)?;
+            log.push(format!("Резервная копия: {}", backup_path.to_string_lossy()));
+        }
+        let work_path = sidecar(library_path, ".audials-tmp");
+        if library_path.exists() {
+            copy(library_path, &work_path)?;
+        }
+        let write_res = match self {
+            PlayerLibrary::Rhythmbox => write_rhythmbox(&work_path, playlists, &mut log),
+            PlayerLibrary::Strawberry | PlayerLibrary::Clementine => write_strawberry(&work_path, playlists, &mut log),
+        };
+        if let Err(err) = write_res {
+            let _ = std::fs::remove_file(&work_path);
+            return Err(err);
+        }
+        std::fs::rename(&work_path, library_path)
+            .map_err(|err| format!("не удалось заменить '{}' рабочей копией: {}", library_path.to_string_lossy(), err))?;
+        Ok(log)
+    }
+}
+impl std::fmt::Display for PlayerLibrary {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PlayerLibrary::Rhythmbox  => "Rhythmbox (playlists.xml)",
+            PlayerLibrary::Strawberry => "Strawberry (strawberry.db)",
+            PlayerLibrary::Clementine => "Clementine (clementine.db)",
+        })
+    }
+}
+
+fn sidecar (path: &Path, suffix: &str) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(suffix);
+    sidecar.into()
+}
+
+fn copy (from: &Path, to: &Path) -> MyResult<()> {
+    std::fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|err| format!("не удалось скопировать '{}' в '{}': {}", from.to_string_lossy(), to.to_string_lossy(), err))
+}
+
+fn backup (library_path: &Path) -> MyResult<PathBuf> {
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    // при повторном запуске в ту же секунду прежняя копия не затирается
+    let mut backup_path = sidecar(library_path, &format!(".bak-{}", timestamp));
+    let mut number = 1;
+    while backup_path.exists() {
+        number += 1;
+        backup_path = sidecar(library_path, &format!(".bak-{}-{}", timestamp, number));
+    }
+    copy(library_path, &backup_path)?;
+    Ok(backup_path)
+}
+
+// Перед копированием база плеера должна быть целиком в основном файле: WAL
+// сливается, а если это не удаётся - плеер запущен и держит базу, и писать в
+// неё нельзя, иначе плеер перезапишет изменения или испортит файл
+fn prepare_original (player: PlayerLibrary, library_path: &Path) -> MyResult<()> {
+    if PlayerLibrary::Rhythmbox == player {
+        return Ok(());
+    }
+    let connection = rusqlite::Connection::open(library_path)
+        .map_err(|err| format!("не удалось открыть базу '{}': {}", library_path.to_string_lossy(), err))?;
+    let checkpoint_res = connection.busy_timeout(crate::modb_snapshot::BUSY_TIMEOUT)
+        .and_then(|_| connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get::<_, i64>(0)));
+    match checkpoint_res {
+        Ok(0) => Ok(()),
+        Ok(_) => Err(format!("база '{}' занята, закройте {}", library_path.to_string_lossy(), player)),
+        Err(err) if crate::modb_snapshot::is_locked(&err) => Err(format!("база '{}' заблокирована, закройте {}", library_path.to_string_lossy(), player)),
+        Err(err) => Err(format!("не удалось подготовить базу '{}': {}", library_path.to_string_lossy(), err)),
+    }
+}
+
+// Rhythmbox хранит плейлисты в памяти и при выходе пишет playlists.xml
+// заново, а не дописывает: запущенный плеер затрёт то, что записано сейчас
+#[cfg(target_os = "linux")]
+fn is_process_running (name: &str) -> bool {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()))
+        .any(|entry| std::fs::read_to_string(entry.path().join("comm")).map_or(false, |comm| comm.trim_end() == name))
+}
+#[cfg(not(target_os = "linux"))]
+fn is_process_running (_name: &str) -> bool {
+    false
+}
+
+// URI как у g_filename_to_uri из GLib: Rhythmbox сопоставляет плейлист с
+// треками своей базы по строке URI, и кодирование должно совпадать
+fn glib_file_uri (path: &Path) -> String {
+    let mut result = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/'
+            | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
+
+fn unescape_xml (text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+// Элементы <playlist> в тексте playlists.xml: (начало, конец, имя)
+fn find_rhythmbox_playlists (xml: &str) -> Vec<(usize, usize, String)> {
+    let mut result = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = xml[position..].find("<playlist ") {
+        let start = position + offset;
+        let tag_end = match xml[start..].find('>') {
+            Some(tag_end) => start + tag_end,
+            None => break,
+        };
+        let tag = &xml[start..tag_end];
+        let end = if tag.ends_with('/') {
+            tag_end + 1
+        }
+        else {
+            match xml[tag_end..].find("</playlist>") {
+                Some(close) => tag_end + close + "</playlist>".len(),
+                None => break,
+            }
+        };
+        let name = tag.find(" name=\"")
+            .map(|name_start| &tag[name_start + " name=\"".len()..])
+            .and_then(|rest| rest.find('"').map(|name_end| unescape_xml(&rest[..name_end])))
+            .unwrap_or_default();
+        result.push((start, end, name));
+        position = end;
+    }
+    result
+}
+
+fn write_rhythmbox (work_path: &Path, playlists: &[ResolvedPlaylist], log: &mut Vec<String>) -> MyResult<()> {
+    let mut xml = if work_path.exists() {
+        std::fs::read_to_string(work_path).map_err(|err| format!("не удалось прочитать '{}': {}", work_path.to_string_lossy(), err))?
+    }
+    else {
+        String::new()
+    };
+    // пустой файл остаётся, если Rhythmbox упал при записи
+    if xml.trim().is_empty() {
+        xml = String::from("<?xml version=\"1.0\"?>\n<rhythmdb-playlists>\n</rhythmdb-playlists>\n");
+    }
+    let mut new_elements: Vec<(&str, String)> = playlists.iter().map(|playlist| {
+        let mut element = format!(
+            "<playlist name=\"{}\" show-browser=\"true\" browser-position=\"180\" search-type=\"search-match\" type=\"static\">\n",
+            escape_xml(&playlist.name)
+        );
+        for audio in &playlist.audio {
+            element.push_str(&format!("    <location>{}</location>\n", escape_xml(&glib_file_uri(Path::new(&audio.path)))));
+        }
+        element.push_str("  </playlist>");
+        (playlist.name.as_str(), element)
+    }).collect();
+    // одноимённый плейлист заменяется на своём месте, его повторы удаляются
+    // вместе со строкой; правки идут с конца, чтобы не сбить позиции
+    let existing = find_rhythmbox_playlists(&xml);
+    for (i, (start, end, name)) in existing.iter().enumerate().rev() {
+        let new_element = match new_elements.iter().position(|(new_name, _)| new_name == name) {
+            Some(new_element) => new_element,
+            None => continue,
+        };
+        if existing[..i].iter().any(|(_, _, earlier_name)| earlier_name == name) {
+            let line_start = xml[..*start].trim_end_matches([' ', '\t']).len();
+            let line_end = if xml[*end..].starts_with('\n') {end + 1} else {*end};
+            xml.replace_range(line_start..line_end, "");
+        }
+        else {
+            xml.replace_range(start..end, &new_elements.remove(new_element).1);
+            log.push(format!("Плейлист '{}' уже был в Rhythmbox и заменён", name));
+        }
+    }
+    let insert_position = xml.rfind("</rhythmdb-playlists>")
+        .ok_or("файл не похож на playlists.xml Rhythmbox: нет </rhythmdb-playlists>".to_string())?;
+    let appended: String = new_elements.iter().map(|(_, element)| format!("  {}\n", element)).collect();
+    xml.insert_str(insert_position, &appended);
+    for playlist in playlists {
+        log.push(format!("Плейлист '{}' записан в Rhythmbox, треков: {}", playlist.name, playlist.audio.len()));
+    }
+    std::fs::write(work_path, xml).map_err(|err| format!("не удалось записать '{}': {}", work_path.to_string_lossy(), err))
+}
+
+// Strawberry и Clementine - потомки одного кода, схемы у них похожи, но не
+// совпадают. Поэтому колонки playlist_items берутся из pragma table_info, а
+// пишутся только известные. Отличие, которое важно: у Clementine путь в
+// filename и тип строки 'File', у Strawberry путь в url, а тип и source -
+// число Song::Source, 1 для локального файла. Длительность в наносекундах
+fn write_strawberry (work_path: &Path, playlists: &[ResolvedPlaylist], log: &mut Vec<String>) -> MyResult<()> {
+    let to_string = |err: rusqlite::Error| format!("ошибка базы плеера '{}': {}", work_path.to_string_lossy(), err);
+    if !work_path.exists() {
+        return Err(format!("база плеера не найдена: '{}'", work_path.to_string_lossy()));
+    }
+    let mut connection = rusqlite::Connection::open(work_path).map_err(to_string)?;
+    let columns: Vec<String> = {
+        let mut statement = connection.prepare("PRAGMA table_info(playlist_items)").map_err(to_string)?;
+        let columns = statement.query_map([], |row| row.get("name")).map_err(to_string)?.collect::<rusqlite::Result<Vec<String>>>().map_err(to_string)?;
+        columns
+    };
+    if !columns.iter().any(|column| "playlist" == column) {
+        return Err(format!("в '{}' нет таблицы playlist_items - это не база Strawberry/Clementine", work_path.to_string_lossy()));
+    }
+    let has_column = |name: &str| columns.iter().any(|column| column == name);
+    let is_strawberry = has_column("url");
+
+    let transaction = connection.transaction().map_err(to_string)?;
+    for playlist in playlists {
+        let existing_id: Option<i64> = match transaction.query_row(
+            "SELECT ROWID FROM playlists WHERE name = ?1 ORDER BY ROWID LIMIT 1", [&playlist.name], |row| row.get(0)
+        ) {
+            Ok(playlist_id) => Some(playlist_id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(err) => return Err(to_string(err)),
+        };
+        let playlist_id = match existing_id {
+            Some(playlist_id) => {
+                transaction.execute("DELETE FROM playlist_items WHERE playlist = ?1", [playlist_id]).map_err(to_string)?;
+                log.push(format!("Плейлист '{}' уже был в базе плеера, его содержимое заменено", playlist.name));
+                playlist_id
+            },
+            None => {
+                transaction.execute("INSERT INTO playlists (name) VALUES (?1)", [&playlist.name]).map_err(to_string)?;
+                transaction.last_insert_rowid()
+            },
+        };
+        for audio in &playlist.audio {
+            let mut values: Vec<(&str, rusqlite::types::Value)> = vec![("playlist", playlist_id.into())];
+            if is_strawberry {
+                values.push(("type", 1i64.into()));
+                values.push(("source", 1i64.into()));
+                values.push(("url", crate::playlist_formats::file_url(Path::new(&audio.path)).into()));
+            }
+            else {
+                values.push(("type", "File".to_string().into()));
+                values.push(("filename", crate::playlist_formats::file_url(Path::new(&audio.path)).into()));
+            }
+            values.push(("title", audio.title.clone().into()));
+            values.push(("artist", audio.artist.clone().into()));
+            if let Some(album) = &audio.metadata.album {
+                values.push(("album", album.clone().into()));
+            }
+            if let Some(album_artist) = &audio.metadata.album_artist {
+                values.push(("albumartist", album_artist.clone().into()));
+            }
+            if let Some(genre) = &audio.metadata.genre {
+                values.push(("genre", genre.clone().into()));
+            }
+            if let Some(track_number) = audio.metadata.track_number {
+                values.push(("track", (track_number as i64).into()));
+            }
+            if let Some(disc_number) = audio.metadata.disc_number {
+                values.push(("disc", (disc_number as i64).into()));
+            }
+            if let Some(year) = audio.metadata.year {
+                values.push(("year", (year as i64).into()));
+            }
+            if let Some(duration_secs) = audio.metadata.duration_secs {
+                values.push(("length", (duration_secs as i64 * 1_000_000_000).into()));
+            }
+            if let Some(bitrate_kbps) = audio.metadata.bitrate_kbps {
+                values.push(("bitrate", (bitrate_kbps as i64).into()));
+            }
+            if let Some(file_size) = audio.metadata.file_size {
+                values.push(("filesize", (file_size as i64).into()));
+            }
+            values.retain(|(column, _)| has_column(column));
+            let sql = format!(
+                "INSERT INTO playlist_items ({}) VALUES ({})",
+                values.iter().map(|(column, _)| *column).collect::<Vec<&str>>().join(", "),
+                (1..=values.len()).map(|i| format!("?{}", i)).collect::<Vec<String>>().join(", ")
+            );
+            transaction.execute(&sql, rusqlite::params_from_iter(values.into_iter().map(|(_, value)| value))).map_err(to_string)?;
+        }
+        log.push(format!("Плейлист '{}' записан в базу плеера, треков: {}", playlist.name, playlist.audio.len()));
+    }
+    transaction.commit().map_err(to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file (name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("audials-rhythmbox-test-{}-{}.xml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn playlist (name: &str, paths: &[&str]) -> ResolvedPlaylist {
+        ResolvedPlaylist {
+            name:  name.to_string(),
+            id:    String::new(),
+            audio: paths.iter().enumerate().map(|(i, path)| crate::AudioEntry {
+                id:       i as crate::AudioEntryId,
+                title:    String::new(),
+                artist:   String::new(),
+                path:     path.to_string(),
+                metadata: crate::AudioMetadata::default(),
+            }).collect(),
+        }
+    }
+
+    fn write (path: &Path, playlists: &[ResolvedPlaylist]) -> String {
+        write_rhythmbox(path, playlists, &mut Vec::new()).unwrap();
+        let xml = std::fs::read_to_string(path).unwrap();
+        let _ = std::fs::remove_file(path);
+        xml
+    }
+
+    #[test]
+    fn playlist_is_appended_to_existing_block () {
+        let path = test_file("append", concat!(
+            "<?xml version=\"1.0\"?>\n<rhythmdb-playlists>\n",
+            "  <playlist name=\"Queue\" type=\"queue\"/>\n",
+            "  <playlist name=\"Old\" type=\"static\">\n    <location>file:///old.mp3</location>\n  </playlist>\n",
+            "</rhythmdb-playlists>\n",
+        ));
+        let xml = write(&path, &[playlist("Rock & Roll", &["/music/a b.mp3"])]);
+        let found = find_rhythmbox_playlists(&xml);
+        let names: Vec<&str> = found.iter().map(|(_, _, name)| name.as_str()).collect();
+        assert_eq!(names, ["Queue", "Old", "Rock & Roll"]);
+        assert!(xml.contains("name=\"Rock &amp; Roll\""));
+        assert!(xml.contains("<location>file:///music/a%20b.mp3</location>"));
+        assert!(xml.contains("<location>file:///old.mp3</location>"));
+        assert!(xml.trim_end().ends_with("</rhythmdb-playlists>"));
+    }
+
+    #[test]
+    fn empty_file_gets_playlists_block () {
+        let path = test_file("empty", "");
+        let xml = write(&path, &[playlist("Chill", &["/music/a.mp3"])]);
+        assert!(xml.starts_with("<?xml"));
+        let found = find_rhythmbox_playlists(&xml);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].2, "Chill");
+        assert!(xml.contains("<rhythmdb-playlists>"));
+    }
+
+    #[test]
+    fn same_name_playlist_is_replaced_in_place () {
+        let path = test_file("replace", concat!(
+            "<?xml version=\"1.0\"?>\n<rhythmdb-playlists>\n",
+            "  <playlist name=\"Chill\" type=\"static\">\n    <location>file:///old.mp3</location>\n  </playlist>\n",
+            "  <playlist name=\"Other\" type=\"static\">\n  </playlist>\n",
+            "  <playlist name=\"Chill\" type=\"static\">\n    <location>file:///older.mp3</location>\n  </playlist>\n",
+            "</rhythmdb-playlists>\n",
+        ));
+        let mut log = Vec::new();
+        write_rhythmbox(&path, &[playlist("Chill", &["/music/new.mp3"])], &mut log).unwrap();
+        let xml = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let names: Vec<String> = find_rhythmbox_playlists(&xml).into_iter().map(|(_, _, name)| name).collect();
+        assert_eq!(names, ["Chill", "Other"]);
+        assert!(xml.contains("file:///music/new.mp3"));
+        assert!(!xml.contains("old.mp3"));
+        assert!(!xml.contains("older.mp3"));
+        assert!(log.iter().any(|line| line.contains("заменён")));
+    }
+}
@@ -0,0 +1,379 @@
+// Форматы файла плейлиста. Каждый формат - реализация PlaylistWriter, которая
+// получает имя плейлиста и уже скопированные треки и возвращает текст файла.
+// Новый формат добавляется вариантом PlaylistFormat и своим writer
+
+use std::path::{Path, PathBuf};
+
+use crate::AudioEntry;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+    Wpl,
+    Asx,
+}
+impl PlaylistFormat {
+    pub const ALL: [PlaylistFormat; 5] = [
+        PlaylistFormat::M3u, PlaylistFormat::Pls, PlaylistFormat::Xspf, PlaylistFormat::Wpl, PlaylistFormat::Asx,
+    ];
+    pub fn extension (self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u  => "m3u8",
+            PlaylistFormat::Pls  => "pls",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Wpl  => "wpl",
+            PlaylistFormat::Asx  => "asx",
+        }
+    }
+    // Имя для файла настроек, не меняется вместе с подписями в интерфейсе
+    pub fn key (self) -> &'static str {
+        self.extension()
+    }
+    pub fn from_key (key: &str) -> Option<PlaylistFormat> {
+        PlaylistFormat::ALL.iter().copied().find(|format| format.key() == key)
+    }
+    pub fn writer (self) -> &'static dyn PlaylistWriter {
+        match self {
+            PlaylistFormat::M3u  => &M3uWriter,
+            PlaylistFormat::Pls  => &PlsWriter,
+            PlaylistFormat::Xspf => &XspfWriter,
+            PlaylistFormat::Wpl  => &WplWriter,
+            PlaylistFormat::Asx  => &AsxWriter,
+        }
+    }
+}
+impl std::fmt::Display for PlaylistFormat {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PlaylistFormat::M3u  => "M3U (UTF-8)",
+            PlaylistFormat::Pls  => "PLS",
+            PlaylistFormat::Xspf => "XSPF (VLC, Strawberry)",
+            PlaylistFormat::Wpl  => "WPL (Windows Media Player)",
+            PlaylistFormat::Asx  => "ASX",
+        })
+    }
+}
+
+// Что выбрано для файла плейлиста: встроенный формат или шаблон пользователя
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlaylistOutput {
+    Format(PlaylistFormat),
+    Template(PathBuf),
+}
+impl PlaylistOutput {
+    const TEMPLATE_KEY_PREFIX: &'static str = "template:";
+    pub fn key (&self) -> String {
+        match self {
+            PlaylistOutput::Format(format) => format.key().to_string(),
+            PlaylistOutput::Template(path) => format!("{}{}", PlaylistOutput::TEMPLATE_KEY_PREFIX, path.to_string_lossy()),
+        }
+    }
+    pub fn from_key (key: &str) -> Option<PlaylistOutput> {
+        match key.strip_prefix(PlaylistOutput::TEMPLATE_KEY_PREFIX) {
+            Some(path) => Some(PlaylistOutput::Template(path.into())),
+            None => PlaylistFormat::from_key(key).map(PlaylistOutput::Format),
+        }
+    }
+    // Текст файла плейлиста и его расширение; шаблон читается с диска при
+    // каждом экспорте, чтобы правки в нём подхватывались без перезапуска
+    pub fn render (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> crate::MyResult<(String, String)> {
+        match self {
+            PlaylistOutput::Format(format) => Ok((format.writer().write(context, tracks), format.extension().to_string())),
+            PlaylistOutput::Template(path) => {
+                let template = crate::playlist_template::PlaylistTemplate::load(path)?;
+                Ok((template.write(context, tracks), template.extension().to_string()))
+            },
+        }
+    }
+}
+impl std::fmt::Display for PlaylistOutput {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlaylistOutput::Format(format) => format.fmt(f),
+            PlaylistOutput::Template(path) => write!(f, "Шаблон: {}", crate::playlist_template::template_title(path)),
+        }
+    }
+}
+
+// Трек в том виде, в каком он попадает в плейлист: исходная запись и путь,
+// по которому файл лежит после экспорта
+pub struct PlaylistTrack<'a> {
+    pub audio:     &'a AudioEntry,
+    pub dest_path: PathBuf,
+}
+
+pub struct PlaylistContext<'a> {
+    pub name:               &'a str,
+    // папка, в которую пишется файл плейлиста
+    pub playlist_dir:       &'a Path,
+    pub relative_locations: bool,
+}
+impl<'a> PlaylistContext<'a> {
+    // Путь к треку для форматов, где это обычный путь в файловой системе
+    pub fn location (&self, track: &PlaylistTrack) -> String {
+        if self.relative_locations {
+            relative_path(&track.dest_path, self.playlist_dir).to_string_lossy().into_owned()
+        }
+        else {
+            track.dest_path.to_string_lossy().into_owned()
+        }
+    }
+    // Путь к треку в виде URI: file:// для абсолютного, иначе относительная
+    // ссылка, в обоих случаях с %-кодированием
+    pub fn location_uri (&self, track: &PlaylistTrack) -> String {
+        if self.relative_locations {
+            percent_encode_path(&relative_path(&track.dest_path, self.playlist_dir))
+        }
+        else {
+            file_url(&track.dest_path)
+        }
+    }
+}
+
+pub trait PlaylistWriter {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String;
+}
+
+// Путь target относительно папки base_dir, с "..", если нужно подняться выше
+pub fn relative_path (target: &Path, base_dir: &Path) -> PathBuf {
+    let target_components: Vec<_> = target.components().collect();
+    let base_components: Vec<_> = base_dir.components().collect();
+    let common = target_components.iter().zip(base_components.iter()).take_while(|(a, b)| a == b).count();
+    if 0 == common && target.is_absolute() {
+        // разные диски на windows - относительного пути нет
+        return target.to_path_buf();
+    }
+    let mut result = PathBuf::new();
+    for _ in common..base_components.len() {
+        result.push("..");
+    }
+    for component in &target_components[common..] {
+        result.push(component);
+    }
+    result
+}
+
+pub fn escape_xml (text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&'  => result.push_str("&amp;"),
+            '<'  => result.push_str("&lt;"),
+            '>'  => result.push_str("&gt;"),
+            '"'  => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _    => result.push(c),
+        }
+    }
+    result
+}
+
+// Разделители пути всегда '/', остальное кроме безопасных символов - %XX
+pub fn percent_encode_path (path: &Path) -> String {
+    let path_string = path.to_string_lossy().replace('\\', "/");
+    let mut result = String::with_capacity(path_string.len());
+    for byte in path_string.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
+
+pub fn file_url (path: &Path) -> String {
+    let encoded = percent_encode_path(path);
+    // C:/Music -> file:///C:/Music, двоеточие после буквы диска оставляется
+    let encoded = match encoded.get(1..4) {
+        Some("%3A") if encoded.as_bytes()[0].is_ascii_alphabetic() => format!("/{}:{}", &encoded[..1], &encoded[4..]),
+        _ => encoded,
+    };
+    format!("file://{}", encoded)
+}
+
+fn display_title (audio: &AudioEntry) -> String {
+    if audio.artist.is_empty() {
+        audio.title.clone()
+    }
+    else {
+        format!("{} - {}", audio.artist, audio.title)
+    }
+}
+
+struct M3uWriter;
+impl PlaylistWriter for M3uWriter {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String {
+        let mut result = format!("#EXTM3U\n#PLAYLIST:{}\n", context.name);
+        for track in tracks {
+            let duration = track.audio.metadata.duration_secs.map_or(-1, |duration| duration as i64);
+            result.push_str(&format!("#EXTINF:{},{}\n", duration, display_title(track.audio)));
+            if let Some(album) = &track.audio.metadata.album {
+                result.push_str(&format!("#EXTALB:{}\n", album));
+            }
+            result.push_str(&context.location(track));
+            result.push('\n');
+        }
+        result
+    }
+}
+
+struct PlsWriter;
+impl PlaylistWriter for PlsWriter {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String {
+        let mut result = String::from("[playlist]\n");
+        for (i, track) in tracks.iter().enumerate() {
+            let duration = track.audio.metadata.duration_secs.map_or(-1, |duration| duration as i64);
+            result.push_str(&format!("File{0}={1}\nTitle{0}={2}\nLength{0}={3}\n", 1 + i, context.location(track), display_title(track.audio), duration));
+        }
+        result.push_str(&format!("NumberOfEntries={}\nVersion=2\n", tracks.len()));
+        result
+    }
+}
+
+struct XspfWriter;
+impl PlaylistWriter for XspfWriter {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String {
+        let mut result = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <title>{}</title>\n  <trackList>\n",
+            escape_xml(context.name)
+        );
+        for track in tracks {
+            result.push_str("    <track>\n");
+            result.push_str(&format!("      <location>{}</location>\n", escape_xml(&context.location_uri(track))));
+            result.push_str(&format!("      <title>{}</title>\n", escape_xml(&track.audio.title)));
+            if !track.audio.artist.is_empty() {
+                result.push_str(&format!("      <creator>{}</creator>\n", escape_xml(&track.audio.artist)));
+            }
+            if let Some(album) = &track.audio.metadata.album {
+                result.push_str(&format!("      <album>{}</album>\n", escape_xml(album)));
+            }
+            if let Some(track_number) = track.audio.metadata.track_number {
+                result.push_str(&format!("      <trackNum>{}</trackNum>\n", track_number));
+            }
+            if let Some(duration_secs) = track.audio.metadata.duration_secs {
+                result.push_str(&format!("      <duration>{}</duration>\n", 1000 * duration_secs));
+            }
+            result.push_str("    </track>\n");
+        }
+        result.push_str("  </trackList>\n</playlist>\n");
+        result
+    }
+}
+
+struct WplWriter;
+impl PlaylistWriter for WplWriter {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String {
+        let total_duration: u64 = tracks.iter().filter_map(|track| track.audio.metadata.duration_secs).sum();
+        let mut result = format!(
+            "<?wpl version=\"1.0\"?>\n<smil>\n  <head>\n    <meta name=\"Generator\" content=\"audials-playlist-exporter\"/>\n    <meta name=\"ItemCount\" content=\"{}\"/>\n    <meta name=\"TotalDuration\" content=\"{}\"/>\n    <title>{}</title>\n  </head>\n  <body>\n    <seq>\n",
+            tracks.len(), total_duration, escape_xml(context.name)
+        );
+        for track in tracks {
+            result.push_str(&format!("      <media src=\"{}\"/>\n", escape_xml(&context.location(track))));
+        }
+        result.push_str("    </seq>\n  </body>\n</smil>\n");
+        result
+    }
+}
+
+struct AsxWriter;
+impl PlaylistWriter for AsxWriter {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String {
+        let mut result = format!("<asx version=\"3.0\">\n  <title>{}</title>\n", escape_xml(context.name));
+        for track in tracks {
+            result.push_str("  <entry>\n");
+            result.push_str(&format!("    <title>{}</title>\n", escape_xml(&track.audio.title)));
+            if !track.audio.artist.is_empty() {
+                result.push_str(&format!("    <author>{}</author>\n", escape_xml(&track.audio.artist)));
+            }
+            if let Some(duration_secs) = track.audio.metadata.duration_secs {
+                result.push_str(&format!("    <duration value=\"{:02}:{:02}:{:02}.00\"/>\n", duration_secs / 3600, duration_secs % 3600 / 60, duration_secs % 60));
+            }
+            result.push_str(&format!("    <ref href=\"{}\"/>\n", escape_xml(&context.location(track))));
+            result.push_str("  </entry>\n");
+        }
+        result.push_str("</asx>\n");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio () -> AudioEntry {
+        AudioEntry {
+            id:       1,
+            title:    "Ёлка & <Друзья>".to_string(),
+            artist:   "Би-2".to_string(),
+            path:     "/src/elka.mp3".to_string(),
+            metadata: crate::AudioMetadata {duration_secs: Some(125), album: Some("Альбом".to_string()), ..crate::AudioMetadata::default()},
+        }
+    }
+
+    fn render (format: PlaylistFormat, relative_locations: bool) -> String {
+        let audio = audio();
+        let tracks = [PlaylistTrack {audio: &audio, dest_path: PathBuf::from("/out/Music/Би-2/Ёлка & <Друзья>.mp3")}];
+        let context = PlaylistContext {name: "Rock & Roll", playlist_dir: Path::new("/out/Playlists"), relative_locations};
+        format.writer().write(&context, &tracks)
+    }
+
+    #[test]
+    fn relative_path_climbs_with_dot_dot () {
+        assert_eq!(relative_path(Path::new("/out/Music/a.mp3"), Path::new("/out/Playlists")), PathBuf::from("../Music/a.mp3"));
+        assert_eq!(relative_path(Path::new("/out/Playlists/sub/a.mp3"), Path::new("/out/Playlists")), PathBuf::from("sub/a.mp3"));
+        assert_eq!(relative_path(Path::new("/out/a.mp3"), Path::new("/out")), PathBuf::from("a.mp3"));
+        assert_eq!(relative_path(Path::new("/a/b/c.mp3"), Path::new("/x/y/z")), PathBuf::from("../../../a/b/c.mp3"));
+    }
+
+    #[test]
+    fn xml_special_characters_are_escaped () {
+        assert_eq!(escape_xml("Tom & Jerry <\"live\"> 'x'"), "Tom &amp; Jerry &lt;&quot;live&quot;&gt; &apos;x&apos;");
+        assert_eq!(escape_xml("Ёлка"), "Ёлка");
+    }
+
+    #[test]
+    fn paths_are_percent_encoded_as_utf8 () {
+        assert_eq!(
+            percent_encode_path(Path::new("../Music/Би-2/Ёлка & <Друзья>.mp3")),
+            "../Music/%D0%91%D0%B8-2/%D0%81%D0%BB%D0%BA%D0%B0%20%26%20%3C%D0%94%D1%80%D1%83%D0%B7%D1%8C%D1%8F%3E.mp3"
+        );
+        assert_eq!(percent_encode_path(Path::new("Music\\a.mp3")), "Music/a.mp3");
+        assert_eq!(file_url(Path::new("/music/Ёлка.mp3")), "file:///music/%D0%81%D0%BB%D0%BA%D0%B0.mp3");
+        assert_eq!(file_url(Path::new("C:\\Music\\a b.mp3")), "file:///C:/Music/a%20b.mp3");
+    }
+
+    #[test]
+    fn m3u_and_pls_keep_plain_paths () {
+        assert_eq!(
+            render(PlaylistFormat::M3u, true),
+            "#EXTM3U\n#PLAYLIST:Rock & Roll\n#EXTINF:125,Би-2 - Ёлка & <Друзья>\n#EXTALB:Альбом\n../Music/Би-2/Ёлка & <Друзья>.mp3\n"
+        );
+        assert_eq!(
+            render(PlaylistFormat::Pls, false),
+            "[playlist]\nFile1=/out/Music/Би-2/Ёлка & <Друзья>.mp3\nTitle1=Би-2 - Ёлка & <Друзья>\nLength1=125\nNumberOfEntries=1\nVersion=2\n"
+        );
+    }
+
+    #[test]
+    fn xml_formats_escape_names_and_locations () {
+        let xspf = render(PlaylistFormat::Xspf, true);
+        assert!(xspf.contains("<title>Rock &amp; Roll</title>"));
+        assert!(xspf.contains("<title>Ёлка &amp; &lt;Друзья&gt;</title>"));
+        assert!(xspf.contains("<location>../Music/%D0%91%D0%B8-2/%D0%81%D0%BB%D0%BA%D0%B0%20%26%20%3C%D0%94%D1%80%D1%83%D0%B7%D1%8C%D1%8F%3E.mp3</location>"));
+        assert!(xspf.contains("<duration>125000</duration>"));
+        assert!(render(PlaylistFormat::Xspf, false).contains("<location>file:///out/Music/%D0%91%D0%B8-2/"));
+
+        let wpl = render(PlaylistFormat::Wpl, true);
+        assert!(wpl.contains("<title>Rock &amp; Roll</title>"));
+        assert!(wpl.contains("<media src=\"../Music/Би-2/Ёлка &amp; &lt;Друзья&gt;.mp3\"/>"));
+        assert!(wpl.contains("<meta name=\"TotalDuration\" content=\"125\"/>"));
+
+        let asx = render(PlaylistFormat::Asx, false);
+        assert!(asx.contains("<author>Би-2</author>"));
+        assert!(asx.contains("<duration value=\"00:02:05.00\"/>"));
+        assert!(asx.contains("<ref href=\"/out/Music/Би-2/Ёлка &amp; &lt;Друзья&gt;.mp3\"/>"));
+    }
+}
@@ -0,0 +1,109 @@
+// Список выбора плейлиста: у Audials их бывают сотни и с одинаковыми именами,
+// поэтому список фильтруется по мере ввода, а в строке кроме имени видны число
+// треков, id и отметки времени из payload. Выбор запоминается по id
+
+use std::collections::HashMap;
+
+use crate::PlaylistEntry;
+
+// Поля payload, похожие на отметки времени: CreationDate, LastModified и т.п.
+const TIMESTAMP_KEY_PARTS: [&str; 6] = ["date", "time", "created", "modified", "changed", "updated"];
+
+pub fn payload_timestamps (payload: &serde_json::Value) -> Vec<(String, String)> {
+    let payload = match payload.as_object() {
+        Some(payload) => payload,
+        None => return Vec::new(),
+    };
+    payload.iter()
+        .filter(|(key, _)| {
+            let key = key.to_lowercase();
+            TIMESTAMP_KEY_PARTS.iter().any(|part| key.contains(part))
+        })
+        .filter_map(|(key, value)| match value {
+            serde_json::Value::String(value) if !value.is_empty() => Some((key.clone(), value.clone())),
+            serde_json::Value::Number(value) => Some((key.clone(), value.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+// Число вхождений каждого плейлиста в файле содержимого плейлистов. Битые
+// вхождения пропускаются молча - о них сообщит проверка плейлиста
+pub fn count_entries (playlist_entries: &[serde_json::Value]) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for arr_entry in playlist_entries {
+        let playlist_id = arr_entry.get("payload")
+            .and_then(|payload| payload.as_str())
+            .and_then(|payload| serde_json::from_str::<serde_json::Value>(payload).ok())
+            .and_then(|payload| payload.get("PlaylistId")?.as_str().map(|playlist_id| playlist_id.to_string()));
+        if let Some(playlist_id) = playlist_id {
+            *counts.entry(playlist_id).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+pub fn label (entry: &PlaylistEntry) -> String {
+    let mut label = entry.display_name();
+    match entry.entry_count {
+        Some(entry_count) => label.push_str(&format!(" — треков: {}", entry_count)),
+        None if entry.smart_expression.is_some() => label.push_str(" — умный"),
+        None => (),
+    }
+    label.push_str(&format!(" — id {}", entry.id));
+    for (key, value) in &entry.timestamps {
+        label.push_str(&format!(", {}: {}", key, value));
+    }
+    label
+}
+
+// Все слова фильтра без учёта регистра - в имени, id или отметках времени
+pub fn matches_filter (entry: &PlaylistEntry, filter: &str) -> bool {
+    let haystack = label(entry).to_lowercase();
+    filter.to_lowercase().split_whitespace().all(|word| haystack.contains(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry (name: &str, id: &str, entry_count: Option<usize>, smart_expression: Option<&str>) -> PlaylistEntry {
+        PlaylistEntry {
+            name: name.to_string(),
+            id: id.to_string(),
+            smart_expression: smart_expression.map(|smart_expression| smart_expression.to_string()),
+            entry_count,
+            timestamps: vec![("CreationDate".to_string(), "2021-03-04".to_string())],
+        }
+    }
+
+    #[test]
+    fn label_shows_count_id_and_timestamps () {
+        assert_eq!("Дорога — треков: 12 — id 7f3a, CreationDate: 2021-03-04", label(&entry("Дорога", "7f3a", Some(12), None)));
+        assert_eq!("★ Новое — умный — id smart:Новое, CreationDate: 2021-03-04", label(&entry("Новое", "smart:Новое", None, Some("added > 2021"))));
+    }
+
+    #[test]
+    fn filter_matches_every_word_ignoring_case () {
+        let playlist = entry("Дорога Домой", "7F3A", Some(12), None);
+        assert!(matches_filter(&playlist, ""));
+        assert!(matches_filter(&playlist, "домой  дор"));
+        assert!(matches_filter(&playlist, "7f3a"));
+        assert!(matches_filter(&playlist, "2021-03"));
+        assert!(!matches_filter(&playlist, "дорога работа"));
+    }
+
+    #[test]
+    fn timestamps_and_counts_from_payload () {
+        let payload = serde_json::json!({"Name": "x", "CreationDate": "2021-03-04", "LastModified": 1614834368, "UpdatedBy": "", "PlaylistId": "a"});
+        assert_eq!(vec![("CreationDate".to_string(), "2021-03-04".to_string()), ("LastModified".to_string(), "1614834368".to_string())], payload_timestamps(&payload));
+        let entries = vec![
+            serde_json::json!({"payload": "{\"PlaylistId\": \"a\"}"}),
+            serde_json::json!({"payload": "{\"PlaylistId\": \"a\"}"}),
+            serde_json::json!({"payload": "{\"PlaylistId\": \"b\"}"}),
+            serde_json::json!({"payload": "не json"}),
+        ];
+        let counts = count_entries(&entries);
+        assert_eq!((Some(&2), Some(&1), 2), (counts.get("a"), counts.get("b"), counts.len()));
+    }
+}
@@ -0,0 +1,321 @@
+// Пользовательский формат плейлиста по текстовому шаблону - для своих плееров
+// и киосков, под которые нет встроенного формата. Синтаксис похож на mustache:
+//
+//   {{name}}, {{count}}              имя плейлиста и число треков
+//   {{#tracks}} ... {{/tracks}}      повтор для каждого трека
+//   {{title}}, {{album|xml}}         значение, после '|' фильтр: xml, uri, csv
+//   {{#album}} ... {{/album}}        блок, если значение не пустое
+//   {{^album}} ... {{/album}}        блок, если значение пустое
+//   {{! комментарий }}
+//
+// Поля трека: index, title, artist, path (найденный файл в библиотеке),
+// dest_path (файл после экспорта), location (путь как в встроенных форматах,
+// с учётом относительных путей), relative_path, uri, file_name, duration_secs
+// и ключи metadata (album, year, ...). Строка, где стоит только тег блока,
+// в результат не попадает. Расширение результата берётся из имени шаблона:
+// kiosk.xml.tpl -> <плейлист>.xml
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{MyResult, METADATA_KEYS};
+use crate::playlist_formats::{PlaylistContext, PlaylistTrack, PlaylistWriter, escape_xml, file_url, percent_encode_path, relative_path};
+
+const PLAYLIST_KEYS: [&str; 3] = ["name", "count", "playlist_dir"];
+const TRACK_KEYS: [&str; 9] = ["index", "title", "artist", "path", "dest_path", "location", "relative_path", "uri", "file_name"];
+
+#[derive(Clone, Copy)]
+enum Filter {
+    Xml,
+    Uri,
+    Csv,
+}
+
+enum Node {
+    Text(String),
+    Value {key: String, filter: Option<Filter>},
+    Section {key: String, inverted: bool, children: Vec<Node>},
+}
+
+enum Token {
+    Text(String),
+    Tag(String),
+}
+
+pub struct PlaylistTemplate {
+    nodes:     Vec<Node>,
+    extension: String,
+}
+impl PlaylistTemplate {
+    pub fn load (path: &Path) -> MyResult<PlaylistTemplate> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("не удалось прочитать шаблон '{}': {}", path.to_string_lossy(), err))?;
+        let nodes = PlaylistTemplate::parse(&source)
+            .map_err(|err| format!("ошибка в шаблоне '{}': {}", path.to_string_lossy(), err))?;
+        Ok(PlaylistTemplate {
+            nodes,
+            extension: template_extension(path),
+        })
+    }
+    pub fn extension (&self) -> &str {
+        &self.extension
+    }
+    fn parse (source: &str) -> MyResult<Vec<Node>> {
+        if source.trim().is_empty() {
+            return Err("шаблон пуст".to_string());
+        }
+        let tokens = strip_standalone_lines(tokenize(source)?);
+        // стек открытых блоков: (ключ, инверсия, узлы до блока)
+        let mut stack: Vec<(String, bool, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+        for token in tokens {
+            let tag = match token {
+                Token::Text(text) => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(text));
+                    }
+                    continue;
+                },
+                Token::Tag(tag) => tag,
+            };
+            if tag.starts_with('!') {
+                continue;
+            }
+            if let Some(key) = tag.strip_prefix('#').or_else(|| tag.strip_prefix('^')) {
+                let key = check_key(key.trim())?;
+                stack.push((key, tag.starts_with('^'), std::mem::take(&mut nodes)));
+            }
+            else if let Some(key) = tag.strip_prefix('/') {
+                let (open_key, inverted, outer_nodes) = stack.pop().ok_or(format!("закрытие блока '{}' без открытия", key.trim()))?;
+                if open_key != key.trim() {
+                    return Err(format!("блок '{}' закрыт как '{}'", open_key, key.trim()));
+                }
+                let children = std::mem::replace(&mut nodes, outer_nodes);
+                nodes.push(Node::Section {key: open_key, inverted, children});
+            }
+            else {
+                let mut parts = tag.splitn(2, '|');
+                let key = check_key(parts.next().unwrap().trim())?;
+                let filter = match parts.next().map(|filter| filter.trim()) {
+                    None => None,
+                    Some("xml") => Some(Filter::Xml),
+                    Some("uri") => Some(Filter::Uri),
+                    Some("csv") => Some(Filter::Csv),
+                    Some(filter) => return Err(format!("неизвестный фильтр '{}', есть: xml, uri, csv", filter)),
+                };
+                nodes.push(Node::Value {key, filter});
+            }
+        }
+        if let Some((key, _, _)) = stack.last() {
+            return Err(format!("блок '{}' не закрыт", key));
+        }
+        Ok(nodes)
+    }
+}
+
+fn check_key (key: &str) -> MyResult<String> {
+    if "tracks" == key || "duration_secs" == key || PLAYLIST_KEYS.contains(&key) || TRACK_KEYS.contains(&key) || METADATA_KEYS.contains(&key) {
+        Ok(key.to_string())
+    }
+    else {
+        Err(format!("неизвестное поле '{}'", key))
+    }
+}
+
+// "kiosk.xml.tpl" -> "xml", "report.txt" -> "txt", без расширения - "txt"
+fn template_extension (path: &Path) -> String {
+    let file_name = path.file_name().map_or(String::new(), |file_name| file_name.to_string_lossy().into_owned());
+    let file_name = file_name.strip_suffix(".tpl").or_else(|| file_name.strip_suffix(".template")).unwrap_or(&file_name);
+    Path::new(file_name).extension().map_or(String::from("txt"), |extension| extension.to_string_lossy().into_owned())
+}
+
+fn tokenize (source: &str) -> MyResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        tokens.push(Token::Text(rest[..start].to_string()));
+        let end = rest[start..].find("}}").ok_or("незакрытый тег '{{'".to_string())?;
+        tokens.push(Token::Tag(rest[start + 2..start + end].trim().to_string()));
+        rest = &rest[start + end + 2..];
+    }
+    tokens.push(Token::Text(rest.to_string()));
+    Ok(tokens)
+}
+
+// Тег блока или комментария, который один на строке, убирается вместе со
+// строкой - иначе каждый {{#tracks}} оставлял бы в результате пустые строки
+fn strip_standalone_lines (mut tokens: Vec<Token>) -> Vec<Token> {
+    // решение для всех тегов принимается по исходному тексту, до обрезки
+    let standalone: Vec<bool> = (0..tokens.len()).map(|i| {
+        let is_block_tag = match &tokens[i] {
+            Token::Tag(tag) => tag.starts_with(&['#', '^', '/', '!'][..]),
+            Token::Text(_) => false,
+        };
+        let before_ok = match i.checked_sub(1).map(|j| &tokens[j]) {
+            Some(Token::Text(text)) => {
+                let line_start = text.rfind('\n').map_or(0, |newline| newline + 1);
+                text[line_start..].trim().is_empty() && (0 < line_start || 1 == i)
+            },
+            _ => false,
+        };
+        let after_ok = match tokens.get(i + 1) {
+            Some(Token::Text(text)) => {
+                let line_end = text.find('\n').unwrap_or(text.len());
+                text[..line_end].trim().is_empty() && (line_end < text.len() || i + 2 == tokens.len())
+            },
+            _ => false,
+        };
+        is_block_tag && before_ok && after_ok
+    }).collect();
+    for i in (0..tokens.len()).filter(|i| standalone[*i]) {
+        if let Token::Text(text) = &mut tokens[i - 1] {
+            let line_start = text.rfind('\n').map_or(0, |newline| newline + 1);
+            text.truncate(line_start);
+        }
+        if let Token::Text(text) = &mut tokens[i + 1] {
+            let line_end = text.find('\n').map_or(text.len(), |newline| newline + 1);
+            text.replace_range(..line_end, "");
+        }
+    }
+    tokens
+}
+
+fn apply_filter (value: &str, filter: Option<Filter>) -> String {
+    match filter {
+        None => value.to_string(),
+        Some(Filter::Xml) => escape_xml(value),
+        Some(Filter::Uri) => percent_encode_path(Path::new(value)),
+        Some(Filter::Csv) if value.contains(&[',', '"', '\n'][..]) => format!("\"{}\"", value.replace('"', "\"\"")),
+        Some(Filter::Csv) => value.to_string(),
+    }
+}
+
+type Scope = HashMap<&'static str, String>;
+
+fn lookup<'a> (key: &str, scopes: &[&'a Scope]) -> Option<&'a String> {
+    scopes.iter().rev().find_map(|scope| scope.get(key))
+}
+
+fn render (nodes: &[Node], scopes: &[&Scope], tracks: &[Scope], result: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => result.push_str(text),
+            Node::Value {key, filter} => if let Some(value) = lookup(key, scopes) {
+                result.push_str(&apply_filter(value, *filter));
+            },
+            Node::Section {key, inverted: false, children} if "tracks" == key => {
+                for track in tracks {
+                    let mut track_scopes = scopes.to_vec();
+                    track_scopes.push(track);
+                    render(children, &track_scopes, tracks, result);
+                }
+            },
+            Node::Section {key, inverted, children} => {
+                let is_empty = if "tracks" == key {
+                    tracks.is_empty()
+                }
+                else {
+                    match lookup(key, scopes) {
+                        Some(value) => value.is_empty(),
+                        None => true,
+                    }
+                };
+                if is_empty == *inverted {
+                    render(children, scopes, tracks, result);
+                }
+            },
+        }
+    }
+}
+
+impl PlaylistWriter for PlaylistTemplate {
+    fn write (&self, context: &PlaylistContext, tracks: &[PlaylistTrack]) -> String {
+        let mut playlist_scope = Scope::new();
+        playlist_scope.insert("name", context.name.to_string());
+        playlist_scope.insert("count", tracks.len().to_string());
+        playlist_scope.insert("playlist_dir", context.playlist_dir.to_string_lossy().into_owned());
+        let track_scopes: Vec<Scope> = tracks.iter().enumerate().map(|(i, track)| {
+            let mut scope = Scope::new();
+            scope.insert("index", (1 + i).to_string());
+            scope.insert("title", track.audio.title.clone());
+            scope.insert("artist", track.audio.artist.clone());
+            scope.insert("path", track.audio.path.clone());
+            scope.insert("dest_path", track.dest_path.to_string_lossy().into_owned());
+            scope.insert("location", context.location(track));
+            scope.insert("relative_path", relative_path(&track.dest_path, context.playlist_dir).to_string_lossy().into_owned());
+            scope.insert("uri", file_url(&track.dest_path));
+            scope.insert("file_name", track.dest_path.file_name().map_or(String::new(), |file_name| file_name.to_string_lossy().into_owned()));
+            if let Some(duration_secs) = track.audio.metadata.duration_secs {
+                scope.insert("duration_secs", duration_secs.to_string());
+            }
+            for (key, value) in track.audio.metadata.named_values() {
+                scope.insert(key, value);
+            }
+            scope
+        }).collect();
+        let mut result = String::new();
+        render(&self.nodes, &[&playlist_scope], &track_scopes, &mut result);
+        result
+    }
+}
+
+// Имя шаблона для списка форматов - имя файла без .tpl
+pub fn template_title (path: &Path) -> String {
+    let file_name = path.file_name().map_or(String::new(), |file_name| file_name.to_string_lossy().into_owned());
+    file_name.strip_suffix(".tpl").or_else(|| file_name.strip_suffix(".template")).unwrap_or(&file_name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_source (source: &str, album: Option<&str>) -> String {
+        let nodes = PlaylistTemplate::parse(source).unwrap();
+        let mut playlist_scope = Scope::new();
+        playlist_scope.insert("name", "Road Trip".to_string());
+        let mut track = Scope::new();
+        track.insert("title", "A & B".to_string());
+        if let Some(album) = album {
+            track.insert("album", album.to_string());
+        }
+        let mut result = String::new();
+        render(&nodes, &[&playlist_scope], &[track], &mut result);
+        result
+    }
+
+    #[test]
+    fn empty_template_is_rejected () {
+        assert!(PlaylistTemplate::parse("").is_err());
+        assert!(PlaylistTemplate::parse(" \n\t").is_err());
+    }
+
+    #[test]
+    fn unterminated_tag_is_rejected () {
+        assert!(PlaylistTemplate::parse("{{name").is_err());
+        assert!(PlaylistTemplate::parse("{{#tracks}}{{title}}{{/tracks").is_err());
+        assert!(PlaylistTemplate::parse("{{name}").is_err());
+    }
+
+    #[test]
+    fn unclosed_or_mismatched_block_is_rejected () {
+        assert!(PlaylistTemplate::parse("{{#tracks}}{{title}}").is_err());
+        assert!(PlaylistTemplate::parse("{{#tracks}}{{/album}}").is_err());
+        assert!(PlaylistTemplate::parse("{{/tracks}}").is_err());
+        assert!(PlaylistTemplate::parse("{{unknown}}").is_err());
+        assert!(PlaylistTemplate::parse("{{title|json}}").is_err());
+    }
+
+    #[test]
+    fn standalone_block_lines_are_dropped () {
+        let source = "# {{name}}\n{{#tracks}}\n{{title|xml}}\n{{/tracks}}\nend\n";
+        assert_eq!(render_source(source, None), "# Road Trip\nA &amp; B\nend\n");
+    }
+
+    #[test]
+    fn sections_follow_value_presence () {
+        let source = "{{#tracks}}{{#album}}[{{album}}]{{/album}}{{^album}}-{{/album}}{{/tracks}}";
+        assert_eq!(render_source(source, Some("X")), "[X]");
+        assert_eq!(render_source(source, Some("")), "-");
+        assert_eq!(render_source(source, None), "-");
+    }
+}
@@ -0,0 +1,252 @@
+// Настройки, которые переживают перезапуск: лежат в JSON в папке настроек
+// пользователя. Файл разбирается вручную через serde_json::Value, как и
+// файлы Audials - неизвестные и битые поля просто пропускаются
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::MyResult;
+use crate::archive::ArchiveFormat;
+use crate::export::TransferMode;
+use crate::volumes::VolumePreset;
+use crate::playlist_formats::{PlaylistFormat, PlaylistOutput};
+
+#[derive(Clone)]
+pub struct SmartPlaylistPreset {
+    pub name:       String,
+    pub expression: String,
+}
+
+pub struct Settings {
+    pub smart_playlists:    Vec<SmartPlaylistPreset>,
+    pub playlist_format:    PlaylistOutput,
+    pub relative_locations: bool,
+    // файлы шаблонов, добавленные в список форматов
+    pub playlist_templates: Vec<PathBuf>,
+    pub archive_format:     ArchiveFormat,
+    pub transfer_mode:      TransferMode,
+    pub verify_checksums:   bool,
+    pub copy_workers:       usize,
+    // команда после экспорта, пустая - не запускать
+    pub post_export_hook:   String,
+    pub volume_preset:      VolumePreset,
+    // последний свой размер тома, МБ
+    pub volume_custom_mb:   Option<u64>,
+    // id плейлиста -> пути треков, которые при экспорте пропускаются
+    pub excluded_tracks:    BTreeMap<String, BTreeSet<String>>,
+    // битый файл не удалось отложить: перезаписывать его нельзя
+    save_blocked:           Option<String>,
+}
+
+fn settings_path () -> Option<PathBuf> {
+    let mut path = dirs_next::config_dir()?;
+    path.push("audials-playlist-exporter");
+    path.push("settings.json");
+    Some(path)
+}
+
+fn get_string (obj: &serde_json::Value, key: &str) -> Option<String> {
+    obj.get(key)?.as_str().map(|value| value.to_string())
+}
+
+impl Settings {
+    pub fn new () -> Settings {
+        Settings {
+            smart_playlists:    Vec::new(),
+            playlist_format:    PlaylistOutput::Format(PlaylistFormat::M3u),
+            relative_locations: true,
+            playlist_templates: Vec::new(),
+            archive_format:     ArchiveFormat::Zip,
+            transfer_mode:      TransferMode::Copy,
+            verify_checksums:   true,
+            copy_workers:       1,
+            post_export_hook:   String::new(),
+            volume_preset:      VolumePreset::Cd,
+            volume_custom_mb:   None,
+            excluded_tracks:    BTreeMap::new(),
+            save_blocked:       None,
+        }
+    }
+    // Отсутствующий файл означает настройки по умолчанию. Нечитаемый или битый
+    // файл переименовывается в settings.json.bad-<время>, чтобы следующее
+    // сохранение не затёрло его; если и это не вышло, настройки не сохраняются
+    pub fn load () -> (Settings, Vec<String>) {
+        match settings_path() {
+            Some(path) => Settings::load_from(&path),
+            None => (Settings::new(), Vec::new()),
+        }
+    }
+    fn load_from (path: &Path) -> (Settings, Vec<String>) {
+        let mut settings = Settings::new();
+        let read_result = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).map_err(|err| err.to_string()));
+        let root = match read_result {
+            Ok(root) => root,
+            Err(_) if !path.exists() => return (settings, Vec::new()),
+            Err(err) => {
+                let reason = format!("файл настроек '{}' не прочитан ({})", path.to_string_lossy(), err);
+                let unix_secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_secs());
+                let mut backup_name = path.file_name().unwrap_or_default().to_os_string();
+                backup_name.push(format!(".bad-{}", unix_secs));
+                let backup_path = path.with_file_name(backup_name);
+                let message = match std::fs::rename(path, &backup_path) {
+                    Ok(()) => format!("# Ошибка: {}, он переименован в '{}', используются настройки по умолчанию", reason, backup_path.to_string_lossy()),
+                    Err(rename_err) => {
+                        settings.save_blocked = Some(format!("{} и не переименован ({}), чтобы не потерять его, настройки не сохраняются", reason, rename_err));
+                        format!("# Ошибка: {} и не переименован ({}), используются настройки по умолчанию, изменения не будут сохранены", reason, rename_err)
+                    },
+                };
+                return (settings, vec![message]);
+            },
+        };
+        if let Some(presets) = root.get("smart_playlists").and_then(|presets| presets.as_array()) {
+            for preset in presets {
+                if let (Some(name), Some(expression)) = (get_string(preset, "name"), get_string(preset, "expression")) {
+                    settings.smart_playlists.push(SmartPlaylistPreset {name, expression});
+                }
+            }
+        }
+        if let Some(playlist_format) = get_string(&root, "playlist_format").and_then(|key| PlaylistOutput::from_key(&key)) {
+            settings.playlist_format = playlist_format;
+        }
+        if let Some(relative_locations) = root.get("relative_locations").and_then(|value| value.as_bool()) {
+            settings.relative_locations = relative_locations;
+        }
+        if let Some(archive_format) = get_string(&root, "archive_format").and_then(|key| ArchiveFormat::from_key(&key)) {
+            settings.archive_format = archive_format;
+        }
+        if let Some(transfer_mode) = get_string(&root, "transfer_mode").and_then(|key| TransferMode::from_key(&key)) {
+            settings.transfer_mode = transfer_mode;
+        }
+        if let Some(verify_checksums) = root.get("verify_checksums").and_then(|value| value.as_bool()) {
+            settings.verify_checksums = verify_checksums;
+        }
+        if let Some(copy_workers) = root.get("copy_workers").and_then(|value| value.as_u64()).filter(|copy_workers| 0 < *copy_workers) {
+            settings.copy_workers = copy_workers as usize;
+        }
+        if let Some(post_export_hook) = get_string(&root, "post_export_hook") {
+            settings.post_export_hook = post_export_hook;
+        }
+        if let Some(volume_preset) = get_string(&root, "volume_preset").and_then(|key| VolumePreset::from_key(&key)) {
+            settings.volume_preset = volume_preset;
+        }
+        settings.volume_custom_mb = root.get("volume_custom_mb").and_then(|value| value.as_u64());
+        if let Some(excluded_tracks) = root.get("excluded_tracks").and_then(|excluded_tracks| excluded_tracks.as_object()) {
+            for (playlist_id, paths) in excluded_tracks {
+                let paths: BTreeSet<String> = paths.as_array().into_iter().flatten().filter_map(|path| path.as_str()).map(|path| path.to_string()).collect();
+                if !paths.is_empty() {
+                    settings.excluded_tracks.insert(playlist_id.clone(), paths);
+                }
+            }
+        }
+        if let Some(templates) = root.get("playlist_templates").and_then(|templates| templates.as_array()) {
+            settings.playlist_templates = templates.iter().filter_map(|template| template.as_str()).map(PathBuf::from).collect();
+        }
+        (settings, Vec::new())
+    }
+    // Пустой набор исключений не хранится
+    pub fn set_excluded_tracks (&mut self, playlist_id: &str, excluded: &BTreeSet<String>) {
+        if excluded.is_empty() {
+            self.excluded_tracks.remove(playlist_id);
+        }
+        else {
+            self.excluded_tracks.insert(playlist_id.to_string(), excluded.clone());
+        }
+    }
+    pub fn save (&self) -> MyResult<()> {
+        let path = settings_path().ok_or("не удалось определить папку настроек пользователя".to_string())?;
+        self.save_to(&path)
+    }
+    fn save_to (&self, path: &Path) -> MyResult<()> {
+        if let Some(reason) = &self.save_blocked {
+            return Err(reason.clone());
+        }
+        let mut root = serde_json::Map::new();
+        root.insert("smart_playlists".into(), serde_json::Value::Array(
+            self.smart_playlists.iter().map(|preset| serde_json::json!({
+                "name":       preset.name,
+                "expression": preset.expression,
+            })).collect()
+        ));
+        root.insert("playlist_format".into(), self.playlist_format.key().into());
+        root.insert("relative_locations".into(), self.relative_locations.into());
+        root.insert("archive_format".into(), self.archive_format.key().into());
+        root.insert("transfer_mode".into(), self.transfer_mode.key().into());
+        root.insert("verify_checksums".into(), self.verify_checksums.into());
+        root.insert("copy_workers".into(), self.copy_workers.into());
+        root.insert("post_export_hook".into(), self.post_export_hook.clone().into());
+        root.insert("volume_preset".into(), self.volume_preset.key().into());
+        if let Some(volume_custom_mb) = self.volume_custom_mb {
+            root.insert("volume_custom_mb".into(), volume_custom_mb.into());
+        }
+        root.insert("excluded_tracks".into(), serde_json::Value::Object(
+            self.excluded_tracks.iter().map(|(playlist_id, paths)| (
+                playlist_id.clone(),
+                serde_json::Value::Array(paths.iter().map(|path| path.clone().into()).collect()),
+            )).collect()
+        ));
+        root.insert("playlist_templates".into(), serde_json::Value::Array(
+            self.playlist_templates.iter().map(|path| path.to_string_lossy().into_owned().into()).collect()
+        ));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| format!("не удалось создать папку '{}': {}", dir.to_string_lossy(), err))?;
+        }
+        let content = serde_json::to_string_pretty(&serde_json::Value::Object(root)).unwrap();
+        std::fs::write(path, content).map_err(|err| format!("не удалось записать настройки в '{}': {}", path.to_string_lossy(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir (name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audials-settings-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn missing_file_gives_defaults_silently () {
+        let dir = test_dir("missing");
+        let (settings, log) = Settings::load_from(&dir.join("settings.json"));
+        assert!(log.is_empty());
+        assert_eq!(settings.copy_workers, 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn corrupt_file_is_backed_up_before_save () {
+        let dir = test_dir("corrupt");
+        let path = dir.join("settings.json");
+        std::fs::write(&path, "{\"copy_workers\": 4,").unwrap();
+        let (settings, log) = Settings::load_from(&path);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].starts_with("# Ошибка"));
+        assert!(!path.exists());
+        let backups: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].file_name().unwrap().to_string_lossy().starts_with("settings.json.bad-"));
+        assert_eq!(std::fs::read_to_string(&backups[0]).unwrap(), "{\"copy_workers\": 4,");
+        settings.save_to(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&backups[0]).unwrap(), "{\"copy_workers\": 4,");
+        let (reloaded, log) = Settings::load_from(&path);
+        assert!(log.is_empty());
+        assert_eq!(reloaded.copy_workers, 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn blocked_save_does_not_overwrite () {
+        let dir = test_dir("blocked");
+        let path = dir.join("settings.json");
+        std::fs::write(&path, "not json").unwrap();
+        let mut settings = Settings::new();
+        settings.save_blocked = Some("битый файл".to_string());
+        assert!(settings.save_to(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,132 @@
+// Пакетный экспорт нескольких плейлистов с общей папкой треков: каждый
+// найденный файл переносится один раз в Music/ внутри папки результата, а
+// плейлисты пишутся рядом и ссылаются на эту папку. Одинаковыми считаются
+// треки с одним и тем же найденным путём
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{AudioEntry, checksums};
+use crate::export::{ExportOptions, ExportReport, format_size, plan_dest_paths, sanitize_file_name, transfer_planned, write_playlist_file_with_manifest};
+use crate::export_manifest::ExportManifest;
+use crate::hooks::{HookContext, run_post_export_hook};
+use crate::library_formats::ResolvedPlaylist;
+use crate::playlist_formats::PlaylistTrack;
+use crate::space_estimate::estimate_space;
+
+pub const POOL_DIR_NAME: &str = "Music";
+
+pub fn export_playlists_to_pool (playlists: &[ResolvedPlaylist], output_path: &Path, options: &ExportOptions) -> ExportReport {
+    let mut report = ExportReport {
+        log:           Vec::new(),
+        exported:      0,
+        failed:        0,
+        playlist_path: None,
+    };
+    let pool_path = output_path.join(POOL_DIR_NAME);
+    if let Err(err) = std::fs::create_dir_all(&pool_path) {
+        report.log.push(format!("# Ошибка: не удалось создать папку '{}': {}", pool_path.to_string_lossy(), err));
+        return report;
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    let unique_audio: Vec<&AudioEntry> = playlists.iter()
+        .flat_map(|playlist| playlist.audio.iter())
+        .filter(|audio| seen.insert(audio.path.as_str()))
+        .collect();
+    let dest_paths = plan_dest_paths(&unique_audio, &pool_path);
+    let planned: Vec<(&str, &Path)> = unique_audio.iter().zip(&dest_paths).map(|(audio, dest_path)| (audio.path.as_str(), dest_path.as_path())).collect();
+    let manifest_name = playlists.iter().map(|playlist| playlist.name.as_str()).collect::<Vec<&str>>().join(", ");
+    let space_estimate = estimate_space(&planned, output_path);
+    report.log.push(space_estimate.summary());
+    match space_estimate.check(output_path, options.transfer_mode) {
+        Ok(note) => report.log.extend(note.map(|note| format!("# Замечание: {}", note))),
+        Err(err) => {
+            report.log.push(format!("# Ошибка: {}", err));
+            return report;
+        },
+    }
+    let mut manifest = match ExportManifest::begin(output_path, &manifest_name, &planned, &mut report.log) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            report.log.push(format!("# Ошибка: {}", err));
+            return report;
+        },
+    };
+    // путь в библиотеке -> путь в общей папке, только для перенесённых
+    let mut pool_paths = HashMap::new();
+    let mut sizes = HashMap::new();
+    let results = transfer_planned(&mut manifest, options);
+    for (i, ((audio, dest_path), result)) in unique_audio.iter().zip(dest_paths).zip(results).enumerate() {
+        match result {
+            Ok(method) => {
+                report.log.push(format!("{} => {} ({})", audio.path, dest_path.to_string_lossy(), method));
+                report.exported += 1;
+                sizes.insert(audio.path.as_str(), manifest.entries[i].size);
+                pool_paths.insert(audio.path.as_str(), dest_path);
+            },
+            Err(err) => {
+                report.log.push(format!("# Ошибка: {}", err));
+                report.failed += 1;
+            },
+        }
+    }
+
+    // одноимённые плейлисты Audials не должны затирать файлы друг друга
+    let mut used_names = std::collections::HashSet::new();
+    let mut total_occurrences = 0;
+    let mut total_bytes = 0;
+    // для команды после экспорта: имя, файл плейлиста, перенесено, не удалось
+    let mut written_playlists = Vec::new();
+    for playlist in playlists {
+        let mut name = playlist.name.clone();
+        let mut number = 1;
+        while !used_names.insert(sanitize_file_name(&name).to_lowercase()) {
+            number += 1;
+            name = format!("{} ({})", playlist.name, number);
+        }
+        let tracks: Vec<PlaylistTrack> = playlist.audio.iter()
+            .filter_map(|audio| pool_paths.get(audio.path.as_str()).map(|dest_path| PlaylistTrack {audio, dest_path: dest_path.clone()}))
+            .collect();
+        total_occurrences += tracks.len();
+        total_bytes += tracks.iter().map(|track| sizes[track.audio.path.as_str()]).sum::<u64>();
+        let playlist_path = match write_playlist_file_with_manifest(&name, &tracks, output_path, options, Some(&mut manifest)) {
+            Ok(playlist_path) => {
+                report.log.push(format!("Записан плейлист {}: {} (треков: {})", options.format, playlist_path.to_string_lossy(), tracks.len()));
+                Some(playlist_path)
+            },
+            Err(err) => {
+                report.log.push(format!("# Ошибка: {}", err));
+                None
+            },
+        };
+        written_playlists.push((name, playlist_path, tracks.len(), playlist.audio.len() - tracks.len()));
+    }
+    if options.verify_checksums {
+        match checksums::write_checksum_file(output_path, &mut manifest) {
+            Ok(checksum_path) => report.log.push(format!("Записаны контрольные суммы: {}", checksum_path.to_string_lossy())),
+            Err(err) => report.log.push(format!("# Ошибка: {}", err)),
+        }
+    }
+    if let Err(err) = manifest.finish() {
+        report.log.push(format!("# Ошибка: {}", err));
+    }
+    let pool_bytes: u64 = sizes.values().sum();
+    report.log.push(format!(
+        "Общая папка {}: {} уникальных треков ({}) вместо {} копий ({}), сэкономлено {}",
+        pool_path.to_string_lossy(), pool_paths.len(), format_size(pool_bytes), total_occurrences, format_size(total_bytes), format_size(total_bytes - pool_bytes)
+    ));
+    // команда запускается, когда общая папка и SHA256SUMS уже готовы
+    if let Some(command) = &options.post_export_hook {
+        for (name, playlist_path, exported, failed) in &written_playlists {
+            report.log.extend(run_post_export_hook(command, &HookContext {
+                playlist_name: name,
+                output_path,
+                playlist_path: playlist_path.as_deref(),
+                exported:      *exported,
+                failed:        *failed,
+            }));
+        }
+    }
+    report
+}
@@ -0,0 +1,361 @@
+// Умные плейлисты: список треков задаётся не плейлистом Audials, а фильтром
+// по колонкам file_t. Фильтр - условия через ';', выполняться должны все:
+//     artist ~ Queen; year >= 1980; path glob */Rock/*; added >= 2026
+// Операторы: = и != (сравнение без учёта регистра), ~ и !~ (содержит),
+// glob (* и ?), >, >=, <, <= (для чисел и дат). Значение в кавычках может
+// содержать ';'. Дата "2026" или "2026-03" - это весь год или месяц:
+// added = 2026 подходит для любого дня 2026 года, added > 2026 - начиная с 2027.
+// Найденные id дальше идут той же дорогой, что и треки обычного плейлиста
+
+use crate::{AudioEntryId, AudioMetadata, MyResult};
+
+pub const FIELDS: [&str; 13] = [
+    "title", "artist", "path", "album", "album_artist", "genre", "year",
+    "track_number", "disc_number", "duration", "bitrate", "size", "added",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Equals,
+    NotEquals,
+    Contains,
+    NotContains,
+    Glob,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+// Порядок важен: двухсимвольные операторы проверяются раньше односимвольных
+const OPERATORS: [(&str, Operator); 9] = [
+    ("!=", Operator::NotEquals),
+    ("!~", Operator::NotContains),
+    (">=", Operator::GreaterOrEqual),
+    ("<=", Operator::LessOrEqual),
+    ("glob", Operator::Glob),
+    ("=", Operator::Equals),
+    ("~", Operator::Contains),
+    (">", Operator::Greater),
+    ("<", Operator::Less),
+];
+
+struct Condition {
+    field:    &'static str,
+    operator: Operator,
+    value:    String,
+    // Числа и даты - полуинтервал [начало, конец): для числа 5 это [5, 6),
+    // для added = 2026-03 - весь март
+    range:    Option<(u64, u64)>,
+}
+
+pub struct SmartFilter {
+    conditions: Vec<Condition>,
+}
+
+struct SmartRow {
+    id:       AudioEntryId,
+    title:    String,
+    artist:   String,
+    path:     String,
+    metadata: AudioMetadata,
+}
+
+fn parse_date_range (value: &str) -> Option<(u64, u64)> {
+    let start = crate::parse_date(value)?;
+    let parts: Vec<u64> = value.trim().split('-').map(|part| part.parse().ok()).collect::<Option<Vec<u64>>>()?;
+    let end = match parts[..] {
+        [year] => crate::parse_date(&(year + 1).to_string())?,
+        [year, 12] => crate::parse_date(&format!("{}-01", year + 1))?,
+        [year, month] => crate::parse_date(&format!("{}-{}", year, month + 1))?,
+        _ => start + 86400,
+    };
+    Some((start, end))
+}
+
+fn parse_range (field: &str, value: &str) -> Option<(u64, u64)> {
+    let number: u64 = match field {
+        "added" => return parse_date_range(value),
+        "duration" => match value.split_once(':') {
+            Some((minutes, seconds)) => minutes.trim().parse::<u64>().ok()? * 60 + seconds.trim().parse::<u64>().ok()?,
+            None => value.parse().ok()?,
+        },
+        _ => value.parse().ok()?,
+    };
+    Some((number, number + 1))
+}
+
+// Делит фильтр на условия по ';' и переводу строки вне кавычек
+fn split_conditions (expression: &str) -> MyResult<Vec<&str>> {
+    let mut conditions = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    for (index, c) in expression.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' | '\n' if !in_quotes => {
+                conditions.push(&expression[start..index]);
+                start = index + 1;
+            },
+            _ => (),
+        }
+    }
+    if in_quotes {
+        return Err(format!("не закрыта кавычка в условии '{}'", expression[start..].trim()));
+    }
+    conditions.push(&expression[start..]);
+    Ok(conditions)
+}
+
+fn parse_condition (condition: &str) -> MyResult<Condition> {
+    let field_end = condition.find(|c: char| !(c.is_alphanumeric() || '_' == c)).unwrap_or(condition.len());
+    let (field_name, rest) = condition.split_at(field_end);
+    let field = match FIELDS.iter().find(|field| **field == field_name) {
+        Some(field) => *field,
+        None => return Err(format!("неизвестное поле '{}' в условии '{}', доступны: {}", field_name, condition, FIELDS.join(", "))),
+    };
+    let rest = rest.trim_start();
+    let (operator, value) = match OPERATORS.iter().find(|(text, _)| rest.starts_with(text)) {
+        Some((text, operator)) => (*operator, rest[text.len()..].trim()),
+        None => return Err(format!("после поля {} ожидался оператор (=, !=, ~, !~, glob, >, >=, <, <=) в условии '{}'", field, condition)),
+    };
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => match quoted.strip_suffix('"') {
+            Some(unquoted) => unquoted.to_string(),
+            None => return Err(format!("не закрыта кавычка в условии '{}'", condition)),
+        },
+        None => value.to_string(),
+    };
+    let range = parse_range(field, &value);
+    let is_comparison = [Operator::Greater, Operator::GreaterOrEqual, Operator::Less, Operator::LessOrEqual].contains(&operator);
+    if is_comparison && range.is_none() {
+        return Err(format!(
+            "для сравнения поля {} нужно {} в условии '{}'",
+            field,
+            match field {
+                "added" => "дата вида ГГГГ, ГГГГ-ММ или ГГГГ-ММ-ДД",
+                "duration" => "число секунд или м:сс",
+                _ => "число",
+            },
+            condition
+        ));
+    }
+    Ok(Condition {
+        field,
+        operator,
+        value,
+        range,
+    })
+}
+
+// Сопоставление с шаблоном из * и ?, с возвратом к последней звёздочке
+fn glob_matches (pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && ('?' == pattern[p] || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        }
+        else if p < pattern.len() && '*' == pattern[p] {
+            last_star = Some((p, t));
+            p += 1;
+        }
+        else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        }
+        else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| '*' == *c)
+}
+
+impl SmartRow {
+    fn number_field (&self, field: &str) -> Option<u64> {
+        match field {
+            "year"         => self.metadata.year,
+            "track_number" => self.metadata.track_number,
+            "disc_number"  => self.metadata.disc_number,
+            "duration"     => self.metadata.duration_secs,
+            "bitrate"      => self.metadata.bitrate_kbps,
+            "size"         => self.metadata.file_size,
+            "added"        => self.metadata.date_added,
+            _              => None,
+        }
+    }
+    // Отсутствующее значение для текстовых операторов - пустая строка
+    fn text_field (&self, field: &str) -> String {
+        match field {
+            "title"        => self.title.clone(),
+            "artist"       => self.artist.clone(),
+            "path"         => self.path.replace('\\', "/"),
+            "album"        => self.metadata.album.clone().unwrap_or_default(),
+            "album_artist" => self.metadata.album_artist.clone().unwrap_or_default(),
+            "genre"        => self.metadata.genre.clone().unwrap_or_default(),
+            "added"        => self.metadata.date_added.map(crate::format_date).unwrap_or_default(),
+            _              => self.number_field(field).map(|number| number.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+impl Condition {
+    fn matches (&self, row: &SmartRow) -> bool {
+        let text = || row.text_field(self.field).to_lowercase();
+        let value = self.value.to_lowercase();
+        let compare = |check: fn(u64, (u64, u64)) -> bool| match (row.number_field(self.field), self.range) {
+            (Some(number), Some(range)) => check(number, range),
+            _ => false,
+        };
+        let in_range = |number: u64, (start, end): (u64, u64)| start <= number && number < end;
+        // Дату сравниваем по периоду, иначе added = 2026 не совпало бы ни с одним днём
+        let by_period = "added" == self.field && self.range.is_some();
+        match self.operator {
+            Operator::Equals if by_period    => compare(in_range),
+            Operator::NotEquals if by_period => !compare(in_range),
+            Operator::Equals         => text() == value,
+            Operator::NotEquals      => text() != value,
+            Operator::Contains       => text().contains(&value),
+            Operator::NotContains    => !text().contains(&value),
+            Operator::Glob           => glob_matches(&value.chars().collect::<Vec<char>>(), &text().chars().collect::<Vec<char>>()),
+            Operator::Greater        => compare(|number, (_, end)| number >= end),
+            Operator::GreaterOrEqual => compare(|number, (start, _)| number >= start),
+            Operator::Less           => compare(|number, (start, _)| number < start),
+            Operator::LessOrEqual    => compare(|number, (_, end)| number < end),
+        }
+    }
+}
+
+impl SmartFilter {
+    pub fn parse (expression: &str) -> MyResult<SmartFilter> {
+        let conditions = split_conditions(expression)?
+            .into_iter()
+            .map(|condition| condition.trim())
+            .filter(|condition| !condition.is_empty())
+            .map(parse_condition)
+            .collect::<MyResult<Vec<Condition>>>()?;
+        if conditions.is_empty() {
+            return Err("фильтр пуст, нужно хотя бы одно условие".to_string());
+        }
+        Ok(SmartFilter {conditions})
+    }
+    fn matches (&self, row: &SmartRow) -> bool {
+        self.conditions.iter().all(|condition| condition.matches(row))
+    }
+    // id подходящих треков в порядке исполнитель, альбом, диск, трек, название
+    pub fn query_ids (&self, connection: &rusqlite::Connection, schema: &crate::modb_schema::ModbSchema) -> rusqlite::Result<Vec<AudioEntryId>> {
+        let optional_columns = schema.optional_columns();
+        let mut statement = connection.prepare(format!("SELECT {} FROM {}", schema.select_list(), schema.table).as_str())?;
+        let mut rows = statement.query([])?;
+        let mut matched = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut metadata = AudioMetadata::default();
+            for column in &optional_columns {
+                metadata.set_from_db_column(column, row.get(*column)?);
+            }
+            let smart_row = SmartRow {
+                id:     row.get("ft_id")?,
+                title:  row.get::<_, Option<String>>("ft_title")?.unwrap_or_default(),
+                artist: row.get::<_, Option<String>>("ft_artist")?.unwrap_or_default(),
+                path:   row.get::<_, Option<String>>("ft_path")?.unwrap_or_default(),
+                metadata,
+            };
+            if self.matches(&smart_row) {
+                matched.push(smart_row);
+            }
+        }
+        matched.sort_by_cached_key(|row| (
+            row.artist.to_lowercase(),
+            row.metadata.album.as_ref().map(|album| album.to_lowercase()),
+            row.metadata.disc_number,
+            row.metadata.track_number,
+            row.title.to_lowercase(),
+        ));
+        let mut ids: Vec<AudioEntryId> = matched.iter().map(|row| row.id).collect();
+        // одна и та же запись может встретиться в file_t несколько раз
+        let mut seen = std::collections::HashSet::new();
+        ids.retain(|id| seen.insert(*id));
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_filter_is_rejected () {
+        assert!(SmartFilter::parse("").is_err());
+        assert!(SmartFilter::parse(" ; \n ;").is_err());
+    }
+
+    #[test]
+    fn unterminated_quote_is_rejected () {
+        assert!(SmartFilter::parse("artist = \"Queen").is_err());
+        assert!(SmartFilter::parse("artist = \"").is_err());
+        assert!(SmartFilter::parse("year >= 1980; title = \"Live").err().unwrap().contains("кавычка"));
+    }
+
+    #[test]
+    fn quoted_value_is_unquoted () {
+        let filter = SmartFilter::parse("title ~ \"a b\"; year >= 1980").unwrap();
+        assert_eq!(filter.conditions.len(), 2);
+        assert_eq!(filter.conditions[0].value, "a b");
+        assert_eq!(filter.conditions[1].range, Some((1980, 1981)));
+    }
+
+    #[test]
+    fn separator_inside_quotes_is_part_of_value () {
+        let filter = SmartFilter::parse("title = \"A; B\"; artist ~ \"x\ny\"").unwrap();
+        assert_eq!(filter.conditions.len(), 2);
+        assert_eq!(filter.conditions[0].value, "A; B");
+        assert_eq!(filter.conditions[1].value, "x\ny");
+        assert!(SmartFilter::parse("title = \"Live; artist = \"Queen\"").err().unwrap().contains("кавычка"));
+    }
+
+    fn row_added (date: &str) -> SmartRow {
+        SmartRow {
+            id:       1,
+            title:    String::new(),
+            artist:   String::new(),
+            path:     String::new(),
+            metadata: AudioMetadata {date_added: crate::parse_date(date).map(|start| start + 3600), ..AudioMetadata::default()},
+        }
+    }
+
+    #[test]
+    fn added_year_and_month_match_whole_period () {
+        let matches = |filter: &str, date: &str| SmartFilter::parse(filter).unwrap().matches(&row_added(date));
+        assert!(matches("added = 2026", "2026-01-01"));
+        assert!(matches("added = 2026", "2026-12-31"));
+        assert!(!matches("added = 2026", "2025-12-31"));
+        assert!(matches("added = 2026-03", "2026-03-31"));
+        assert!(!matches("added = 2026-03", "2026-04-01"));
+        assert!(matches("added = 2026-12", "2026-12-31"));
+        assert!(matches("added = 2026-03-05", "2026-03-05"));
+        assert!(!matches("added != 2026", "2026-07-15"));
+        assert!(matches("added != 2026", "2027-01-01"));
+        assert!(matches("added > 2025", "2026-01-01"));
+        assert!(!matches("added > 2025", "2025-12-31"));
+        assert!(matches("added <= 2025-12", "2025-12-31"));
+        assert!(!matches("added < 2026", "2026-01-01"));
+    }
+
+    #[test]
+    fn impossible_date_is_rejected () {
+        assert!(crate::parse_date("2026-02-31").is_none());
+        assert!(crate::parse_date("2026-02-29").is_none());
+        assert!(crate::parse_date("2024-02-29").is_some());
+        assert!(crate::parse_date("2026-04-31").is_none());
+        assert!(SmartFilter::parse("added >= 2026-02-31").is_err());
+    }
+
+    #[test]
+    fn glob_backtracks_to_last_star () {
+        let chars = |text: &str| text.chars().collect::<Vec<char>>();
+        assert!(glob_matches(&chars("*/rock/*"), &chars("music/rock/a.mp3")));
+        assert!(glob_matches(&chars("a*b?"), &chars("aXbYbZ")));
+        assert!(!glob_matches(&chars("*.mp3"), &chars("a.flac")));
+    }
+}
@@ -0,0 +1,186 @@
+// Оценка места перед экспортом: сколько весят найденные треки, сколько из них
+// уже лежит в папке результата (тот же файл того же размера, например после
+// прерванного экспорта) и хватит ли свободного места на её диске
+
+use std::path::Path;
+
+use crate::{AudioEntry, MyResult};
+use crate::export::{TransferMode, format_size, plan_dest_paths};
+use crate::volumes::{MB, VolumePreset};
+
+// запас на файлы плейлиста, журнал и SHA256SUMS
+const RESERVE_BYTES: u64 = 64 << 10;
+
+pub struct TrackSpace {
+    // None - исходный файл не читается
+    pub size:    Option<u64>,
+    pub present: bool,
+}
+
+pub struct SpaceEstimate {
+    pub tracks:          Vec<TrackSpace>,
+    pub total:           u64,
+    pub already_present: u64,
+    // None - свободное место узнать не удалось
+    pub available:       Option<u64>,
+}
+impl SpaceEstimate {
+    pub fn needed (&self) -> u64 {
+        self.total - self.already_present
+    }
+    pub fn fits (&self) -> bool {
+        match self.available {
+            Some(available) => self.needed() + RESERVE_BYTES <= available,
+            None => true,
+        }
+    }
+    // Сколько первых треков поместится в свободное место
+    pub fn fitting_prefix (&self) -> usize {
+        let available = match self.available {
+            Some(available) => available.saturating_sub(RESERVE_BYTES),
+            None => return self.tracks.len(),
+        };
+        let mut needed = 0;
+        self.tracks.iter().take_while(|track| {
+            if !track.present {
+                needed += track.size.unwrap_or(0);
+            }
+            needed <= available
+        }).count()
+    }
+    // Размер тома для предложения разбить плейлист: самый крупный готовый,
+    // который помещается в свободное место, иначе всё свободное место в МБ.
+    // None - места нет совсем или его не удалось узнать
+    pub fn fitting_volume (&self) -> Option<(VolumePreset, u64)> {
+        let available = self.available?.saturating_sub(RESERVE_BYTES);
+        let preset = [VolumePreset::Dvd, VolumePreset::Cd].iter().copied()
+            .find(|preset| preset.max_bytes().map_or(false, |max_bytes| max_bytes <= available));
+        match preset {
+            Some(preset) => preset.max_bytes().map(|max_bytes| (preset, max_bytes)),
+            None if MB <= available => Some((VolumePreset::Custom, available / MB * MB)),
+            None => None,
+        }
+    }
+    pub fn summary (&self) -> String {
+        format!(
+            "Размер треков: {}, уже в папке: {}, нужно записать: {}, свободно: {}",
+            format_size(self.total),
+            format_size(self.already_present),
+            format_size(self.needed()),
+            self.available.map_or(String::from("неизвестно"), format_size)
+        )
+    }
+    // Ошибка, если экспорт не поместится; ссылки места почти не занимают,
+    // поэтому для них только замечание
+    pub fn check (&self, output_path: &Path, transfer_mode: TransferMode) -> MyResult<Option<String>> {
+        if self.fits() {
+            return Ok(None);
+        }
+        let message = format!(
+            "на диске '{}' свободно {}, а нужно {} и {} на плейлист и журнал (всего {}, уже в папке {}); поместятся первые {} треков из {}",
+            output_path.to_string_lossy(),
+            self.available.map_or(String::new(), format_size),
+            format_size(self.needed()),
+            format_size(RESERVE_BYTES),
+            format_size(self.total),
+            format_size(self.already_present),
+            self.fitting_prefix(),
+            self.tracks.len()
+        );
+        if TransferMode::Copy == transfer_mode || TransferMode::Reflink == transfer_mode {
+            Err(format!("{} - разбейте плейлист на тома или освободите место, экспорт не начат", message))
+        }
+        else {
+            Ok(Some(format!("{}, но при ссылках место почти не нужно", message)))
+        }
+    }
+}
+
+// Свободное место на диске папки; самой папки может ещё не быть
+fn available_space (output_path: &Path) -> Option<u64> {
+    let existing_dir = output_path.ancestors().find(|dir| dir.is_dir())?;
+    fs2::available_space(existing_dir).ok()
+}
+
+// planned - пары исходный файл -> файл в папке результата, как в журнале экспорта
+pub fn estimate_space (planned: &[(&str, &Path)], output_path: &Path) -> SpaceEstimate {
+    let mut estimate = SpaceEstimate {
+        tracks:          Vec::new(),
+        total:           0,
+        already_present: 0,
+        available:       available_space(output_path),
+    };
+    for (source, dest_path) in planned {
+        let size = std::fs::metadata(source).ok().map(|metadata| metadata.len());
+        // символьная ссылка от прошлого экспорта места не занимает, копию на её место ещё писать
+        let present = match (size, dest_path.symlink_metadata()) {
+            (Some(size), Ok(dest_metadata)) => dest_metadata.is_file() && dest_metadata.len() == size,
+            _ => false,
+        };
+        estimate.total += size.unwrap_or(0);
+        if present {
+            estimate.already_present += size.unwrap_or(0);
+        }
+        estimate.tracks.push(TrackSpace {size, present});
+    }
+    estimate
+}
+
+// Оценка для предпросмотра плейлиста - с теми же именами файлов, что даст экспорт
+pub fn estimate_playlist_space (audio_in_playlist: &[AudioEntry], output_path: &Path) -> SpaceEstimate {
+    let dest_paths = plan_dest_paths(audio_in_playlist, output_path);
+    let planned: Vec<(&str, &Path)> = audio_in_playlist.iter().zip(&dest_paths).map(|(audio, dest_path)| (audio.path.as_str(), dest_path.as_path())).collect();
+    estimate_space(&planned, output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate (sizes: &[(Option<u64>, bool)], available: Option<u64>) -> SpaceEstimate {
+        let tracks: Vec<TrackSpace> = sizes.iter().map(|(size, present)| TrackSpace {size: *size, present: *present}).collect();
+        SpaceEstimate {
+            total:           tracks.iter().filter_map(|track| track.size).sum(),
+            already_present: tracks.iter().filter(|track| track.present).filter_map(|track| track.size).sum(),
+            tracks,
+            available,
+        }
+    }
+
+    #[test]
+    fn fitting_prefix_skips_present_and_unreadable_tracks () {
+        let tracks = [(Some(400 * MB), false), (Some(500 * MB), true), (None, false), (Some(300 * MB), false), (Some(100 * MB), false)];
+        assert_eq!(4, estimate(&tracks, Some(700 * MB + RESERVE_BYTES)).fitting_prefix());
+        assert_eq!(3, estimate(&tracks, Some(699 * MB + RESERVE_BYTES)).fitting_prefix());
+        assert_eq!(0, estimate(&tracks, Some(RESERVE_BYTES)).fitting_prefix());
+        assert_eq!(5, estimate(&tracks, None).fitting_prefix());
+    }
+
+    #[test]
+    fn check_refuses_copy_and_only_warns_for_links () {
+        let output_path = Path::new("/media/card");
+        let fitting = estimate(&[(Some(100 * MB), false), (Some(100 * MB), true)], Some(100 * MB + RESERVE_BYTES));
+        assert!(fitting.fits());
+        assert_eq!(Ok(None), fitting.check(output_path, TransferMode::Copy));
+
+        let too_big = estimate(&[(Some(100 * MB), false), (Some(100 * MB), false)], Some(150 * MB));
+        assert!(!too_big.fits());
+        let err = too_big.check(output_path, TransferMode::Copy).err().unwrap();
+        assert!(err.contains("поместятся первые 1 треков из 2"));
+        assert!(err.contains("экспорт не начат"));
+        assert!(too_big.check(output_path, TransferMode::Reflink).is_err());
+        assert!(too_big.check(output_path, TransferMode::SymlinkAbsolute).unwrap().unwrap().contains("при ссылках"));
+
+        assert_eq!(Ok(None), estimate(&[(Some(100 * MB), false)], None).check(output_path, TransferMode::Copy));
+    }
+
+    #[test]
+    fn fitting_volume_prefers_largest_preset () {
+        let tracks = [(Some(10_000 * MB), false)];
+        assert_eq!(Some((VolumePreset::Dvd, 4_700_000_000)), estimate(&tracks, Some(5_000 * MB)).fitting_volume());
+        assert_eq!(Some((VolumePreset::Cd, 700 * MB)), estimate(&tracks, Some(1_000 * MB)).fitting_volume());
+        assert_eq!(Some((VolumePreset::Custom, 300 * MB)), estimate(&tracks, Some(300 * MB + RESERVE_BYTES + 5)).fitting_volume());
+        assert_eq!(None, estimate(&tracks, Some(RESERVE_BYTES + 5)).fitting_volume());
+        assert_eq!(None, estimate(&tracks, None).fitting_volume());
+    }
+}
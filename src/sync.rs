@@ -0,0 +1,363 @@
+// Синхронизация папки результата с плейлистом: при повторном экспорте на ту же
+// карту переносится только новое и изменившееся, а треки, которых больше нет в
+// плейлисте, удаляются. Сначала строится план - он показывается как пробный
+// прогон, и только после подтверждения применяется.
+//
+// Удаляются только аудиофайлы прямо в папке результата, которые туда положил
+// прошлый экспорт этого плейлиста: они записаны в его журнале экспорта
+// (export_manifest) или перечислены в его файле плейлиста. Остальное (обложки,
+// чужие папки, другие плейлисты, треки, положенные руками) не наше и не трогается
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::AudioEntry;
+use crate::export::{ExportOptions, ExportReport, TransferJob, TransferOutcome, plan_dest_paths, run_transfer_jobs, sanitize_file_name, write_playlist_file};
+use crate::export_manifest::ExportManifest;
+use crate::hooks::{HookContext, run_post_export_hook};
+use crate::playlist_formats::{PlaylistTrack, escape_xml, percent_encode_path};
+
+const AUDIO_EXTENSIONS: [&str; 16] = [
+    "mp3", "flac", "m4a", "mp4", "aac", "ogg", "oga", "opus", "wma", "wav", "aif", "aiff", "ape", "wv", "mpc", "alac",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncAction {
+    Add,
+    Update,
+    Keep,
+    Remove,
+}
+
+pub struct SyncPlanEntry {
+    pub action: SyncAction,
+    // индекс трека в плейлисте, для Remove - None
+    pub track:  Option<usize>,
+    pub dest:   PathBuf,
+    pub reason: String,
+}
+
+pub struct SyncPlan {
+    pub output_path: PathBuf,
+    pub entries:     Vec<SyncPlanEntry>,
+}
+impl SyncPlan {
+    pub fn count (&self, action: SyncAction) -> usize {
+        self.entries.iter().filter(|entry| entry.action == action).count()
+    }
+    // Строки для лога пробного прогона: неизменные файлы только числом
+    pub fn diff (&self) -> Vec<String> {
+        let mut result = Vec::new();
+        for entry in &self.entries {
+            let sign = match entry.action {
+                SyncAction::Add    => "+",
+                SyncAction::Update => "~",
+                SyncAction::Remove => "-",
+                SyncAction::Keep   => continue,
+            };
+            result.push(format!("{} {} ({})", sign, entry.dest.to_string_lossy(), entry.reason));
+        }
+        result.push(format!(
+            "План синхронизации '{}': добавить {}, обновить {}, удалить {}, без изменений {}",
+            self.output_path.to_string_lossy(),
+            self.count(SyncAction::Add), self.count(SyncAction::Update), self.count(SyncAction::Remove), self.count(SyncAction::Keep)
+        ));
+        result
+    }
+}
+
+fn is_audio_file (path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => AUDIO_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// Имена файлов (в нижнем регистре), на которые ссылается файл плейлиста.
+// Форматы разбирать не нужно: путь в любом из них стоит в отдельной строке, в
+// кавычках, между тегами или после '=' (PLS), а имя - последняя часть пути
+fn referenced_file_names (content: &str) -> HashSet<String> {
+    let mut result = HashSet::new();
+    for line in content.lines() {
+        let after_equals = line.split_once('=').map(|(_, value)| value);
+        for piece in line.split(&['"', '<', '>'][..]).chain(after_equals) {
+            let file_name = piece.trim().rsplit(&['/', '\\'][..]).next().unwrap_or("");
+            if !file_name.is_empty() {
+                result.insert(file_name.to_lowercase());
+            }
+        }
+    }
+    result
+}
+
+// Имена файлов, которые прошлый экспорт этого плейлиста положил в папку: из
+// журнала экспорта и из файла плейлиста с его именем (в любом формате)
+fn previously_exported_names (playlist_name: &str, output_path: &Path) -> HashSet<String> {
+    let mut result = HashSet::new();
+    let mut playlist_files = Vec::new();
+    if let Ok(Some(manifest)) = ExportManifest::load(output_path) {
+        if manifest.name == playlist_name {
+            result.extend(manifest.entries.iter()
+                .filter(|entry| entry.dest.parent() == Some(output_path))
+                .filter_map(|entry| entry.dest.file_name())
+                .map(|file_name| file_name.to_string_lossy().to_lowercase()));
+            playlist_files.extend(manifest.playlist_files.into_iter().map(|playlist_file| playlist_file.path));
+        }
+    }
+    let playlist_stem = sanitize_file_name(playlist_name).to_lowercase();
+    if let Ok(read_dir) = output_path.read_dir() {
+        playlist_files.extend(read_dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| !is_audio_file(path))
+            .filter(|path| match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().to_lowercase() == playlist_stem,
+                None => false,
+            }));
+    }
+    for playlist_file in playlist_files {
+        // файл плейлиста в чужой кодировке или не текст - по нему ничего не удаляется
+        if let Ok(content) = std::fs::read_to_string(&playlist_file) {
+            result.extend(referenced_file_names(&content));
+        }
+    }
+    result
+}
+
+// Имя в том виде, в каком его пишут форматы: как есть, для XML и в URI
+fn is_previously_exported (file_name: &str, exported_names: &HashSet<String>) -> bool {
+    let file_name = file_name.to_lowercase();
+    [escape_xml(&file_name), percent_encode_path(Path::new(&file_name)).to_lowercase(), file_name]
+        .iter()
+        .any(|form| exported_names.contains(form))
+}
+
+fn same_content (a: &Path, b: &Path) -> std::io::Result<bool> {
+    use std::io::Read;
+    let mut a = std::io::BufReader::new(std::fs::File::open(a)?);
+    let mut b = std::io::BufReader::new(std::fs::File::open(b)?);
+    let mut buffer_a = vec![0u8; 1 << 16];
+    let mut buffer_b = vec![0u8; 1 << 16];
+    loop {
+        let read = a.read(&mut buffer_a)?;
+        if 0 == read {
+            return Ok(0 == b.read(&mut buffer_b[..1])?);
+        }
+        b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+// Почему файл в папке результата нужно обновить; None - он совпадает с
+// исходным. Без compare_content совпадением считается тот же размер и время
+// изменения не раньше исходного - копия всегда новее оригинала
+fn update_reason (source: &Path, dest: &Path, compare_content: bool) -> Option<String> {
+    let (source_metadata, dest_metadata) = match (source.metadata(), dest.metadata()) {
+        (Ok(source_metadata), Ok(dest_metadata)) => (source_metadata, dest_metadata),
+        (Err(err), _) => return Some(format!("исходный файл недоступен: {}", err)),
+        (_, Err(_)) => return Some("файл в папке результата не читается".into()),
+    };
+    if source_metadata.len() != dest_metadata.len() {
+        return Some(format!("размер {} вместо {}", dest_metadata.len(), source_metadata.len()));
+    }
+    if compare_content {
+        return match same_content(source, dest) {
+            Ok(true) => None,
+            Ok(false) => Some("содержимое отличается".into()),
+            Err(err) => Some(format!("не удалось сравнить: {}", err)),
+        };
+    }
+    match (source_metadata.modified(), dest_metadata.modified()) {
+        (Ok(source_time), Ok(dest_time)) if dest_time < source_time => Some("исходный файл новее".into()),
+        _ => None,
+    }
+}
+
+pub fn plan_sync (playlist_name: &str, audio_in_playlist: &[AudioEntry], output_path: &Path, compare_content: bool) -> SyncPlan {
+    let mut entries = Vec::new();
+    let dest_paths = plan_dest_paths(audio_in_playlist, output_path);
+    for (i, (audio, dest_path)) in audio_in_playlist.iter().zip(&dest_paths).enumerate() {
+        let source = Path::new(&audio.path);
+        let (action, reason) = if dest_path.symlink_metadata().is_err() {
+            (SyncAction::Add, "нет в папке".to_string())
+        }
+        else {
+            match update_reason(source, dest_path, compare_content) {
+                Some(reason) => (SyncAction::Update, reason),
+                None => (SyncAction::Keep, String::new()),
+            }
+        };
+        entries.push(SyncPlanEntry {action, track: Some(i), dest: dest_path.clone(), reason});
+    }
+    // на windows и в FAT регистр в именах не различается, как в plan_dest_paths
+    let planned: HashSet<String> = dest_paths.iter()
+        .filter_map(|dest_path| dest_path.file_name())
+        .map(|file_name| file_name.to_string_lossy().to_lowercase())
+        .collect();
+    let exported_names = previously_exported_names(playlist_name, output_path);
+    if let Ok(read_dir) = output_path.read_dir() {
+        let mut stale: Vec<PathBuf> = read_dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| matches!(entry.file_type(), Ok(file_type) if !file_type.is_dir()))
+            .map(|entry| entry.path())
+            .filter(|path| is_audio_file(path))
+            .filter(|path| {
+                let file_name = path.file_name().unwrap().to_string_lossy();
+                !planned.contains(&file_name.to_lowercase()) && is_previously_exported(&file_name, &exported_names)
+            })
+            .collect();
+        stale.sort();
+        entries.extend(stale.into_iter().map(|dest| SyncPlanEntry {
+            action: SyncAction::Remove,
+            track:  None,
+            dest,
+            reason: "нет в плейлисте, положен прошлым экспортом".into(),
+        }));
+    }
+    SyncPlan {
+        output_path: output_path.to_path_buf(),
+        entries,
+    }
+}
+
+pub fn apply_sync (playlist_name: &str, audio_in_playlist: &[AudioEntry], plan: &SyncPlan, options: &ExportOptions) -> ExportReport {
+    let mut report = ExportReport {
+        log:           Vec::new(),
+        exported:      0,
+        failed:        0,
+        playlist_path: None,
+    };
+    // переносы идут параллельно и со сверкой сумм, как при обычном экспорте,
+    // а лог собирается по порядку плана
+    let (transfers, jobs): (Vec<usize>, Vec<TransferJob>) = plan.entries.iter().enumerate()
+        .filter(|(_, entry)| SyncAction::Keep != entry.action)
+        .filter_map(|(i, entry)| entry.track.map(|track| (i, (PathBuf::from(&audio_in_playlist[track].path), entry.dest.clone(), false, false))))
+        .unzip();
+    let mut outcomes: Vec<Option<TransferOutcome>> = plan.entries.iter().map(|_| None).collect();
+    run_transfer_jobs(&jobs, options, |job, outcome| outcomes[transfers[job]] = Some(outcome));
+    let mut tracks = Vec::new();
+    for (entry, outcome) in plan.entries.iter().zip(outcomes) {
+        let audio = match entry.track {
+            Some(i) => &audio_in_playlist[i],
+            None => {
+                match std::fs::remove_file(&entry.dest) {
+                    Ok(_) => report.log.push(format!("Удалён {} ({})", entry.dest.to_string_lossy(), entry.reason)),
+                    Err(err) => {
+                        report.log.push(format!("# Ошибка: не удалось удалить '{}': {}", entry.dest.to_string_lossy(), err));
+                        report.failed += 1;
+                    },
+                }
+                continue;
+            },
+        };
+        if SyncAction::Keep == entry.action {
+            report.exported += 1;
+            tracks.push(PlaylistTrack {audio, dest_path: entry.dest.clone()});
+            continue;
+        }
+        match outcome.unwrap_or_else(|| Err("перенос не выполнен".to_string())) {
+            Ok((method, _)) => {
+                report.log.push(format!("{} => {} ({}; {})", audio.path, entry.dest.to_string_lossy(), method, entry.reason));
+                report.exported += 1;
+                tracks.push(PlaylistTrack {audio, dest_path: entry.dest.clone()});
+            },
+            Err(err) => {
+                report.log.push(format!("# Ошибка: {}", err));
+                report.failed += 1;
+            },
+        }
+    }
+    match write_playlist_file(playlist_name, &tracks, &plan.output_path, options) {
+        Ok(playlist_path) => {
+            report.log.push(format!("Записан плейлист {}: {}", options.format, playlist_path.to_string_lossy()));
+            report.playlist_path = Some(playlist_path);
+        },
+        Err(err) => report.log.push(format!("# Ошибка: {}", err)),
+    }
+    report.log.push(format!(
+        "Синхронизация: добавлено {}, обновлено {}, удалено {}, без изменений {}",
+        plan.count(SyncAction::Add), plan.count(SyncAction::Update), plan.count(SyncAction::Remove), plan.count(SyncAction::Keep)
+    ));
+    if let Some(command) = &options.post_export_hook {
+        report.log.extend(run_post_export_hook(command, &HookContext {
+            playlist_name,
+            output_path:   &plan.output_path,
+            playlist_path: report.playlist_path.as_deref(),
+            exported:      report.exported,
+            failed:        report.failed,
+        }));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio (path: &Path) -> AudioEntry {
+        AudioEntry {
+            id:       1,
+            title:    String::new(),
+            artist:   String::new(),
+            path:     path.to_string_lossy().into_owned(),
+            metadata: Default::default(),
+        }
+    }
+
+    fn test_dir (name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audials-sync-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("library")).unwrap();
+        std::fs::create_dir_all(dir.join("output")).unwrap();
+        dir
+    }
+
+    fn removed (plan: &SyncPlan) -> Vec<String> {
+        plan.entries.iter()
+            .filter(|entry| SyncAction::Remove == entry.action)
+            .map(|entry| entry.dest.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn removes_only_files_of_previous_export () {
+        let dir = test_dir("stale");
+        let (library, output) = (dir.join("library"), dir.join("output"));
+        std::fs::write(library.join("keep.mp3"), b"keep").unwrap();
+        for file_name in ["keep.mp3", "Old Song.MP3", "Rock & Roll.flac", "manual.mp3", "cover.jpg"].iter() {
+            std::fs::write(output.join(file_name), b"x").unwrap();
+        }
+        std::fs::write(output.join("Road Trip.m3u8"), "#EXTM3U\nkeep.mp3\nold song.mp3\n").unwrap();
+        std::fs::write(output.join("Road Trip.xspf"), "<location>file:///card/Rock%20%26%20Roll.flac</location>\n").unwrap();
+        let plan = plan_sync("Road Trip", &[audio(&library.join("keep.mp3"))], &output, false);
+        let mut stale = removed(&plan);
+        stale.sort();
+        assert_eq!(vec!["Old Song.MP3".to_string(), "Rock & Roll.flac".to_string()], stale);
+        assert_eq!(1, plan.count(SyncAction::Update));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn foreign_playlist_does_not_mark_files () {
+        let dir = test_dir("foreign");
+        let output = dir.join("output");
+        std::fs::write(output.join("other.mp3"), b"x").unwrap();
+        std::fs::write(output.join("Chill.pls"), "[playlist]\nFile1=other.mp3\n").unwrap();
+        let plan = plan_sync("Road Trip", &[], &output, false);
+        assert!(removed(&plan).is_empty());
+        let plan = plan_sync("Chill", &[], &output, false);
+        assert_eq!(vec!["other.mp3".to_string()], removed(&plan));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn referenced_names_from_all_formats () {
+        let names = referenced_file_names("File1=C:\\Music\\A.mp3\n<media src=\"..\\b&amp;c.wma\"/>\n#EXTINF:1,x\nsub/d.ogg\n");
+        assert!(names.contains("a.mp3"));
+        assert!(names.contains("b&amp;c.wma"));
+        assert!(names.contains("d.ogg"));
+        assert!(is_previously_exported("B&C.wma", &names));
+    }
+}
@@ -0,0 +1,161 @@
+// Выгрузка только метаданных треков, без копирования файлов: по такому списку
+// инструменты переноса собирают плейлисты заново в стриминговых сервисах.
+// Сюда попадают и треки, файл которых не нашёлся - их-то восстанавливать и нужно
+
+use crate::{AudioEntryId, AudioMetadata, METADATA_KEYS};
+
+pub struct TrackRecord {
+    pub id:        AudioEntryId,
+    pub title:     String,
+    pub artist:    String,
+    // путь как он записан в базе Audials
+    pub db_path:   String,
+    // путь в библиотеке, если файл нашёлся
+    pub real_path: Option<String>,
+    pub metadata:  AudioMetadata,
+}
+
+pub struct TrackListPlaylist {
+    pub name:   String,
+    pub tracks: Vec<TrackRecord>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackListFormat {
+    Csv,
+    Json,
+}
+impl TrackListFormat {
+    pub const ALL: [TrackListFormat; 2] = [TrackListFormat::Csv, TrackListFormat::Json];
+    pub fn extension (self) -> &'static str {
+        match self {
+            TrackListFormat::Csv  => "csv",
+            TrackListFormat::Json => "json",
+        }
+    }
+    pub fn write (self, playlists: &[TrackListPlaylist]) -> String {
+        match self {
+            TrackListFormat::Csv  => write_csv(playlists),
+            TrackListFormat::Json => write_json(playlists),
+        }
+    }
+}
+impl std::fmt::Display for TrackListFormat {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TrackListFormat::Csv  => "CSV",
+            TrackListFormat::Json => "JSON",
+        })
+    }
+}
+
+fn metadata_value (metadata: &AudioMetadata, key: &str) -> Option<String> {
+    metadata.named_values().into_iter().find(|(value_key, _)| *value_key == key).map(|(_, value)| value)
+}
+
+fn escape_csv (field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_string()
+    }
+}
+
+// Одна строка на трек, имя плейлиста повторяется в первой колонке: так
+// несколько плейлистов помещаются в один файл, который понимают сервисы переноса
+fn write_csv (playlists: &[TrackListPlaylist]) -> String {
+    let mut header = vec!["playlist", "position", "artist", "title"];
+    header.extend(METADATA_KEYS.iter());
+    header.extend(["path", "resolved_path"].iter());
+    let mut result = header.join(",");
+    result.push('\n');
+    for playlist in playlists {
+        for (i, track) in playlist.tracks.iter().enumerate() {
+            let mut fields = vec![playlist.name.clone(), (1 + i).to_string(), track.artist.clone(), track.title.clone()];
+            fields.extend(METADATA_KEYS.iter().map(|key| metadata_value(&track.metadata, key).unwrap_or_default()));
+            fields.push(track.db_path.clone());
+            fields.push(track.real_path.clone().unwrap_or_default());
+            result.push_str(&fields.iter().map(|field| escape_csv(field)).collect::<Vec<String>>().join(","));
+            result.push('\n');
+        }
+    }
+    result
+}
+
+fn write_json (playlists: &[TrackListPlaylist]) -> String {
+    let root: Vec<serde_json::Value> = playlists.iter().map(|playlist| {
+        let tracks: Vec<serde_json::Value> = playlist.tracks.iter().enumerate().map(|(i, track)| {
+            let mut obj = serde_json::Map::new();
+            obj.insert("position".into(), (1 + i).into());
+            obj.insert("id".into(), track.id.into());
+            obj.insert("artist".into(), track.artist.clone().into());
+            obj.insert("title".into(), track.title.clone().into());
+            for (key, value) in track.metadata.named_values() {
+                obj.insert(key.into(), value.into());
+            }
+            obj.insert("path".into(), track.db_path.clone().into());
+            obj.insert("resolved_path".into(), track.real_path.clone().map_or(serde_json::Value::Null, serde_json::Value::String));
+            serde_json::Value::Object(obj)
+        }).collect();
+        serde_json::json!({
+            "name":   playlist.name,
+            "tracks": tracks,
+        })
+    }).collect();
+    serde_json::to_string_pretty(&serde_json::Value::Array(root)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlists () -> Vec<TrackListPlaylist> {
+        vec![TrackListPlaylist {
+            name:   "Rock, Pop".to_string(),
+            tracks: vec![
+                TrackRecord {
+                    id:        1,
+                    title:     "Hello, \"World\"".to_string(),
+                    artist:    "A".to_string(),
+                    db_path:   "C:\\Music\\a.mp3".to_string(),
+                    real_path: Some("/lib/a.mp3".to_string()),
+                    metadata:  AudioMetadata {album: Some("X".to_string()), ..AudioMetadata::default()},
+                },
+                TrackRecord {
+                    id:        2,
+                    title:     "Line1\nLine2".to_string(),
+                    artist:    String::new(),
+                    db_path:   "C:\\Music\\gone.mp3".to_string(),
+                    real_path: None,
+                    metadata:  AudioMetadata::default(),
+                },
+            ],
+        }]
+    }
+
+    #[test]
+    fn csv_quotes_commas_quotes_and_newlines () {
+        let csv = TrackListFormat::Csv.write(&playlists());
+        let mut rows = csv.splitn(2, '\n');
+        assert!(rows.next().unwrap().starts_with("playlist,position,artist,title,album,"));
+        assert_eq!(
+            rows.next().unwrap(),
+            concat!(
+                "\"Rock, Pop\",1,A,\"Hello, \"\"World\"\"\",X,,,,,,,,,,C:\\Music\\a.mp3,/lib/a.mp3\n",
+                "\"Rock, Pop\",2,,\"Line1\nLine2\",,,,,,,,,,,C:\\Music\\gone.mp3,\n",
+            )
+        );
+    }
+
+    #[test]
+    fn unresolved_track_is_listed_without_resolved_path () {
+        let json: serde_json::Value = serde_json::from_str(&TrackListFormat::Json.write(&playlists())).unwrap();
+        let tracks = json[0]["tracks"].as_array().unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0]["resolved_path"], "/lib/a.mp3");
+        assert_eq!(tracks[1]["path"], "C:\\Music\\gone.mp3");
+        assert!(tracks[1]["resolved_path"].is_null());
+        assert_eq!(tracks[1]["position"], 2);
+    }
+}
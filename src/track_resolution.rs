@@ -0,0 +1,170 @@
+// Как нашёлся (или не нашёлся) файл каждого вхождения плейлиста: что лежит в
+// ft_path, какие пути в библиотеке проверялись и какой выбран. Таблица треков
+// показывает по строке на вхождение, в том числе ненайденные, а по щелчку -
+// подробности
+
+use crate::{AudioEntryId, AudioMetadata};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResolutionStatus {
+    // файл нашёлся по первому же кандидату из пути в базе
+    Remapped,
+    // файл нашёлся, но не по первому кандидату: выше по пути или по другой записи
+    Fallback,
+    // записей в базе несколько, взята первая, у которой нашёлся файл
+    DuplicateRows,
+    MissingFile,
+    MissingRow,
+}
+impl ResolutionStatus {
+    // в шрифте нет значков вроде галочки, поэтому метки из ASCII
+    pub fn icon (self) -> &'static str {
+        match self {
+            ResolutionStatus::Remapped      => "[+]",
+            ResolutionStatus::Fallback      => "[~]",
+            ResolutionStatus::DuplicateRows => "[=]",
+            ResolutionStatus::MissingFile   => "[!]",
+            ResolutionStatus::MissingRow    => "[?]",
+        }
+    }
+    pub fn label (self) -> &'static str {
+        match self {
+            ResolutionStatus::Remapped      => "найден по пути из базы",
+            ResolutionStatus::Fallback      => "найден запасным путём",
+            ResolutionStatus::DuplicateRows => "несколько записей в базе",
+            ResolutionStatus::MissingFile   => "нет файла",
+            ResolutionStatus::MissingRow    => "нет записи в базе",
+        }
+    }
+    pub fn color (self) -> [f32; 3] {
+        match self {
+            ResolutionStatus::Remapped      => [0.0, 0.5, 0.0],
+            ResolutionStatus::Fallback | ResolutionStatus::DuplicateRows => [0.7, 0.45, 0.0],
+            ResolutionStatus::MissingFile | ResolutionStatus::MissingRow => [1.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub path:  String,
+    pub found: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackResolution {
+    pub audio_id:   AudioEntryId,
+    pub status:     ResolutionStatus,
+    // из первой читаемой записи базы, у ненайденных в базе пусто (и метаданные тоже)
+    pub title:      String,
+    pub artist:     String,
+    pub metadata:   AudioMetadata,
+    // ft_path каждой записи базы с этим id; None - запись не прочиталась
+    pub db_paths:   Vec<Option<String>>,
+    pub candidates: Vec<Candidate>,
+    pub chosen:     Option<String>,
+}
+impl TrackResolution {
+    pub fn new (audio_id: AudioEntryId) -> TrackResolution {
+        TrackResolution {
+            audio_id,
+            status:     ResolutionStatus::MissingRow,
+            title:      String::new(),
+            artist:     String::new(),
+            metadata:   AudioMetadata::default(),
+            db_paths:   Vec::new(),
+            candidates: Vec::new(),
+            chosen:     None,
+        }
+    }
+    // Статус по уже собранным записям, кандидатам и выбранному пути
+    pub fn set_status (&mut self) {
+        self.status = match &self.chosen {
+            _ if self.db_paths.is_empty() => ResolutionStatus::MissingRow,
+            None => ResolutionStatus::MissingFile,
+            Some(_) if 1 < self.db_paths.len() => ResolutionStatus::DuplicateRows,
+            Some(chosen) if self.candidates.first().map(|candidate| &candidate.path) == Some(chosen) => ResolutionStatus::Remapped,
+            Some(_) => ResolutionStatus::Fallback,
+        };
+    }
+    // Путь для таблицы: выбранный, а если файла нет - как в базе
+    pub fn shown_path (&self) -> &str {
+        self.chosen.as_deref()
+            .or_else(|| self.db_paths.iter().flatten().next().map(|db_path| db_path.as_str()))
+            .unwrap_or("")
+    }
+    pub fn details (&self) -> Vec<String> {
+        let mut details = vec![format!("audio_id={}: {}", self.audio_id, self.status.label())];
+        if self.db_paths.is_empty() {
+            details.push("в file_t нет строки с таким ft_id".into());
+        }
+        for (i, db_path) in self.db_paths.iter().enumerate() {
+            details.push(match db_path {
+                Some(db_path) => format!("ft_path ({}-я запись): {}", 1 + i, db_path),
+                None => format!("{}-я запись не прочиталась из базы", 1 + i),
+            });
+        }
+        if self.candidates.is_empty() && !self.db_paths.is_empty() {
+            details.push("в пути из базы нет папки библиотеки, проверять в библиотеке нечего".into());
+        }
+        for candidate in &self.candidates {
+            details.push(format!("{} {}", if candidate.found {"есть:"} else {"нет: "}, candidate.path));
+        }
+        details.push(match &self.chosen {
+            Some(chosen) => format!("выбран: {}", chosen),
+            None => "файл не выбран, трек не экспортируется".into(),
+        });
+        details
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // записи базы с путями db_paths, кандидаты с отметкой, нашёлся ли файл, и
+    // выбранный путь - первый найденный кандидат
+    fn resolved (db_paths: &[&str], candidates: &[(&str, bool)]) -> TrackResolution {
+        let mut resolution = TrackResolution::new(1);
+        resolution.db_paths = db_paths.iter().map(|db_path| Some(db_path.to_string())).collect();
+        resolution.candidates = candidates.iter().map(|(path, found)| Candidate {path: path.to_string(), found: *found}).collect();
+        resolution.chosen = resolution.candidates.iter().find(|candidate| candidate.found).map(|candidate| candidate.path.clone());
+        resolution.set_status();
+        resolution
+    }
+
+    #[test]
+    fn first_candidate_found_is_remapped () {
+        let resolution = resolved(&["C:\\Music\\a.mp3"], &[("/music/a.mp3", true), ("/music/other/a.mp3", true)]);
+        assert_eq!(ResolutionStatus::Remapped, resolution.status);
+        assert_eq!("/music/a.mp3", resolution.shown_path());
+    }
+
+    #[test]
+    fn later_candidate_found_is_fallback () {
+        let resolution = resolved(&["C:\\Music\\a.mp3"], &[("/music/a.mp3", false), ("/a.mp3", true)]);
+        assert_eq!(ResolutionStatus::Fallback, resolution.status);
+    }
+
+    #[test]
+    fn several_rows_win_over_remapped () {
+        let resolution = resolved(&["C:\\Music\\a.mp3", "D:\\a.mp3"], &[("/music/a.mp3", true)]);
+        assert_eq!(ResolutionStatus::DuplicateRows, resolution.status);
+    }
+
+    #[test]
+    fn missing_file_and_missing_row () {
+        let resolution = resolved(&["C:\\Music\\a.mp3"], &[("/music/a.mp3", false)]);
+        assert_eq!(ResolutionStatus::MissingFile, resolution.status);
+        assert_eq!("C:\\Music\\a.mp3", resolution.shown_path());
+        // строки в file_t нет - статус не зависит от кандидатов
+        let resolution = resolved(&[], &[]);
+        assert_eq!(ResolutionStatus::MissingRow, resolution.status);
+        assert_eq!("", resolution.shown_path());
+        // запись есть, но не прочиталась: это не MissingRow
+        let mut resolution = TrackResolution::new(1);
+        resolution.db_paths.push(None);
+        resolution.set_status();
+        assert_eq!(ResolutionStatus::MissingFile, resolution.status);
+    }
+}
@@ -0,0 +1,70 @@
+// Треки, которые не нужно переносить на конкретное устройство: в таблице
+// предпросмотра их снимают по одному или сразу по исполнителю или формату.
+// Исключения хранятся в настройках для каждого плейлиста (по id) найденными
+// путями файлов, поэтому повторный экспорт, в том числе из командной строки,
+// их помнит
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::AudioEntry;
+use crate::library_formats::ResolvedPlaylist;
+use crate::settings::Settings;
+use crate::track_list::TrackRecord;
+
+// Формат трека - расширение файла в нижнем регистре: "mp3", "flac"
+pub fn track_format (audio: &AudioEntry) -> String {
+    Path::new(&audio.path).extension().map_or(String::new(), |extension| extension.to_string_lossy().to_lowercase())
+}
+
+// Варианты для массового исключения, по алфавиту и без повторов
+pub fn artists (audio_in_playlist: &[AudioEntry]) -> Vec<String> {
+    audio_in_playlist.iter().map(|audio| audio.artist.clone()).filter(|artist| !artist.is_empty()).collect::<BTreeSet<String>>().into_iter().collect()
+}
+pub fn formats (audio_in_playlist: &[AudioEntry]) -> Vec<String> {
+    audio_in_playlist.iter().map(track_format).filter(|format| !format.is_empty()).collect::<BTreeSet<String>>().into_iter().collect()
+}
+
+// Исключить или вернуть все треки, подходящие под условие
+pub fn set_excluded_where<F: Fn(&AudioEntry) -> bool> (excluded: &mut BTreeSet<String>, audio_in_playlist: &[AudioEntry], is_affected: F, exclude: bool) {
+    for audio in audio_in_playlist.iter().filter(|audio| is_affected(audio)) {
+        if exclude {
+            excluded.insert(audio.path.clone());
+        }
+        else {
+            excluded.remove(&audio.path);
+        }
+    }
+}
+
+pub fn included_audio (audio_in_playlist: &[AudioEntry], excluded: &BTreeSet<String>) -> Vec<AudioEntry> {
+    audio_in_playlist.iter().filter(|audio| !excluded.contains(&audio.path)).cloned().collect()
+}
+
+// Убирает из найденных треков исключённые в настройках для этого плейлиста
+pub fn remove_saved_exclusions (playlist_name: &str, playlist_id: &str, audio: &mut Vec<AudioEntry>, settings: &Settings, log: &mut Vec<String>) {
+    retain_not_excluded(playlist_name, playlist_id, audio, |audio| Some(&audio.path), settings, log);
+}
+
+// То же для списка треков; трек без найденного файла исключить нельзя
+pub fn remove_saved_exclusions_from_records (playlist_name: &str, playlist_id: &str, tracks: &mut Vec<TrackRecord>, settings: &Settings, log: &mut Vec<String>) {
+    retain_not_excluded(playlist_name, playlist_id, tracks, |track| track.real_path.as_ref(), settings, log);
+}
+
+fn retain_not_excluded<T> (playlist_name: &str, playlist_id: &str, items: &mut Vec<T>, path_of: impl Fn(&T) -> Option<&String>, settings: &Settings, log: &mut Vec<String>) {
+    let excluded = match settings.excluded_tracks.get(playlist_id) {
+        Some(excluded) => excluded,
+        None => return,
+    };
+    let count_before = items.len();
+    items.retain(|item| !path_of(item).map_or(false, |path| excluded.contains(path)));
+    if count_before != items.len() {
+        log.push(format!("# Замечание: в плейлисте '{}' исключено треков: {}", playlist_name, count_before - items.len()));
+    }
+}
+
+pub fn remove_saved_exclusions_from_all (resolved_playlists: &mut [ResolvedPlaylist], settings: &Settings, log: &mut Vec<String>) {
+    for playlist in resolved_playlists {
+        remove_saved_exclusions(&playlist.name, &playlist.id, &mut playlist.audio, settings, log);
+    }
+}
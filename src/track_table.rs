@@ -0,0 +1,241 @@
+// Таблица треков в предпросмотре плейлиста: по строке на каждое вхождение
+// плейлиста, в том числе ненайденное, - номер, название, исполнитель, путь,
+// размер, как нашёлся файл и что с ним будет при экспорте. Сортируется по любой
+// колонке и фильтруется по мере ввода; сама таблица рисуется в SelectionMenu::view
+
+use std::collections::BTreeSet;
+
+use crate::AudioEntry;
+use crate::space_estimate::{SpaceEstimate, TrackSpace};
+use crate::track_resolution::TrackResolution;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackColumn {
+    Index,
+    Title,
+    Artist,
+    Path,
+    Size,
+    Resolution,
+    Status,
+}
+impl TrackColumn {
+    pub const ALL: [TrackColumn; 7] = [
+        TrackColumn::Index, TrackColumn::Title, TrackColumn::Artist, TrackColumn::Path, TrackColumn::Size, TrackColumn::Resolution, TrackColumn::Status,
+    ];
+    pub fn title (self) -> &'static str {
+        match self {
+            TrackColumn::Index      => "№",
+            TrackColumn::Title      => "Название",
+            TrackColumn::Artist     => "Исполнитель",
+            TrackColumn::Path       => "Путь",
+            TrackColumn::Size       => "Размер",
+            TrackColumn::Resolution => "Поиск файла",
+            TrackColumn::Status     => "Состояние",
+        }
+    }
+    // доля ширины таблицы
+    pub fn width_portion (self) -> u16 {
+        match self {
+            TrackColumn::Index      => 2,
+            TrackColumn::Title      => 4,
+            TrackColumn::Artist     => 3,
+            TrackColumn::Path       => 7,
+            TrackColumn::Size       => 2,
+            TrackColumn::Resolution => 4,
+            TrackColumn::Status     => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackSort {
+    pub column:     TrackColumn,
+    pub descending: bool,
+}
+impl TrackSort {
+    pub fn new () -> TrackSort {
+        TrackSort {
+            column:     TrackColumn::Index,
+            descending: false,
+        }
+    }
+    // Повторный щелчок по той же колонке меняет направление
+    pub fn toggled (self, column: TrackColumn) -> TrackSort {
+        TrackSort {
+            column,
+            descending: column == self.column && !self.descending,
+        }
+    }
+    pub fn header (self, column: TrackColumn) -> String {
+        match (column == self.column, self.descending) {
+            (true, false) => format!("{} ^", column.title()),
+            (true, true) => format!("{} v", column.title()),
+            (false, _) => column.title().to_string(),
+        }
+    }
+}
+
+// Строка таблицы. Оценка места считается только по включённым найденным
+// трекам, поэтому у остальных её нет
+pub struct TrackRow<'a> {
+    pub resolution:  &'a TrackResolution,
+    // индекс в audio_in_playlist; None - файл не найден
+    pub audio_index: Option<usize>,
+    pub audio:       Option<&'a AudioEntry>,
+    pub included:    bool,
+    pub space:       Option<&'a TrackSpace>,
+}
+
+// Найденные вхождения идут в audio_in_playlist в том же порядке, что и в
+// разборах, поэтому индексы сопоставляются подсчётом
+pub fn track_rows<'a> (resolutions: &'a [TrackResolution], audio_in_playlist: &'a [AudioEntry], space_estimate: Option<&'a SpaceEstimate>, excluded: &BTreeSet<String>) -> Vec<TrackRow<'a>> {
+    let mut audio_index = 0;
+    let mut included_index = 0;
+    resolutions.iter().map(|resolution| {
+        if resolution.chosen.is_none() {
+            return TrackRow {resolution, audio_index: None, audio: None, included: false, space: None};
+        }
+        let audio = &audio_in_playlist[audio_index];
+        let mut row = TrackRow {resolution, audio_index: Some(audio_index), audio: Some(audio), included: false, space: None};
+        audio_index += 1;
+        if !excluded.contains(&audio.path) {
+            row.included = true;
+            row.space = space_estimate.and_then(|space_estimate| space_estimate.tracks.get(included_index));
+            included_index += 1;
+        }
+        row
+    }).collect()
+}
+
+pub fn track_status (row: &TrackRow) -> &'static str {
+    match row {
+        TrackRow {audio: None, ..} => "не экспортируется",
+        TrackRow {included: false, ..} => "исключён",
+        TrackRow {space: Some(TrackSpace {size: None, ..}), ..} => "файл не читается",
+        TrackRow {space: Some(TrackSpace {present: true, ..}), ..} => "уже в папке",
+        TrackRow {space: Some(TrackSpace {present: false, ..}), ..} => "будет перенесён",
+        TrackRow {space: None, ..} => "найден",
+    }
+}
+
+fn matches_filter (row: &TrackRow, words: &[String]) -> bool {
+    let resolution = row.resolution;
+    let mut haystack = format!("{}\n{}\n{}\n{}", resolution.title, resolution.artist, resolution.shown_path(), resolution.status.label());
+    if let Some(audio) = row.audio {
+        for (_, value) in audio.metadata.named_values() {
+            haystack.push('\n');
+            haystack.push_str(&value);
+        }
+    }
+    let haystack = haystack.to_lowercase();
+    words.iter().all(|word| haystack.contains(word.as_str()))
+}
+
+// Индексы строк в порядке показа: подходят под все слова фильтра (без учёта
+// регистра, по названию, исполнителю, пути, поиску файла и метаданным) и
+// отсортированы. Сортировка устойчивая - при равных значениях остаётся
+// порядок плейлиста
+pub fn visible_rows (rows: &[TrackRow], filter: &str, sort: TrackSort) -> Vec<usize> {
+    let words: Vec<String> = filter.to_lowercase().split_whitespace().map(|word| word.to_string()).collect();
+    let mut visible: Vec<usize> = (0..rows.len())
+        .filter(|i| matches_filter(&rows[*i], &words))
+        .collect();
+    visible.sort_by(|a, b| {
+        let (row_a, row_b) = (&rows[*a], &rows[*b]);
+        let (resolution_a, resolution_b) = (row_a.resolution, row_b.resolution);
+        let ordering = match sort.column {
+            TrackColumn::Index      => a.cmp(b),
+            TrackColumn::Title      => resolution_a.title.to_lowercase().cmp(&resolution_b.title.to_lowercase()),
+            TrackColumn::Artist     => resolution_a.artist.to_lowercase().cmp(&resolution_b.artist.to_lowercase()),
+            TrackColumn::Path       => resolution_a.shown_path().to_lowercase().cmp(&resolution_b.shown_path().to_lowercase()),
+            TrackColumn::Size       => row_a.space.and_then(|space| space.size).cmp(&row_b.space.and_then(|space| space.size)),
+            TrackColumn::Resolution => resolution_a.status.cmp(&resolution_b.status),
+            TrackColumn::Status     => track_status(row_a).cmp(track_status(row_b)),
+        };
+        if sort.descending {ordering.reverse()} else {ordering}
+    });
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track_resolution::{Candidate, ResolutionStatus};
+
+    fn resolution (title: &str, artist: &str, path: Option<&str>) -> TrackResolution {
+        let mut resolution = TrackResolution::new(0);
+        resolution.title = title.to_string();
+        resolution.artist = artist.to_string();
+        if let Some(path) = path {
+            resolution.db_paths.push(Some(path.to_string()));
+            resolution.candidates.push(Candidate {path: path.to_string(), found: true});
+            resolution.chosen = Some(path.to_string());
+        }
+        resolution.set_status();
+        resolution
+    }
+
+    fn audio (resolution: &TrackResolution) -> AudioEntry {
+        AudioEntry {
+            id:       resolution.audio_id,
+            title:    resolution.title.clone(),
+            artist:   resolution.artist.clone(),
+            path:     resolution.chosen.clone().unwrap(),
+            metadata: Default::default(),
+        }
+    }
+
+    fn fixture () -> (Vec<TrackResolution>, Vec<AudioEntry>) {
+        let resolutions = vec![
+            resolution("Кукушка", "Кино", Some("/music/Кино/Кукушка.mp3")),
+            resolution("Numb", "Linkin Park", None),
+            resolution("алюминиевые огурцы", "Кино", Some("/music/Кино/Огурцы.flac")),
+        ];
+        let audio_in_playlist = resolutions.iter().filter(|resolution| resolution.chosen.is_some()).map(audio).collect();
+        (resolutions, audio_in_playlist)
+    }
+
+    #[test]
+    fn rows_match_found_audio_and_exclusions () {
+        let (resolutions, audio_in_playlist) = fixture();
+        let excluded: BTreeSet<String> = vec!["/music/Кино/Огурцы.flac".to_string()].into_iter().collect();
+        let rows = track_rows(&resolutions, &audio_in_playlist, None, &excluded);
+        assert_eq!(vec![Some(0), None, Some(1)], rows.iter().map(|row| row.audio_index).collect::<Vec<_>>());
+        assert_eq!(ResolutionStatus::MissingRow, rows[1].resolution.status);
+        assert_eq!(vec!["найден", "не экспортируется", "исключён"], rows.iter().map(track_status).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sort_toggles_direction_on_same_column () {
+        let sort = TrackSort::new();
+        let by_title = sort.toggled(TrackColumn::Title);
+        assert_eq!(TrackSort {column: TrackColumn::Title, descending: false}, by_title);
+        assert_eq!(TrackSort {column: TrackColumn::Title, descending: true}, by_title.toggled(TrackColumn::Title));
+        assert_eq!(TrackSort {column: TrackColumn::Title, descending: false}, by_title.toggled(TrackColumn::Title).toggled(TrackColumn::Title));
+        // другая колонка всегда начинается по возрастанию
+        assert_eq!(TrackSort {column: TrackColumn::Artist, descending: false}, by_title.toggled(TrackColumn::Title).toggled(TrackColumn::Artist));
+        assert_eq!("Название v", by_title.toggled(TrackColumn::Title).header(TrackColumn::Title));
+        assert_eq!("Исполнитель", by_title.header(TrackColumn::Artist));
+    }
+
+    #[test]
+    fn sort_ignores_case_and_keeps_playlist_order_for_equal_values () {
+        let (resolutions, audio_in_playlist) = fixture();
+        let rows = track_rows(&resolutions, &audio_in_playlist, None, &BTreeSet::new());
+        assert_eq!(vec![0, 1, 2], visible_rows(&rows, "", TrackSort::new()));
+        assert_eq!(vec![1, 2, 0], visible_rows(&rows, "", TrackSort {column: TrackColumn::Title, descending: false}));
+        assert_eq!(vec![0, 2, 1], visible_rows(&rows, "", TrackSort {column: TrackColumn::Artist, descending: true}));
+        assert_eq!(vec![0, 2, 1], visible_rows(&rows, "", TrackSort {column: TrackColumn::Resolution, descending: false}));
+    }
+
+    #[test]
+    fn filter_needs_every_word_in_any_column () {
+        let (resolutions, audio_in_playlist) = fixture();
+        let rows = track_rows(&resolutions, &audio_in_playlist, None, &BTreeSet::new());
+        assert_eq!(vec![0, 2], visible_rows(&rows, "кИНО", TrackSort::new()));
+        assert_eq!(vec![2], visible_rows(&rows, "кино  flac", TrackSort::new()));
+        assert_eq!(vec![1], visible_rows(&rows, "нет записи", TrackSort::new()));
+        assert!(visible_rows(&rows, "кино numb", TrackSort::new()).is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+// Экспорт большого плейлиста по томам для записи на CD/DVD или нескольких
+// маленьких флешек: треки по порядку раскладываются в папки "Vol 01",
+// "Vol 02", ... не больше заданного размера, в каждой свой плейлист. Тома
+// идут подряд, так что порядок плейлиста сохраняется и между ними
+
+use std::path::Path;
+
+use crate::{AudioEntry, MyResult};
+use crate::export::{ExportOptions, ExportReport, export_playlist, format_size};
+
+pub const MB: u64 = 1024 * 1024;
+// запас в каждом томе на плейлист, журнал и SHA256SUMS
+const RESERVE_BYTES: u64 = 256 << 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumePreset {
+    Cd,
+    Dvd,
+    Custom,
+}
+impl VolumePreset {
+    pub const ALL: [VolumePreset; 3] = [VolumePreset::Cd, VolumePreset::Dvd, VolumePreset::Custom];
+    pub fn key (self) -> &'static str {
+        match self {
+            VolumePreset::Cd     => "cd",
+            VolumePreset::Dvd    => "dvd",
+            VolumePreset::Custom => "custom",
+        }
+    }
+    pub fn from_key (key: &str) -> Option<VolumePreset> {
+        VolumePreset::ALL.iter().copied().find(|preset| preset.key() == key)
+    }
+    // размер тома; для Custom - None, его задаёт пользователь
+    pub fn max_bytes (self) -> Option<u64> {
+        match self {
+            VolumePreset::Cd     => Some(700 * MB),
+            VolumePreset::Dvd    => Some(4_700_000_000),
+            VolumePreset::Custom => None,
+        }
+    }
+}
+impl std::fmt::Display for VolumePreset {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VolumePreset::Cd     => "CD-MP3 700 МБ",
+            VolumePreset::Dvd    => "DVD 4.7 ГБ",
+            VolumePreset::Custom => "Свой размер, МБ",
+        })
+    }
+}
+
+// "cd", "dvd" или размер в мегабайтах
+pub fn parse_volume_size (value: &str) -> MyResult<u64> {
+    if let Some(max_bytes) = VolumePreset::from_key(value).and_then(|preset| preset.max_bytes()) {
+        return Ok(max_bytes);
+    }
+    match value.trim().parse::<u64>() {
+        Ok(megabytes) if 0 < megabytes => Ok(megabytes * MB),
+        _ => Err(format!("размер тома '{}' - нужно cd, dvd или число мегабайт", value)),
+    }
+}
+
+// "Vol 01", при сотне томов и больше - "Vol 001"
+pub fn volume_name (number: usize, count: usize) -> String {
+    format!("Vol {:0width$}", number, width = std::cmp::max(2, count.to_string().len()))
+}
+
+// Границы томов: подряд идущие куски списка, каждый не больше max_bytes.
+// Трек больше тома не помещается никуда и попадает в too_large
+pub fn split_into_volumes (audio_in_playlist: &[AudioEntry], max_bytes: u64) -> (Vec<Vec<&AudioEntry>>, Vec<&AudioEntry>) {
+    let capacity = max_bytes.saturating_sub(RESERVE_BYTES);
+    let mut volumes: Vec<Vec<&AudioEntry>> = Vec::new();
+    let mut too_large = Vec::new();
+    let mut current = Vec::new();
+    let mut current_size = 0;
+    for audio in audio_in_playlist {
+        let size = std::fs::metadata(&audio.path).map_or(0, |metadata| metadata.len());
+        if capacity < size {
+            too_large.push(audio);
+            continue;
+        }
+        if capacity < current_size + size {
+            volumes.push(std::mem::take(&mut current));
+            current_size = 0;
+        }
+        current.push(audio);
+        current_size += size;
+    }
+    if !current.is_empty() {
+        volumes.push(current);
+    }
+    (volumes, too_large)
+}
+
+pub fn export_playlist_volumes (playlist_name: &str, audio_in_playlist: &[AudioEntry], output_path: &Path, max_bytes: u64, options: &ExportOptions) -> ExportReport {
+    let mut report = ExportReport {
+        log:           Vec::new(),
+        exported:      0,
+        failed:        0,
+        playlist_path: None,
+    };
+    let (volumes, too_large) = split_into_volumes(audio_in_playlist, max_bytes);
+    for audio in &too_large {
+        report.log.push(format!("# Ошибка: '{}' больше тома ({}), пропущен", audio.path, format_size(max_bytes)));
+        report.failed += 1;
+    }
+    report.log.push(format!("Плейлист '{}' разбит на тома по {}: {}", playlist_name, format_size(max_bytes), volumes.len()));
+    for (i, volume) in volumes.iter().enumerate() {
+        let volume_name = volume_name(1 + i, volumes.len());
+        let volume_path = output_path.join(&volume_name);
+        if let Err(err) = std::fs::create_dir_all(&volume_path) {
+            report.log.push(format!("# Ошибка: не удалось создать папку '{}': {}", volume_path.to_string_lossy(), err));
+            report.failed += volume.len();
+            continue;
+        }
+        let volume_audio: Vec<AudioEntry> = volume.iter().map(|audio| (*audio).clone()).collect();
+        report.log.push(format!("{}: треков {}", volume_name, volume_audio.len()));
+        let volume_report = export_playlist(&format!("{} - {}", playlist_name, volume_name), &volume_audio, &volume_path, options);
+        report.log.extend(volume_report.log);
+        report.exported += volume_report.exported;
+        report.failed += volume_report.failed;
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Файлы нужного размера во временной папке, по треку на размер
+    fn audio_of_sizes (name: &str, sizes: &[u64]) -> (std::path::PathBuf, Vec<AudioEntry>) {
+        let dir = std::env::temp_dir().join(format!("audials-volumes-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let audio = sizes.iter().enumerate().map(|(i, size)| {
+            let path = dir.join(format!("{}.mp3", i));
+            std::fs::File::create(&path).unwrap().set_len(*size).unwrap();
+            AudioEntry {
+                id:       i as u32,
+                title:    String::new(),
+                artist:   String::new(),
+                path:     path.to_string_lossy().into_owned(),
+                metadata: Default::default(),
+            }
+        }).collect();
+        (dir, audio)
+    }
+
+    fn ids (volume: &[&AudioEntry]) -> Vec<u32> {
+        volume.iter().map(|audio| audio.id).collect()
+    }
+
+    #[test]
+    fn track_larger_than_volume_is_set_aside () {
+        let max_bytes = MB + RESERVE_BYTES;
+        let (dir, audio) = audio_of_sizes("large", &[MB / 2, 2 * MB, MB / 2, MB + 1]);
+        let (volumes, too_large) = split_into_volumes(&audio, max_bytes);
+        assert_eq!(vec![vec![0, 2]], volumes.iter().map(|volume| ids(volume)).collect::<Vec<_>>());
+        assert_eq!(vec![1, 3], ids(&too_large));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn volumes_keep_playlist_order () {
+        let max_bytes = MB + RESERVE_BYTES;
+        let (dir, audio) = audio_of_sizes("order", &[MB / 2, MB / 2, MB / 4, MB, 0]);
+        let (volumes, too_large) = split_into_volumes(&audio, max_bytes);
+        assert_eq!(vec![vec![0, 1], vec![2], vec![3, 4]], volumes.iter().map(|volume| ids(volume)).collect::<Vec<_>>());
+        assert!(too_large.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn volume_names_widen_with_count () {
+        assert_eq!("Vol 01", volume_name(1, 9));
+        assert_eq!("Vol 007", volume_name(7, 120));
+        assert_eq!(Ok(700 * MB), parse_volume_size("cd"));
+        assert!(parse_volume_size("0").is_err());
+    }
+}